@@ -0,0 +1,515 @@
+//! `sfhir-codegen.toml`, which tunes what is generated and how without changes to the codegen.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use toml_edit::{Item, Table};
+
+use crate::diagnostics::Diagnostics;
+use crate::schema::{Schema, StructureDefinition};
+use crate::tokens::ident;
+
+/// The config read from the working directory when none is given.
+pub const CONFIG_FILENAME: &str = "sfhir-codegen.toml";
+
+/// Modules of a release's module that the generated modules mustn't take the names of.
+const RESERVED_MODULE_NAMES: &[&str] = &["codes", "traits", "resource", "typed_reference"];
+
+#[derive(Default)]
+pub struct Config {
+    /// The crate the generated modules and features are written to.
+    pub crate_path: Option<PathBuf>,
+    /// Resources to generate, by name or canonical URL, or by the URL of a profile on them; every
+    /// resource when empty.
+    pub include: Vec<String>,
+    /// Resources, profiles and extensions to leave out, by name or canonical URL.
+    pub exclude: Vec<String>,
+    /// Type names by canonical URL of the definition they're generated for.
+    pub type_renames: HashMap<String, String>,
+    /// Field names by element id, e.g. `Patient.deceased[x]`.
+    pub field_renames: HashMap<String, String>,
+    pub modules: ModuleLayout,
+    /// Derives and attributes for every struct and enum generated for a resource or data type.
+    pub all_types: TypeOptions,
+    /// Derives and attributes for the types with these names, on top of `all_types`.
+    pub types: HashMap<String, TypeOptions>,
+    pub output: OutputOptions,
+}
+
+/// Names of the modules the generated types are grouped in, within each release's module.
+#[derive(Debug)]
+pub struct ModuleLayout {
+    /// The module holding the resources, re-exported by the release's module, or `None` to put
+    /// them in the release's module itself.
+    pub resources: Option<String>,
+    pub datatypes: String,
+    pub extensions: String,
+    pub profiles: String,
+}
+
+impl Default for ModuleLayout {
+    fn default() -> Self {
+        ModuleLayout {
+            resources: None,
+            datatypes: "datatypes".to_string(),
+            extensions: "extensions".to_string(),
+            profiles: "profiles".to_string(),
+        }
+    }
+}
+
+/// How the generated code fits the crate it's generated into.
+pub struct OutputOptions {
+    /// Whether each resource is behind a cargo feature of its own, and each release behind one
+    /// named after its module.
+    pub features: bool,
+    /// Path to the `fhir` crate's runtime, `crate` when generating into the `fhir` crate itself.
+    pub runtime: syn::Path,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            features: true,
+            runtime: syn::parse_quote!(crate),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct TypeOptions {
+    pub derives: Vec<syn::Path>,
+    pub attributes: Vec<syn::Attribute>,
+}
+
+impl Config {
+    /// Reads the config at `path`; paths in it are relative to the directory it's in.
+    pub fn from_path(path: &Path) -> Result<Config> {
+        let contents = std::fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Config::parse(&contents, dir).with_context(|| format!("read {}", path.display()))
+    }
+
+    /// Reads the config given on the command line, else `sfhir-codegen.toml` if there is one.
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        match path {
+            Some(path) => Config::from_path(path),
+            None if Path::new(CONFIG_FILENAME).exists() => Config::from_path(Path::new(CONFIG_FILENAME)),
+            None => Ok(Config::default()),
+        }
+    }
+
+    fn parse(contents: &str, dir: &Path) -> Result<Config> {
+        let doc: toml_edit::DocumentMut = contents.parse()?;
+        let root = doc.as_table();
+        check_keys(root, "", &["crate", "select", "rename", "modules", "types", "output"])?;
+        let mut config = Config {
+            crate_path: get_str(root, "crate")?.map(|p| dir.join(p)),
+            ..Config::default()
+        };
+
+        if let Some(select) = get_table(root, "select")? {
+            check_keys(select, "select", &["include", "exclude"])?;
+            config.include = get_strs(select, "include")?;
+            config.exclude = get_strs(select, "exclude")?;
+        }
+
+        if let Some(rename) = get_table(root, "rename")? {
+            check_keys(rename, "rename", &["types", "fields"])?;
+            if let Some(types) = get_table(rename, "types")? {
+                for (url, _) in types.iter() {
+                    let name = get_str(types, url)?.unwrap_or_default();
+                    ident(name).with_context(|| format!("rename.types.\"{}\"", url))?;
+                    config.type_renames.insert(url.to_string(), name.to_string());
+                }
+            }
+            if let Some(fields) = get_table(rename, "fields")? {
+                for (id, _) in fields.iter() {
+                    let name = get_str(fields, id)?.unwrap_or_default();
+                    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    {
+                        bail!("rename.fields.\"{}\": `{}` isn't a field name", id, name);
+                    }
+                    config.field_renames.insert(id.to_string(), name.to_string());
+                }
+            }
+        }
+
+        if let Some(modules) = get_table(root, "modules")? {
+            check_keys(
+                modules,
+                "modules",
+                &["resources", "datatypes", "extensions", "profiles"],
+            )?;
+            let layout = &mut config.modules;
+            layout.resources = get_str(modules, "resources")?.map(str::to_string);
+            for (key, name) in [
+                ("datatypes", &mut layout.datatypes),
+                ("extensions", &mut layout.extensions),
+                ("profiles", &mut layout.profiles),
+            ] {
+                if let Some(value) = get_str(modules, key)? {
+                    *name = value.to_string();
+                }
+            }
+            let names = [&layout.datatypes, &layout.extensions, &layout.profiles]
+                .into_iter()
+                .chain(&layout.resources);
+            let mut seen = HashSet::new();
+            for name in names {
+                ident(name).with_context(|| format!("modules: `{}`", name))?;
+                if RESERVED_MODULE_NAMES.contains(&name.as_str()) || !seen.insert(name) {
+                    bail!("modules: `{}` is taken", name);
+                }
+            }
+        }
+
+        if let Some(types) = get_table(root, "types")? {
+            config.all_types = get_type_options(types, "types")?;
+            for (name, item) in types.iter().filter(|(_, item)| item.is_table()) {
+                let key = format!("types.{}", name);
+                let table = item.as_table().ok_or_else(|| anyhow!("{} isn't a table", key))?;
+                config.types.insert(name.to_string(), get_type_options(table, &key)?);
+            }
+        }
+
+        if let Some(output) = get_table(root, "output")? {
+            check_keys(output, "output", &["features", "runtime"])?;
+            if let Some(item) = output.get("features") {
+                config.output.features = item.as_bool().ok_or_else(|| anyhow!("features isn't a boolean"))?;
+            }
+            if let Some(runtime) = get_str(output, "runtime")? {
+                config.output.runtime =
+                    syn::parse_str(runtime).with_context(|| format!("output.runtime: `{}`", runtime))?;
+            }
+        }
+        Ok(config)
+    }
+
+    /// Removes the definitions that aren't selected from `resources`: the resources not included,
+    /// or excluded, unless a selected resource needs them, and the profiles and extensions excluded
+    /// or on resources that aren't generated. Lists the entries that match nothing in `diagnostics`.
+    pub fn select(&self, resources: &mut Schema, diagnostics: &mut Diagnostics) {
+        let listed = |list: &[String], def: &StructureDefinition| {
+            list.iter()
+                .any(|entry| *entry == def.url || (def.is_resource() && *entry == def.r#type))
+        };
+        for entry in self.include.iter().chain(&self.exclude) {
+            let matched = resources
+                .structures_definitions
+                .iter()
+                .any(|d| d.url == *entry || (d.is_resource() && d.r#type == *entry));
+            if !matched {
+                let message = format!("`{}` matches no definition", entry);
+                diagnostics.warning(CONFIG_FILENAME, Some("select"), message);
+            }
+        }
+
+        let profiled: HashSet<&str> = resources
+            .structures_definitions
+            .iter()
+            .filter(|d| d.is_resource_profile() && self.include.contains(&d.url))
+            .map(|d| d.r#type.as_str())
+            .collect();
+        let mut selected: HashSet<String> = HashSet::new();
+        for def in resources.structures_definitions.iter().filter(|d| d.is_resource()) {
+            let included =
+                self.include.is_empty() || listed(&self.include, def) || profiled.contains(def.r#type.as_str());
+            if included && !listed(&self.exclude, def) {
+                selected.insert(def.url.clone());
+                selected.extend(resources.get_resource_dependencies(def).iter().map(|d| d.url.clone()));
+            }
+        }
+        let deselected: Vec<(String, String)> = resources
+            .structures_definitions
+            .iter()
+            .filter(|d| d.is_resource() && !selected.contains(&d.url))
+            .map(|d| (d.url.clone(), d.r#type.clone()))
+            .collect();
+        resources.structures_definitions.retain(|d| {
+            if d.is_resource() {
+                selected.contains(&d.url)
+            } else if d.is_resource_profile() {
+                !self.exclude.contains(&d.url) && !deselected.iter().any(|(_, r#type)| *r#type == d.r#type)
+            } else if d.is_extension() {
+                !self.exclude.contains(&d.url)
+            } else {
+                true
+            }
+        });
+
+        // There's no type to name a resource that's left out by, so references that may point at
+        // one may point at any resource instead.
+        for def in &mut resources.structures_definitions {
+            for r#type in def.snapshot.element.iter_mut().flat_map(|el| &mut el.r#type) {
+                let targets_deselected = r#type.target_profile.iter().flatten().any(|target| {
+                    let target = target.split('|').next().unwrap_or(target);
+                    deselected.iter().any(|(url, _)| url == target)
+                });
+                if targets_deselected {
+                    r#type.target_profile = None;
+                }
+            }
+        }
+    }
+
+    /// Extra derives and attributes for the type `name`.
+    pub fn get_type_options(&self, name: &str) -> (Vec<&syn::Path>, Vec<&syn::Attribute>) {
+        let options = [Some(&self.all_types), self.types.get(name)];
+        let derives = options.iter().flatten().flat_map(|o| &o.derives).collect();
+        let attributes = options.iter().flatten().flat_map(|o| &o.attributes).collect();
+        (derives, attributes)
+    }
+}
+
+fn get_type_options(table: &Table, key: &str) -> Result<TypeOptions> {
+    // Tables below `types` configure the type of that name.
+    if let Some((k, _)) = table
+        .iter()
+        .find(|(k, item)| !item.is_table() && !["derives", "attributes"].contains(k))
+    {
+        bail!("unknown key `{}` in {}", k, key);
+    }
+    let mut options = TypeOptions::default();
+    for derive in get_strs(table, "derives")? {
+        let path = syn::parse_str(&derive).with_context(|| format!("{}.derives: `{}`", key, derive))?;
+        options.derives.push(path);
+    }
+    for attribute in get_strs(table, "attributes")? {
+        let parsed = syn::parse::Parser::parse_str(syn::Attribute::parse_outer, &attribute)
+            .with_context(|| format!("{}.attributes: `{}`", key, attribute))?;
+        options.attributes.extend(parsed);
+    }
+    Ok(options)
+}
+
+/// Fails on keys other than `known`, which are most likely misspelt.
+fn check_keys(table: &Table, key: &str, known: &[&str]) -> Result<()> {
+    match table.iter().find(|(k, _)| !known.contains(k)) {
+        Some((k, _)) if key.is_empty() => bail!("unknown key `{}`", k),
+        Some((k, _)) => bail!("unknown key `{}` in {}", k, key),
+        None => Ok(()),
+    }
+}
+
+fn get_table<'a>(table: &'a Table, key: &str) -> Result<Option<&'a Table>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(Item::Table(table)) => Ok(Some(table)),
+        Some(_) => bail!("{} isn't a table", key),
+    }
+}
+
+fn get_str<'a>(table: &'a Table, key: &str) -> Result<Option<&'a str>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(item) => item.as_str().map(Some).ok_or_else(|| anyhow!("{} isn't a string", key)),
+    }
+}
+
+fn get_strs(table: &Table, key: &str) -> Result<Vec<String>> {
+    let Some(item) = table.get(key) else {
+        return Ok(Vec::new());
+    };
+    let array = item.as_array().ok_or_else(|| anyhow!("{} isn't an array", key))?;
+    array
+        .iter()
+        .map(|v| {
+            v.as_str()
+                .map(str::to_string)
+                .ok_or_else(|| anyhow!("{} has a non-string", key))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    const PATIENT: &str = "http://hl7.org/fhir/StructureDefinition/Patient";
+    const MY_PATIENT: &str = "http://example.org/StructureDefinition/my-patient";
+
+    fn parse(contents: &str) -> Result<Config> {
+        Config::parse(contents, Path::new(""))
+    }
+
+    /// A definition of `r#type` whose single element has the type `element_type`, if any.
+    fn definition(
+        url: &str,
+        kind: &str,
+        derivation: &str,
+        r#type: &str,
+        element_type: Option<&str>,
+    ) -> StructureDefinition {
+        let mut element = json!({"id": r#type, "path": r#type});
+        if let Some(code) = element_type {
+            element = json!({"id": format!("{}.value", r#type), "path": format!("{}.value", r#type), "type": [{"code": code}]});
+        }
+        serde_json::from_value(json!({
+            "resourceType": "StructureDefinition",
+            "id": r#type,
+            "url": url,
+            "name": r#type,
+            "fhirVersion": "5.0.0",
+            "kind": kind,
+            "abstract": false,
+            "type": r#type,
+            "derivation": derivation,
+            "snapshot": {"element": [element]},
+        }))
+        .unwrap()
+    }
+
+    /// Patient, and Observation, which needs Patient through the `Subject` data type.
+    fn schema() -> Schema {
+        let core = "http://hl7.org/fhir/StructureDefinition/";
+        Schema {
+            structures_definitions: vec![
+                definition(PATIENT, "resource", "specialization", "Patient", Some("HumanName")),
+                definition(
+                    &format!("{}HumanName", core),
+                    "complex-type",
+                    "specialization",
+                    "HumanName",
+                    None,
+                ),
+                definition(
+                    &format!("{}Observation", core),
+                    "resource",
+                    "specialization",
+                    "Observation",
+                    Some("Subject"),
+                ),
+                definition(
+                    &format!("{}Subject", core),
+                    "complex-type",
+                    "specialization",
+                    "Subject",
+                    Some("Patient"),
+                ),
+                definition(&format!("{}Group", core), "resource", "specialization", "Group", None),
+                definition(MY_PATIENT, "resource", "constraint", "Patient", None),
+            ],
+            ..Schema::default()
+        }
+    }
+
+    /// The types of the resources and the URLs of the profiles left after selecting with `config`.
+    fn select(config: &str) -> (Vec<String>, Vec<String>) {
+        let mut schema = schema();
+        let mut diagnostics = Diagnostics::default();
+        parse(config).unwrap().select(&mut schema, &mut diagnostics);
+        assert_eq!(diagnostics.sorted().len(), 0, "{:?}", diagnostics.sorted());
+        let mut resources: Vec<String> = schema
+            .structures_definitions
+            .iter()
+            .filter(|d| d.is_resource())
+            .map(|d| d.r#type.clone())
+            .collect();
+        resources.sort();
+        let profiles = schema
+            .structures_definitions
+            .iter()
+            .filter(|d| d.is_resource_profile())
+            .map(|d| d.url.clone())
+            .collect();
+        (resources, profiles)
+    }
+
+    #[test]
+    fn parse_rejects_unknown_keys() {
+        let err = parse("creat = \"fhir\"").err().unwrap();
+        assert_eq!(err.to_string(), "unknown key `creat`");
+        let err = parse("[select]\nincludes = [\"Patient\"]").err().unwrap();
+        assert_eq!(err.to_string(), "unknown key `includes` in select");
+        let err = parse("[types]\nderive = [\"Hash\"]").err().unwrap();
+        assert_eq!(err.to_string(), "unknown key `derive` in types");
+        let err = parse("[types.Patient]\nderives = [\"Hash\"]\nattribute = []")
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "unknown key `attribute` in types.Patient");
+    }
+
+    #[test]
+    fn parse_rejects_reserved_module_names() {
+        let err = parse("[modules]\ndatatypes = \"codes\"").err().unwrap();
+        assert_eq!(err.to_string(), "modules: `codes` is taken");
+        let err = parse("[modules]\nresources = \"profiles\"").err().unwrap();
+        assert_eq!(err.to_string(), "modules: `profiles` is taken");
+        assert!(parse("[modules]\nresources = \"resources\"").is_ok());
+    }
+
+    #[test]
+    fn type_options_add_to_all_types() {
+        let config = parse(
+            "[types]\nderives = [\"Hash\"]\n[types.Patient]\nderives = [\"Eq\"]\nattributes = [\"#[non_exhaustive]\"]",
+        )
+        .unwrap();
+        let names = |paths: Vec<&syn::Path>| paths.iter().map(|p| quote::quote!(#p).to_string()).collect::<Vec<_>>();
+        let (derives, attributes) = config.get_type_options("Patient");
+        assert_eq!(names(derives), ["Hash", "Eq"]);
+        assert_eq!(attributes.len(), 1);
+        let (derives, attributes) = config.get_type_options("Group");
+        assert_eq!(names(derives), ["Hash"]);
+        assert!(attributes.is_empty());
+    }
+
+    #[test]
+    fn select_everything_by_default() {
+        let (resources, profiles) = select("");
+        assert_eq!(resources, ["Group", "Observation", "Patient"]);
+        assert_eq!(profiles, [MY_PATIENT]);
+    }
+
+    #[test]
+    fn select_includes_by_name_url_and_profile() {
+        let (resources, profiles) = select("[select]\ninclude = [\"Group\"]");
+        assert_eq!(resources, ["Group"]);
+        assert!(profiles.is_empty());
+
+        let config = format!("[select]\ninclude = [\"{}\"]", PATIENT);
+        assert_eq!(
+            select(&config),
+            (vec!["Patient".to_string()], vec![MY_PATIENT.to_string()])
+        );
+
+        let config = format!("[select]\ninclude = [\"{}\"]", MY_PATIENT);
+        assert_eq!(
+            select(&config),
+            (vec!["Patient".to_string()], vec![MY_PATIENT.to_string()])
+        );
+    }
+
+    #[test]
+    fn select_includes_dependencies() {
+        let (resources, _) = select("[select]\ninclude = [\"Observation\"]");
+        assert_eq!(resources, ["Observation", "Patient"]);
+    }
+
+    #[test]
+    fn select_exclude_wins() {
+        let (resources, profiles) = select("[select]\ninclude = [\"Patient\", \"Group\"]\nexclude = [\"Patient\"]");
+        assert_eq!(resources, ["Group"]);
+        assert!(profiles.is_empty());
+
+        let config = format!("[select]\nexclude = [\"{}\"]", MY_PATIENT);
+        let (resources, profiles) = select(&config);
+        assert_eq!(resources, ["Group", "Observation", "Patient"]);
+        assert!(profiles.is_empty());
+    }
+
+    #[test]
+    fn select_reports_unmatched_entries() {
+        let mut schema = schema();
+        let mut diagnostics = Diagnostics::default();
+        let config = parse("[select]\ninclude = [\"Patient\", \"Patinet\"]").unwrap();
+        config.select(&mut schema, &mut diagnostics);
+        let warnings = diagnostics.sorted();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].message, "`Patinet` matches no definition");
+    }
+}
@@ -0,0 +1,80 @@
+//! Problems found in the definitions, collected so that one run reports all of them rather than
+//! stopping at the first.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Something is generated, but less than the definitions ask for.
+    Warning,
+    /// A definition or element is left out of the generated code.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => f.write_str("warning"),
+            Severity::Error => f.write_str("error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Canonical URL of the definition, the config file for problems with the config, or
+    /// `<name>#<version>` for problems with a package.
+    pub url: String,
+    /// Id of the element, or key of the config, unless the problem is with the definition as a whole.
+    pub element: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.element {
+            Some(ref element) => write!(f, "{}: {} {}: {}", self.severity, self.url, element, self.message),
+            None => write!(f, "{}: {}: {}", self.severity, self.url, self.message),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Diagnostics(Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub fn error(&mut self, url: &str, element: Option<&str>, message: String) {
+        self.push(Severity::Error, url, element, message);
+    }
+
+    pub fn warning(&mut self, url: &str, element: Option<&str>, message: String) {
+        self.push(Severity::Warning, url, element, message);
+    }
+
+    fn push(&mut self, severity: Severity, url: &str, element: Option<&str>, message: String) {
+        self.0.push(Diagnostic {
+            severity,
+            url: url.to_string(),
+            element: element.map(str::to_string),
+            message,
+        });
+    }
+
+    pub fn extend(&mut self, other: Diagnostics) {
+        self.0.extend(other.0);
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.0.iter().filter(|d| d.severity == severity).count()
+    }
+
+    /// The diagnostics by definition URL and element id, errors first within each.
+    pub fn sorted(&self) -> Vec<&Diagnostic> {
+        let mut diagnostics: Vec<&Diagnostic> = self.0.iter().collect();
+        diagnostics.sort_by(|a, b| {
+            (&a.url, &a.element, b.severity, &a.message).cmp(&(&b.url, &b.element, a.severity, &b.message))
+        });
+        diagnostics
+    }
+}
@@ -0,0 +1,614 @@
+//! Doc comments for the generated types from the metadata of the elements they're generated for,
+//! with the markdown in it made into rustdoc.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+
+use crate::schema::{BindingStrength, ConstraintSeverity, ElementDefinition, ElementMax, StructureDefinition};
+
+/// Width the doc comments are filled to, as for `tokens::doc_comment`.
+const WIDTH: usize = 80;
+
+/// HTML tags rustdoc passes through, which the definitions' markdown uses now and then.
+const HTML_TAGS: &[&str] = &[
+    "a", "b", "br", "code", "em", "i", "li", "ol", "p", "pre", "strong", "sub", "sup", "table", "tbody", "td", "th",
+    "thead", "tr", "u", "ul",
+];
+
+/// The doc comment for the field or type generated for `el`: its short description, definition,
+/// comment and requirements, then its cardinality, binding, flags and invariants, and a link to the
+/// page documenting it.
+pub fn element_doc(def: &StructureDefinition, el: &ElementDefinition) -> TokenStream {
+    let base = def.get_web_base();
+    let is_root = el.path == def.r#type;
+    let mut sections = Vec::new();
+
+    // The short description leads, as rustdoc's summary of the item, unless the definition says
+    // the same.
+    let definition = present(&el.definition);
+    if let Some(short) = present(&el.short) {
+        if definition.is_none_or(|d| d.trim_end_matches('.') != short.trim_end_matches('.')) {
+            sections.push(to_rustdoc(short, base));
+        }
+    }
+    sections.extend(definition.map(|d| to_rustdoc(d, base)));
+    sections.extend(present(&el.comment).map(|c| to_rustdoc(c, base)));
+    if let Some(requirements) = present(&el.requirements) {
+        sections.push(format!("**Requirements:** {}", to_rustdoc(requirements, base)));
+    }
+
+    let mut facts = Vec::new();
+    if !is_root {
+        let max = match el.get_max() {
+            ElementMax::Bounded(max) => max.to_string(),
+            ElementMax::Unbounded => "*".to_string(),
+        };
+        facts.push(format!("Cardinality: `{}..{}`", el.get_min(), max));
+    }
+    if let Some(ref binding) = el.binding {
+        let strength = match binding.strength {
+            BindingStrength::Required => "required",
+            BindingStrength::Extensible => "extensible",
+            BindingStrength::Preferred => "preferred",
+            BindingStrength::Example => "example",
+        };
+        let mut fact = format!("Binding: {}", strength);
+        if let Some(ref value_set) = binding.value_set {
+            let url = value_set.split('|').next().unwrap_or(value_set);
+            fact.push_str(&format!(" to <{}>", url));
+        }
+        if let Some(description) = present(&binding.description) {
+            fact.push_str(&format!(" ({})", to_inline(description, base)));
+        }
+        facts.push(fact);
+    }
+    if el.is_modifier {
+        match present(&el.is_modifier_reason) {
+            Some(reason) => facts.push(format!("Modifier element: {}", to_inline(reason, base))),
+            None => facts.push("Modifier element".to_string()),
+        }
+    }
+    if el.is_summary {
+        facts.push("Summary element".to_string());
+    }
+    // As for the invariants evaluated, those inherited by elements below the root are documented on
+    // the type they come from.
+    let invariants: Vec<String> = el
+        .constraint
+        .iter()
+        .filter(|c| is_root || c.source.as_ref().is_none_or(|s| *s == def.url))
+        .map(|c| {
+            let severity = match c.severity {
+                ConstraintSeverity::Error => "error",
+                ConstraintSeverity::Warning => "warning",
+            };
+            let mut invariant = format!("`{}` ({}): {}", c.key, severity, to_inline(&c.human, base));
+            if let Some(ref expression) = c.expression {
+                invariant.push_str(&format!(" {}", code_span(expression)));
+            }
+            invariant
+        })
+        .collect();
+    if !invariants.is_empty() {
+        let items: Vec<String> = invariants.iter().map(|i| format!("  - {}", i)).collect();
+        facts.push(format!("Invariants:\n{}", items.join("\n")));
+    }
+    if !facts.is_empty() {
+        let items: Vec<String> = facts.iter().map(|f| format!("- {}", f)).collect();
+        sections.push(items.join("\n"));
+    }
+    sections.push(format!("[Specification]({})", def.get_spec_url(el)));
+
+    let text = fill(&sections.join("\n\n"));
+    let lines = text.lines().map(|line| {
+        if line.trim().is_empty() {
+            String::new()
+        } else {
+            format!(" {}", line)
+        }
+    });
+    quote! { #(#[doc = #lines])* }
+}
+
+/// `text`, trimmed, unless there's nothing to it.
+fn present(text: &Option<String>) -> Option<&str> {
+    text.as_deref().map(str::trim).filter(|t| !t.is_empty())
+}
+
+/// `markdown` as rustdoc: relative links resolved against `base`, code blocks marked as text so
+/// that they aren't taken for doctests, and brackets, bare URLs and `<` that rustdoc would take for
+/// intra-doc links or HTML, or warn about, escaped.
+fn to_rustdoc(markdown: &str, base: &str) -> String {
+    let mut lines = Vec::new();
+    // The character and length of the fence of the code block the line is in.
+    let mut fence: Option<(char, usize)> = None;
+    for line in markdown.replace("\r\n", "\n").replace('\t', "    ").lines() {
+        let trimmed = line.trim_start();
+        let indent = &line[..(line.len() - trimmed.len()).min(3)];
+        let marker = trimmed.chars().next().filter(|c| *c == '`' || *c == '~');
+        let marker_len = marker.map_or(0, |m| trimmed.chars().take_while(|c| *c == m).count());
+        match fence {
+            Some((c, len)) if marker == Some(c) && marker_len >= len && trimmed[marker_len..].trim().is_empty() => {
+                fence = None;
+                lines.push(format!("{}{}", indent, trimmed));
+            }
+            Some(_) => lines.push(line.to_string()),
+            None if marker_len >= 3 => {
+                let info = trimmed[marker_len..].trim();
+                let info = if info.is_empty() || info == "rust" {
+                    "text"
+                } else {
+                    info
+                };
+                lines.push(format!("{}{}{}", indent, &trimmed[..marker_len], info));
+                fence = marker.map(|c| (c, marker_len));
+            }
+            // Four spaces would make an indented code block, which rustdoc would run as a doctest.
+            None => lines.push(format!("{}{}", indent, to_inline(trimmed, base))),
+        }
+    }
+    if let Some((c, len)) = fence {
+        lines.push(c.to_string().repeat(len));
+    }
+    lines.join("\n")
+}
+
+/// One line of markdown as rustdoc, see [`to_rustdoc`]; line breaks in `markdown` become spaces.
+fn to_inline(markdown: &str, base: &str) -> String {
+    let markdown = markdown.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut out = String::new();
+    let mut rest = markdown.as_str();
+    let mut after_word = false;
+    while let Some(c) = rest.chars().next() {
+        let len = match c {
+            '\\' => rest.chars().nth(1).map_or(1, |next| 1 + next.len_utf8()),
+            '`' => {
+                let ticks = &rest[..rest.find(|c| c != '`').unwrap_or(rest.len())];
+                match rest[ticks.len()..].find(ticks) {
+                    Some(end) => 2 * ticks.len() + end,
+                    None => ticks.len(),
+                }
+            }
+            '[' => match parse_link(rest) {
+                Some((text, target, len)) => {
+                    out.push_str(&format!("[{}]({})", to_inline(text, base), resolve_link(target, base)));
+                    rest = &rest[len..];
+                    after_word = false;
+                    continue;
+                }
+                None => {
+                    out.push_str("\\[");
+                    rest = &rest[1..];
+                    continue;
+                }
+            },
+            ']' => {
+                out.push_str("\\]");
+                rest = &rest[1..];
+                continue;
+            }
+            '<' => match rest.find('>').map(|end| &rest[..=end]) {
+                Some(tag) if is_autolink(tag) || is_html_tag(tag) => tag.len(),
+                _ => {
+                    out.push_str("&lt;");
+                    rest = &rest[1..];
+                    continue;
+                }
+            },
+            'h' if !after_word && (rest.starts_with("http://") || rest.starts_with("https://")) => {
+                let end = rest
+                    .find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"' | '`' | '[' | ']'))
+                    .unwrap_or(rest.len());
+                let url = rest[..end].trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '\'']);
+                out.push_str(&format!("<{}>", url));
+                rest = &rest[url.len()..];
+                after_word = true;
+                continue;
+            }
+            c => c.len_utf8(),
+        };
+        out.push_str(&rest[..len]);
+        after_word = rest[..len].chars().last().is_some_and(char::is_alphanumeric);
+        rest = &rest[len..];
+    }
+    out
+}
+
+/// The text and target of the link `[text](target)` that `markdown` starts with, and its length.
+fn parse_link(markdown: &str) -> Option<(&str, &str, usize)> {
+    let text_end = markdown.find("](")?;
+    let text = &markdown[1..text_end];
+    if text.contains(['[', ']']) {
+        return None;
+    }
+    let target_start = text_end + 2;
+    let target_end = target_start + markdown[target_start..].find(')')?;
+    let target = markdown[target_start..target_end].trim();
+    if target.is_empty() || target.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((text, target, target_end + 1))
+}
+
+/// `target` made absolute against `base` if it's relative to the page, e.g. `datatypes.html#Period`.
+fn resolve_link(target: &str, base: &str) -> String {
+    if target.contains("://") || target.starts_with("mailto:") || target.starts_with('#') {
+        target.to_string()
+    } else {
+        format!("{}{}", base, target.trim_start_matches("./").trim_start_matches('/'))
+    }
+}
+
+fn is_autolink(tag: &str) -> bool {
+    let url = &tag[1..tag.len() - 1];
+    (url.starts_with("http://") || url.starts_with("https://") || url.starts_with("mailto:"))
+        && !url.contains(char::is_whitespace)
+}
+
+fn is_html_tag(tag: &str) -> bool {
+    let name: String = tag[1..]
+        .trim_start_matches('/')
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect();
+    HTML_TAGS.contains(&name.to_ascii_lowercase().as_str())
+}
+
+/// `text` as inline code, delimited by enough backticks to hold the ones in it.
+fn code_span(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut longest = 0;
+    let mut run = 0;
+    for c in text.chars() {
+        run = if c == '`' { run + 1 } else { 0 };
+        longest = longest.max(run);
+    }
+    let ticks = "`".repeat(longest + 1);
+    if longest > 0 {
+        format!("{} {} {}", ticks, text, ticks)
+    } else {
+        format!("{}{}{}", ticks, text, ticks)
+    }
+}
+
+/// `text` filled to `WIDTH`, leaving code blocks, tables and headings be, and continuing list items
+/// at their content's indent. Lines are never broken before something markdown would start a new
+/// block at, such as `- ` or `1. `.
+fn fill(text: &str) -> String {
+    let mut lines = Vec::new();
+    let mut in_fence = false;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        let is_fence = trimmed.starts_with("```") || trimmed.starts_with("~~~");
+        if is_fence {
+            in_fence = !in_fence;
+        }
+        if is_fence || in_fence || trimmed.starts_with(['|', '#']) {
+            lines.push(line.to_string());
+            continue;
+        }
+        let indent = line.len() - trimmed.len();
+        let continuation = indent + list_marker_len(trimmed).unwrap_or(0);
+        lines.extend(wrap(&line[..indent], trimmed, &" ".repeat(continuation)));
+    }
+    lines.join("\n")
+}
+
+/// Breaks `text` at spaces into lines of at most `WIDTH`, the first starting with `indent` and the
+/// rest with `subsequent_indent`. A word that a line mustn't start with stays with the one before
+/// it, and a word too long for a line gets one of its own.
+fn wrap(indent: &str, text: &str, subsequent_indent: &str) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    let mut rest = text.trim_start_matches(' ');
+    while !rest.is_empty() {
+        let end = rest.find(' ').unwrap_or(rest.len());
+        match words.last_mut() {
+            Some(last) if starts_block(rest) => {
+                last.push(' ');
+                last.push_str(&rest[..end]);
+            }
+            _ => words.push(rest[..end].to_string()),
+        }
+        rest = rest[end..].trim_start_matches(' ');
+    }
+
+    let mut lines = Vec::new();
+    let mut line = indent.to_string();
+    for (i, word) in words.iter().enumerate() {
+        if i > 0 && line.chars().count() + 1 + word.chars().count() > WIDTH {
+            lines.push(std::mem::replace(&mut line, subsequent_indent.to_string()));
+        } else if i > 0 {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    lines.push(line);
+    lines
+}
+
+/// The length of the list marker `line` starts with, with the space after it, e.g. 2 for `- `.
+fn list_marker_len(line: &str) -> Option<usize> {
+    if line.starts_with("- ") || line.starts_with("* ") || line.starts_with("+ ") {
+        return Some(2);
+    }
+    let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+    let after = &line[digits..];
+    (digits > 0 && (after.starts_with(". ") || after.starts_with(") "))).then_some(digits + 2)
+}
+
+/// Whether a line starting with `text` would start a block rather than continue a paragraph.
+fn starts_block(text: &str) -> bool {
+    list_marker_len(text).is_some() || text.starts_with(['#', '>', '|', '=']) || text.starts_with("```")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    const BASE: &str = "https://hl7.org/fhir/R5/";
+
+    fn observation(elements: serde_json::Value) -> StructureDefinition {
+        serde_json::from_value(json!({
+            "resourceType": "StructureDefinition",
+            "id": "Observation",
+            "url": "http://hl7.org/fhir/StructureDefinition/Observation",
+            "name": "Observation",
+            "fhirVersion": "5.0.0",
+            "kind": "resource",
+            "abstract": false,
+            "type": "Observation",
+            "baseDefinition": "http://hl7.org/fhir/StructureDefinition/DomainResource",
+            "derivation": "specialization",
+            "snapshot": {"element": elements},
+        }))
+        .unwrap()
+    }
+
+    /// The text of the doc attributes `element_doc` gives for the `index`th element, without the
+    /// space each line starts with.
+    fn doc(def: &StructureDefinition, index: usize) -> String {
+        let tokens = element_doc(def, &def.snapshot.element[index]);
+        let item: syn::ItemStruct = syn::parse2(quote! { #tokens struct Doc; }).unwrap();
+        let lines: Vec<String> = item
+            .attrs
+            .iter()
+            .map(|attr| match &attr.meta {
+                syn::Meta::NameValue(syn::MetaNameValue {
+                    value:
+                        syn::Expr::Lit(syn::ExprLit {
+                            lit: syn::Lit::Str(line),
+                            ..
+                        }),
+                    ..
+                }) => line.value(),
+                _ => panic!("not a doc attribute"),
+            })
+            .map(|line| match line.strip_prefix(' ') {
+                Some(line) => line.to_string(),
+                None if line.is_empty() => line,
+                None => panic!("{:?} doesn't start with a space", line),
+            })
+            .collect();
+        lines.join("\n")
+    }
+
+    #[test]
+    fn element_doc_sections() {
+        let def = observation(json!([
+            {"id": "Observation", "path": "Observation"},
+            {
+                "id": "Observation.value[x]",
+                "path": "Observation.value[x]",
+                "short": "Actual result",
+                "definition": "The information determined as a result of making the observation.",
+                "comment": "See [the notes](observation.html#notes) on `value[x]`.",
+                "requirements": "An observation exists to have a value.",
+                "min": 0,
+                "max": "1",
+                "type": [{"code": "Quantity"}, {"code": "string"}],
+                "constraint": [{
+                    "key": "obs-7",
+                    "severity": "error",
+                    "human": "If Observation.component.code is the same as Observation.code, then Observation.value SHALL NOT be present",
+                    "expression": "value.empty() or component.code.where(coding.intersect(%resource.code.coding).exists()).empty()",
+                    "source": "http://hl7.org/fhir/StructureDefinition/Observation",
+                }, {
+                    "key": "ele-1",
+                    "severity": "error",
+                    "human": "All FHIR elements must have a @value or children",
+                    "expression": "hasValue() or (children().count() > id.count())",
+                    "source": "http://hl7.org/fhir/StructureDefinition/Element",
+                }],
+                "isModifier": true,
+                "isModifierReason": "Not really, but for the test",
+                "isSummary": true,
+                "binding": {
+                    "strength": "example",
+                    "valueSet": "http://hl7.org/fhir/ValueSet/observation-values|5.0.0",
+                    "description": "Codes for [values]",
+                },
+            },
+        ]));
+        assert_eq!(
+            doc(&def, 1),
+            "\
+Actual result
+
+The information determined as a result of making the observation.
+
+See [the notes](https://hl7.org/fhir/R5/observation.html#notes) on `value[x]`.
+
+**Requirements:** An observation exists to have a value.
+
+- Cardinality: `0..1`
+- Binding: example to <http://hl7.org/fhir/ValueSet/observation-values> (Codes
+  for \\[values\\])
+- Modifier element: Not really, but for the test
+- Summary element
+- Invariants:
+  - `obs-7` (error): If Observation.component.code is the same as
+    Observation.code, then Observation.value SHALL NOT be present `value.empty()
+    or
+    component.code.where(coding.intersect(%resource.code.coding).exists()).empty()`
+
+[Specification](https://hl7.org/fhir/R5/observation-definitions.html#Observation.value_x_)"
+        );
+    }
+
+    #[test]
+    fn element_doc_base_cardinality() {
+        let def = observation(json!([
+            {"id": "Observation", "path": "Observation"},
+            {
+                "id": "Observation.component",
+                "path": "Observation.component",
+                "short": "Component results",
+                "base": {"path": "Observation.component", "min": 1, "max": "*"},
+                "type": [{"code": "BackboneElement"}],
+            },
+        ]));
+        assert_eq!(
+            doc(&def, 1),
+            "\
+Component results
+
+- Cardinality: `1..*`
+
+[Specification](https://hl7.org/fhir/R5/observation-definitions.html#Observation.component)"
+        );
+    }
+
+    #[test]
+    fn element_doc_root() {
+        let def = observation(json!([{
+            "id": "Observation",
+            "path": "Observation",
+            "short": "Measurements and simple assertions",
+            "definition": "Measurements and simple assertions.",
+            "constraint": [{
+                "key": "ele-1",
+                "severity": "warning",
+                "human": "All FHIR elements must have a @value or children",
+                "expression": "text.`div`.exists()",
+                "source": "http://hl7.org/fhir/StructureDefinition/Element",
+            }],
+        }]));
+        assert_eq!(
+            doc(&def, 0),
+            "\
+Measurements and simple assertions.
+
+- Invariants:
+  - `ele-1` (warning): All FHIR elements must have a @value or children ``
+    text.`div`.exists() ``
+
+[Specification](https://hl7.org/fhir/R5/observation-definitions.html#Observation)"
+        );
+    }
+
+    #[test]
+    fn rustdoc_code_blocks() {
+        assert_eq!(
+            to_rustdoc("Example:\n```\nlet x = 1;\n```", BASE),
+            "Example:\n```text\nlet x = 1;\n```"
+        );
+        assert_eq!(
+            to_rustdoc("~~~~rust\nfn main() {}\n~~~~", BASE),
+            "~~~~text\nfn main() {}\n~~~~"
+        );
+        assert_eq!(
+            to_rustdoc("```json\n{\"a\": [1]}\n```", BASE),
+            "```json\n{\"a\": [1]}\n```"
+        );
+        // A block the markdown leaves open is closed.
+        assert_eq!(to_rustdoc("```\n[x]", BASE), "```text\n[x]\n```");
+        // Indented code blocks become paragraphs rather than doctests.
+        assert_eq!(
+            to_rustdoc("Text\n\n        code()\n\tmore()", BASE),
+            "Text\n\n   code()\n   more()"
+        );
+    }
+
+    #[test]
+    fn rustdoc_inline() {
+        assert_eq!(
+            to_rustdoc(
+                "See [Period](datatypes.html#Period) and [here](http://example.org/a).",
+                BASE
+            ),
+            "See [Period](https://hl7.org/fhir/R5/datatypes.html#Period) and [here](http://example.org/a)."
+        );
+        assert_eq!(
+            to_rustdoc("Only value[x] or [[Patient]]", BASE),
+            "Only value\\[x\\] or \\[\\[Patient\\]\\]"
+        );
+        assert_eq!(to_rustdoc("`value[x]` and `` a`b ``", BASE), "`value[x]` and `` a`b ``");
+        assert_eq!(
+            to_rustdoc("At http://example.org/fhir, or <https://example.org>.", BASE),
+            "At <http://example.org/fhir>, or <https://example.org>."
+        );
+        assert_eq!(
+            to_rustdoc("List<Reference> is <b>bold</b>, a < b", BASE),
+            "List&lt;Reference> is <b>bold</b>, a &lt; b"
+        );
+        assert_eq!(to_rustdoc("Escaped \\[x\\] stays", BASE), "Escaped \\[x\\] stays");
+    }
+
+    #[test]
+    fn fill_wraps_paragraphs_and_lists() {
+        let text = "word ".repeat(30);
+        let filled = fill(text.trim_end());
+        assert!(filled.lines().all(|line| line.len() <= WIDTH), "{}", filled);
+        assert_eq!(filled.lines().count(), 2);
+
+        let filled = fill(&format!(
+            "- {}\n  - {}",
+            "item ".repeat(20).trim_end(),
+            "nested ".repeat(15).trim_end()
+        ));
+        assert_eq!(
+            filled,
+            "\
+- item item item item item item item item item item item item item item item
+  item item item item item
+  - nested nested nested nested nested nested nested nested nested nested nested
+    nested nested nested nested"
+        );
+    }
+
+    #[test]
+    fn fill_never_starts_a_block() {
+        let filled = fill(&format!("{} - not a list", "a".repeat(76)));
+        assert_eq!(filled, format!("{} -\nnot a list", "a".repeat(76)));
+
+        for len in 50..80 {
+            let text = format!(
+                "{} - one, 1. two, + three, # four, > five, = six, | seven",
+                "a".repeat(len)
+            );
+            let filled = fill(&text);
+            for line in filled.lines().skip(1) {
+                assert!(!starts_block(line), "{:?} in {}", line, filled);
+            }
+            assert_eq!(filled.replace('\n', " "), text);
+        }
+    }
+
+    #[test]
+    fn fill_leaves_blocks_and_long_words() {
+        let long = "x".repeat(100);
+        let text = format!(
+            "# {}\n\n| {} |\n\n```text\n{}\n```\n\nshort {} short",
+            long, long, long, long
+        );
+        assert_eq!(
+            fill(&text),
+            format!(
+                "# {}\n\n| {} |\n\n```text\n{}\n```\n\nshort\n{}\nshort",
+                long, long, long, long
+            )
+        );
+    }
+}
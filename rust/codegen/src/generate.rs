@@ -0,0 +1,2951 @@
+//! The generated modules, as token streams by the path of their file.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use convert_case::{Case, Casing};
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::ext::IdentExt;
+
+use crate::config::Config;
+use crate::diagnostics::Diagnostics;
+use crate::docs;
+use crate::schema;
+use crate::tokens::{self, doc_comment, ident, int, rust_type};
+
+/// The feature enabling every resource, on by default.
+pub const ALL_RESOURCES_FEATURE: &str = "all-resources";
+
+/// What [`generate`] makes of the definitions of some releases.
+pub struct Generated {
+    /// The items of each file, by path relative to the directory of the module holding the releases'
+    /// modules, e.g. `r5/patient.rs`. That module is `mod.rs`.
+    files: BTreeMap<PathBuf, TokenStream>,
+    /// The cargo feature of each resource, with the features of the resources it depends on.
+    pub features: BTreeMap<String, BTreeSet<String>>,
+    /// What couldn't be generated, or only in part, by release module.
+    pub diagnostics: Vec<(String, Diagnostics)>,
+}
+
+impl Generated {
+    /// The source of each file, by path as in `files`.
+    pub fn render(&self) -> Result<BTreeMap<PathBuf, String>> {
+        self.files
+            .iter()
+            .map(|(path, tokens)| {
+                let source = tokens::render(tokens.clone()).with_context(|| format!("generate {}", path.display()))?;
+                Ok((path.clone(), source))
+            })
+            .collect()
+    }
+
+    /// The source of every file in one, the modules declared with `mod name;` written out in place.
+    /// Unlike the files, it can be `include!`d, e.g. from `OUT_DIR`.
+    pub fn render_single_file(&self) -> Result<String> {
+        let items = self.get_inlined_items(Path::new("mod.rs"))?;
+        Ok(prettyplease::unparse(&syn::File {
+            shebang: None,
+            attrs: Vec::new(),
+            items,
+        }))
+    }
+
+    /// Writes [`Generated::render_single_file`] to `name` in the build script's `OUT_DIR`.
+    pub fn write_to_out_dir(&self, name: &str) -> Result<()> {
+        let out_dir =
+            std::env::var_os("OUT_DIR").ok_or_else(|| anyhow!("OUT_DIR isn't set, is this a build script?"))?;
+        let path = Path::new(&out_dir).join(name);
+        std::fs::write(&path, self.render_single_file()?).with_context(|| format!("write {}", path.display()))
+    }
+
+    /// The items of the file at `path`, with the modules it declares inlined.
+    fn get_inlined_items(&self, path: &Path) -> Result<Vec<syn::Item>> {
+        let tokens = self
+            .files
+            .get(path)
+            .ok_or_else(|| anyhow!("no file {}", path.display()))?;
+        let file: syn::File = syn::parse2(tokens.clone()).with_context(|| format!("generate {}", path.display()))?;
+        let dir = match path.file_stem() {
+            Some(stem) if stem != "mod" => path.with_extension(""),
+            _ => path.parent().unwrap_or(Path::new("")).to_path_buf(),
+        };
+        let mut items = file.items;
+        for item in &mut items {
+            let syn::Item::Mod(module) = item else {
+                continue;
+            };
+            if module.content.is_some() {
+                continue;
+            }
+            let name = module.ident.unraw().to_string();
+            let child = [dir.join(format!("{}.rs", name)), dir.join(name).join("mod.rs")]
+                .into_iter()
+                .find(|p| self.files.contains_key(p))
+                .ok_or_else(|| anyhow!("no file for module {} in {}", module.ident, path.display()))?;
+            let child_file: syn::File =
+                syn::parse2(self.files[&child].clone()).with_context(|| format!("generate {}", child.display()))?;
+            module.attrs.extend(child_file.attrs);
+            module.content = Some((Default::default(), self.get_inlined_items(&child)?));
+            module.semi = None;
+        }
+        Ok(items)
+    }
+}
+
+/// Generates a module per release from its definitions, named as given, e.g. `r5`. The crate the
+/// modules are in is either the `fhir` crate or one depending on it as `output.runtime` in
+/// `config`; either way it needs `serde` and `serde_json` too.
+pub fn generate(config: &Config, releases: Vec<(&str, schema::Schema)>) -> Result<Generated> {
+    let mut generated = Generated {
+        files: BTreeMap::new(),
+        features: BTreeMap::new(),
+        diagnostics: Vec::new(),
+    };
+    let mut root_mod = TokenStream::new();
+    for (name, resources) in releases {
+        let mut diagnostics = Diagnostics::default();
+        let features = generate_release(
+            config,
+            resources,
+            Path::new(name),
+            &mut generated.files,
+            &mut diagnostics,
+        )?;
+        for (feature, dependencies) in features {
+            generated.features.entry(feature).or_default().extend(dependencies);
+        }
+        generated.diagnostics.push((name.to_string(), diagnostics));
+
+        let module = ident(name)?;
+        let cfg = config.output.features.then(|| quote!(#[cfg(feature = #name)]));
+        root_mod.extend(quote! {
+            #cfg
+            pub mod #module;
+        });
+    }
+    generated.files.insert(PathBuf::from("mod.rs"), root_mod);
+
+    let runtime = &config.output.runtime;
+    for tokens in generated.files.values_mut() {
+        *tokens = tokens::replace_crate(std::mem::take(tokens), runtime);
+    }
+    Ok(generated)
+}
+
+/// Generates a release's module into `outputs` under `release_dir`, returning the cargo feature of
+/// each resource with the features of the resources it depends on. What can't be generated is left
+/// out and reported in `diagnostics`.
+fn generate_release(
+    config: &Config,
+    mut resources: schema::Schema,
+    release_dir: &Path,
+    outputs: &mut BTreeMap<PathBuf, TokenStream>,
+    diagnostics: &mut Diagnostics,
+) -> Result<BTreeMap<String, Vec<String>>> {
+    resources.sort();
+    config.select(&mut resources, diagnostics);
+    resources.apply_renames(&config.type_renames, &config.field_renames);
+    resources.remove_unsupported();
+    diagnostics.extend(std::mem::take(&mut resources.diagnostics));
+    resources.resolve_recursive_types();
+    let code_enums = resources.resolve_code_bindings();
+
+    let modules = &config.modules;
+    let datatypes_dir = release_dir.join(&modules.datatypes);
+    let resources_dir = match modules.resources {
+        Some(ref name) => release_dir.join(name),
+        None => release_dir.to_path_buf(),
+    };
+    let extensions = ident(&modules.extensions)?;
+    // Data types refer to resources too, e.g. `Identifier.assigner` to `Organization`.
+    let mut datatypes_mod = quote! {
+        #[allow(unused_imports)]
+        use super::*;
+
+        /// A primitive value with the id and extensions carried in its `_name` JSON property.
+        pub type Element<T> = crate::element::Element<T, Extension>;
+    };
+    let datatypes = ident(&modules.datatypes)?;
+    let mut release_mod = quote! {
+        pub mod #datatypes;
+        pub use #datatypes::*;
+
+        pub mod traits;
+
+        pub mod codes;
+    };
+    let mut resources_mod = quote! {
+        #[allow(unused_imports)]
+        use super::*;
+    };
+    outputs.insert(release_dir.join("codes.rs"), generate_code_enums(&code_enums)?);
+
+    let traits = resolve_traits(&resources);
+    outputs.insert(release_dir.join("traits.rs"), generate_traits(&traits)?);
+
+    for structure_definition in &resources.structures_definitions {
+        if structure_definition.is_datatype_profile() {
+            let doc = format!(
+                " `{}` profile on `{}`.",
+                structure_definition.id, structure_definition.r#type
+            );
+            let name = ident(&structure_definition.id)?;
+            let base = ident(&structure_definition.r#type)?;
+            datatypes_mod.extend(quote! {
+                #[doc = #doc]
+                pub type #name = #base;
+            });
+            continue;
+        }
+
+        let (dir, module) = if structure_definition.is_datatype() {
+            (datatypes_dir.as_path(), &mut datatypes_mod)
+        } else if structure_definition.is_resource() && modules.resources.is_some() {
+            (resources_dir.as_path(), &mut resources_mod)
+        } else if structure_definition.is_resource() {
+            (release_dir, &mut release_mod)
+        } else {
+            continue;
+        };
+
+        let modname = structure_definition.get_structure_field_name();
+        let mut file = quote! {
+            #[allow(unused_imports)]
+            use super::*;
+            #[allow(unused_imports)]
+            use crate::primitives::*;
+        };
+        if let Some(el) = structure_definition.snapshot.element.first() {
+            file.extend(generate_structs(config, structure_definition, el)?);
+        }
+        if structure_definition.is_resource() {
+            let trait_urls = get_trait_urls(&resources, structure_definition);
+            for resource_trait in traits.iter().filter(|t| trait_urls.contains(&t.def.url.as_str())) {
+                file.extend(generate_trait_impl(structure_definition, resource_trait)?);
+            }
+        }
+        if structure_definition.is_bundle() {
+            file.extend(generate_bundle_impl());
+        }
+        if structure_definition.is_resource() && has_extension_field(structure_definition) {
+            file.extend(generate_extension_accessors(
+                &structure_definition.get_structure_type_name(),
+                &extensions,
+            )?);
+        }
+        outputs.insert(dir.join(format!("{}.rs", modname)), file);
+
+        let modname = ident(&modname)?;
+        if structure_definition.is_resource() {
+            let cfg = get_feature_cfg(config, structure_definition);
+            module.extend(quote! {
+                #cfg
+                mod #modname;
+                #cfg
+                pub use #modname::*;
+            });
+        } else {
+            module.extend(quote! {
+                mod #modname;
+                pub use #modname::*;
+            });
+        }
+    }
+
+    let mut concrete_resources: Vec<&schema::StructureDefinition> = resources
+        .structures_definitions
+        .iter()
+        .filter(|d| d.is_resource())
+        .collect();
+    concrete_resources.sort_by_key(|d| d.get_structure_type_name());
+    let features: BTreeMap<String, Vec<String>> = concrete_resources
+        .iter()
+        .map(|d| {
+            let dependencies = resources.get_resource_dependencies(d);
+            (
+                d.get_feature_name(),
+                dependencies.iter().map(|d| d.get_feature_name()).collect(),
+            )
+        })
+        .collect();
+    let shared_traits: Vec<&ResourceTrait> = traits
+        .iter()
+        .filter(|t| {
+            concrete_resources
+                .iter()
+                .all(|d| get_trait_urls(&resources, d).contains(&t.def.url.as_str()))
+        })
+        .collect();
+    outputs.insert(
+        release_dir.join("resource.rs"),
+        generate_resource_enum(config, &concrete_resources, &shared_traits)?,
+    );
+    outputs.insert(
+        release_dir.join("typed_reference.rs"),
+        generate_typed_reference(config, &concrete_resources)?,
+    );
+    generate_extensions(&resources, &release_dir.join(&modules.extensions), outputs)?;
+    generate_profiles(config, &resources, &release_dir.join(&modules.profiles), outputs)?;
+    if let Some(ref name) = modules.resources {
+        outputs.insert(resources_dir.join("mod.rs"), resources_mod);
+        let name = ident(name)?;
+        release_mod.extend(quote! {
+            pub mod #name;
+            pub use #name::*;
+        });
+    }
+
+    // `Resource` has to have a variant for its matches to compile.
+    if config.output.features {
+        let any_resource = features.keys();
+        let message = format!(
+            "no resource features are on, enable some or `{}`",
+            ALL_RESOURCES_FEATURE
+        );
+        release_mod.extend(quote! {
+            #[cfg(not(any(#(feature = #any_resource),*)))]
+            compile_error!(#message);
+        });
+    }
+    let profiles = ident(&modules.profiles)?;
+    release_mod.extend(quote! {
+        mod resource;
+        pub use resource::*;
+
+        mod typed_reference;
+        pub use typed_reference::*;
+
+        pub mod #extensions;
+
+        pub mod #profiles;
+    });
+    outputs.insert(datatypes_dir.join("mod.rs"), datatypes_mod);
+    outputs.insert(release_dir.join("mod.rs"), release_mod);
+    Ok(features)
+}
+
+/// `#[cfg(feature = "...")]` for the feature of the resource `def`, or nothing when resources
+/// aren't behind features.
+fn get_feature_cfg(config: &Config, def: &schema::StructureDefinition) -> TokenStream {
+    if config.output.features {
+        let feature = def.get_feature_name();
+        quote!(#[cfg(feature = #feature)])
+    } else {
+        TokenStream::new()
+    }
+}
+
+/// Whether the definition's root has the usual `extension: Vec<Extension>` field.
+fn has_extension_field(def: &schema::StructureDefinition) -> bool {
+    get_root_fields(def).iter().any(|f| {
+        f.json_name == "extension" && f.type_name == "Extension" && f.cardinality == schema::Cardinality::Repeated
+    })
+}
+
+/// `get_extension`/`set_extension` on a resource, reading and writing its `extension` through the
+/// typed structs in `extensions`.
+fn generate_extension_accessors(typename: &str, extensions: &proc_macro2::Ident) -> Result<TokenStream> {
+    let typename = ident(typename)?;
+    Ok(quote! {
+        impl #typename {
+            /// The first extension with `T`'s URL, read as a `T`.
+            pub fn get_extension<T: #extensions::TypedExtension>(
+                &self,
+            ) -> Result<Option<T>, crate::extension::ExtensionError> {
+                #extensions::get_extension(&self.extension)
+            }
+
+            /// Replaces the extensions with `T`'s URL by `value`.
+            pub fn set_extension<T: #extensions::TypedExtension>(&mut self, value: T) {
+                #extensions::set_extension(&mut self.extension, value)
+            }
+        }
+    })
+}
+
+/// A type the base `Extension.value[x]` allows, and its variant of the `value[x]` enum.
+struct ExtensionValueVariant {
+    code: String,
+    variant: String,
+    type_name: String,
+    boxed: bool,
+}
+
+/// What the extension structs build on: the base `Extension` and its `value[x]` enum.
+struct ExtensionBase<'a> {
+    def: &'a schema::StructureDefinition,
+    value_enum: String,
+    variants: Vec<ExtensionValueVariant>,
+    /// Struct names of the extension definitions, by canonical URL.
+    names: HashMap<&'a str, String>,
+}
+
+impl ExtensionBase<'_> {
+    /// The variants for the types `el` allows, leaving out any the base `Extension` doesn't.
+    fn get_variants(&self, el: &schema::ElementDefinition) -> Vec<&ExtensionValueVariant> {
+        el.get_choice_types()
+            .into_iter()
+            .filter_map(|t| self.variants.iter().find(|v| v.code == t.code))
+            .collect()
+    }
+}
+
+/// What a sub-extension of a complex extension holds.
+enum ExtensionSliceKind<'a> {
+    /// A `value[x]` of one of these types.
+    Value(Vec<&'a ExtensionValueVariant>),
+    /// Sub-extensions of its own, read into a nested struct.
+    Nested(String),
+    /// Another extension definition's struct.
+    Profile(String),
+}
+
+struct ExtensionSlice<'a> {
+    el: &'a schema::ElementDefinition,
+    name: String,
+    url: String,
+    cardinality: schema::Cardinality,
+    kind: ExtensionSliceKind<'a>,
+}
+
+/// Generates the `extensions` module into `dir`: a struct per extension definition implementing
+/// `TypedExtension`, which converts it from and to the generic `Extension`.
+fn generate_extensions(
+    resources: &schema::Schema,
+    dir: &Path,
+    outputs: &mut BTreeMap<PathBuf, TokenStream>,
+) -> Result<()> {
+    let Some(base_def) = resources.get_structure_definition(schema::EXTENSION_STRUCTURE_DEFINITION_URL) else {
+        bail!("no Extension definition");
+    };
+    let Some(value_el) = base_def.snapshot.element.iter().find(|e| e.id == "Extension.value[x]") else {
+        bail!("no Extension.value[x]");
+    };
+    let variants = value_el
+        .get_choice_types()
+        .into_iter()
+        .map(|t| ExtensionValueVariant {
+            code: t.code.clone(),
+            variant: get_choice_variant_name(t),
+            type_name: base_def.get_type_name(t),
+            boxed: t.boxed,
+        })
+        .collect();
+
+    // Extension structs live beside the data types and resources they glob-import, so their
+    // names mustn't shadow any of those.
+    let mut taken = get_reserved_type_names(resources);
+    taken.insert("TypedExtension".to_string());
+    let extension_defs: Vec<&schema::StructureDefinition> = resources
+        .structures_definitions
+        .iter()
+        .filter(|d| d.is_extension())
+        .collect();
+    let mut names = HashMap::new();
+    for def in &extension_defs {
+        names.insert(def.url.as_str(), take_name(&mut taken, def.get_structure_type_name()));
+    }
+    let base = ExtensionBase {
+        def: base_def,
+        value_enum: base_def.get_choice_type_name(value_el),
+        variants,
+        names,
+    };
+
+    let mut module = generate_extensions_mod(&base)?;
+    for def in extension_defs {
+        let name = base.names[def.url.as_str()].clone();
+        let modname = name.to_case(Case::Snake);
+        let mut file = quote! {
+            #[allow(unused_imports)]
+            use super::*;
+            #[allow(unused_imports)]
+            use crate::primitives::*;
+        };
+        let root = def.snapshot.element.first();
+        file.extend(generate_extension_struct(
+            &base,
+            def,
+            &def.r#type,
+            root,
+            &name,
+            &def.url,
+            &mut taken,
+        )?);
+        outputs.insert(dir.join(format!("{}.rs", modname)), file);
+        let modname = ident(&modname)?;
+        module.extend(quote! {
+            mod #modname;
+            pub use #modname::*;
+        });
+    }
+    outputs.insert(dir.join("mod.rs"), module);
+    Ok(())
+}
+
+/// Names of the types in a release's root module, which the modules beside it glob-import.
+fn get_reserved_type_names(resources: &schema::Schema) -> HashSet<String> {
+    let mut taken: HashSet<String> = [
+        "Element",
+        "Extension",
+        "PrimitiveError",
+        "Resource",
+        "ResourceType",
+        "TypedReference",
+        "ReferenceTarget",
+    ]
+    .iter()
+    .map(|n| n.to_string())
+    .collect();
+    taken.extend(
+        schema::PRIMITIVE_TYPE_CODES
+            .iter()
+            .map(|code| schema::StructureDefinition::get_type_code_name(code)),
+    );
+    for def in &resources.structures_definitions {
+        if def.is_datatype_profile() {
+            taken.insert(def.id.clone());
+        } else if def.is_datatype() || def.is_resource() {
+            for el in def.snapshot.element.iter().filter(|e| def.is_struct_element(e)) {
+                taken.insert(def.get_container_type_name(el));
+            }
+            for el in def.snapshot.element.iter().filter(|e| e.is_choice_type()) {
+                taken.insert(def.get_choice_type_name(el));
+            }
+        }
+    }
+    taken
+}
+
+/// `name`, or `name` with a number appended if it's taken already.
+fn take_name(taken: &mut HashSet<String>, name: String) -> String {
+    let mut candidate = name.clone();
+    let mut suffix = 2;
+    while !taken.insert(candidate.clone()) {
+        candidate = format!("{}{}", name, suffix);
+        suffix += 1;
+    }
+    candidate
+}
+
+/// The `TypedExtension` trait and the helpers the generated structs and resources share.
+fn generate_extensions_mod(base: &ExtensionBase) -> Result<TokenStream> {
+    let url_field = get_root_fields(base.def).into_iter().find(|f| f.json_name == "url");
+    let extensible_url = url_field.as_ref().is_some_and(|f| f.el.is_extensible_primitive());
+    let extension_url = if extensible_url {
+        quote!(extension.url.value.as_deref())
+    } else {
+        quote!(Some(extension.url.as_str()))
+    };
+    let value_enum = ident(&base.value_enum)?;
+    let mut fields = Vec::new();
+    for field in get_root_fields(base.def) {
+        let name = ident(&field.name)?;
+        fields.push(match field.json_name.as_str() {
+            "extension" => quote!(extension),
+            "value" => quote!(value),
+            "url" if extensible_url => {
+                quote!(url: Element::new(url.parse().expect("extension URLs are valid uris")))
+            }
+            "url" => quote!(url: url.parse().expect("extension URLs are valid uris")),
+            _ => match field.cardinality {
+                schema::Cardinality::Repeated => quote!(#name: Vec::new()),
+                _ => quote!(#name: None),
+            },
+        });
+    }
+    Ok(quote! {
+        //! Typed structs for the extension definitions.
+
+        #[allow(unused_imports)]
+        use super::*;
+
+        /// An extension definition's content as a struct, converted from and to the generic `Extension`.
+        pub trait TypedExtension: Sized {
+            /// The `url` that identifies the extension: the definition's canonical URL, or the slice name of a part of a complex extension.
+            const URL: &'static str;
+            /// Reads an extension already known to have `URL`.
+            fn from_extension(extension: &Extension) -> Result<Self, crate::extension::ExtensionError>;
+            fn into_extension(self) -> Extension;
+        }
+
+        /// The first extension in `extensions` with `T`'s URL, read as a `T`.
+        pub fn get_extension<T: TypedExtension>(
+            extensions: &[Extension],
+        ) -> Result<Option<T>, crate::extension::ExtensionError> {
+            extensions
+                .iter()
+                .find(|e| extension_url(e) == Some(T::URL))
+                .map(T::from_extension)
+                .transpose()
+        }
+
+        /// Replaces the extensions in `extensions` with `T`'s URL by `value`, where the first of them was.
+        pub fn set_extension<T: TypedExtension>(extensions: &mut Vec<Extension>, value: T) {
+            let position = extensions
+                .iter()
+                .position(|e| extension_url(e) == Some(T::URL))
+                .unwrap_or(extensions.len());
+            extensions.retain(|e| extension_url(e) != Some(T::URL));
+            extensions.insert(position, value.into_extension());
+        }
+
+        fn extension_url(extension: &Extension) -> Option<&str> {
+            #extension_url
+        }
+
+        fn new_extension(url: &str, extension: Vec<Extension>, value: Option<#value_enum>) -> Extension {
+            Extension { #(#fields,)* }
+        }
+    })
+}
+
+/// The struct for the extension, or part of a complex extension, whose elements have ids starting
+/// with `prefix`: a field per sub-extension, and `value` if it allows one. Sub-extensions with
+/// sub-extensions of their own get nested structs.
+#[allow(clippy::too_many_arguments)]
+fn generate_extension_struct(
+    base: &ExtensionBase,
+    def: &schema::StructureDefinition,
+    prefix: &str,
+    el: Option<&schema::ElementDefinition>,
+    typename: &str,
+    url: &str,
+    taken: &mut HashSet<String>,
+) -> Result<TokenStream> {
+    use schema::Cardinality;
+
+    let value = def
+        .get_extension_value(prefix)
+        .map(|value_el| (value_el.get_cardinality(), base.get_variants(value_el)))
+        .filter(|(_, variants)| !variants.is_empty());
+    let mut slices = Vec::new();
+    for slice_el in def.get_extension_slices(prefix) {
+        let cardinality = slice_el.get_cardinality();
+        if cardinality == Cardinality::Prohibited {
+            continue;
+        }
+        let profile = slice_el
+            .r#type
+            .first()
+            .and_then(|t| t.profile.as_ref())
+            .and_then(|p| p.first())
+            .filter(|p| base.names.contains_key(p.as_str()));
+        let (kind, url) = if let Some(profile) = profile {
+            (
+                ExtensionSliceKind::Profile(base.names[profile.as_str()].clone()),
+                profile.clone(),
+            )
+        } else {
+            let url = def
+                .get_extension_url(&slice_el.id)
+                .map(str::to_string)
+                .unwrap_or_else(|| slice_el.slice_name.clone().unwrap_or_default());
+            if !def.get_extension_slices(&slice_el.id).is_empty() {
+                let nested = take_name(taken, format!("{}{}", typename, slice_el.get_slice_type_name()));
+                (ExtensionSliceKind::Nested(nested), url)
+            } else {
+                match def.get_extension_value(&slice_el.id) {
+                    Some(value_el) if !base.get_variants(value_el).is_empty() => {
+                        (ExtensionSliceKind::Value(base.get_variants(value_el)), url)
+                    }
+                    _ => continue,
+                }
+            }
+        };
+        slices.push(ExtensionSlice {
+            el: slice_el,
+            name: slice_el.get_slice_field_name(),
+            url,
+            cardinality,
+            kind,
+        });
+    }
+
+    // Values of more than one type get an enum of their own.
+    let value_enum = format!("{}Value", typename);
+    let slice_enum = |slice: &ExtensionSlice| format!("{}{}", typename, slice.el.get_slice_type_name());
+    let value_type = |variants: &[&ExtensionValueVariant], enum_name: String| match variants {
+        [variant] => rust_type(&variant.type_name),
+        _ => rust_type(&enum_name),
+    };
+
+    let mut fields = Vec::new();
+    let mut locals = Vec::new();
+    let mut reads = Vec::new();
+    let mut inits = Vec::new();
+    let mut writes = Vec::new();
+    for slice in &slices {
+        let name = ident(&slice.name)?;
+        let local = ident(&format!("field_{}", slice.name.trim_start_matches("r#")))?;
+        let doc = docs::element_doc(def, slice.el);
+        let type_name = match slice.kind {
+            ExtensionSliceKind::Value(ref variants) => value_type(variants, slice_enum(slice))?,
+            ExtensionSliceKind::Nested(ref name) | ExtensionSliceKind::Profile(ref name) => rust_type(name)?,
+        };
+        fields.push(match slice.cardinality {
+            Cardinality::Repeated => quote!(#doc pub #name: Vec<#type_name>),
+            Cardinality::Optional => quote!(#doc pub #name: Option<#type_name>),
+            _ => quote!(#doc pub #name: #type_name),
+        });
+
+        locals.push(match slice.cardinality {
+            Cardinality::Repeated => quote!(let mut #local = Vec::new();),
+            _ => quote!(let mut #local = None;),
+        });
+        let read = match slice.kind {
+            ExtensionSliceKind::Value(ref variants) => {
+                let element = format!("{}.value[x]", slice.el.id);
+                read_extension_value(base, variants, &slice_enum(slice), &def.url, &element, false)?
+            }
+            ExtensionSliceKind::Nested(ref name) | ExtensionSliceKind::Profile(ref name) => {
+                let name = ident(name)?;
+                quote!(#name::from_extension(extension)?)
+            }
+        };
+        let slice_url = &slice.url;
+        reads.push(match slice.cardinality {
+            Cardinality::Repeated => quote!(Some(#slice_url) => #local.push(#read),),
+            _ => quote!(Some(#slice_url) => #local = Some(#read),),
+        });
+        inits.push(match slice.cardinality {
+            Cardinality::Required => {
+                let (url, element) = (&def.url, &slice.el.id);
+                quote! {
+                    #name: #local.ok_or(crate::extension::ExtensionError { url: #url, element: #element })?,
+                }
+            }
+            _ => quote!(#name: #local,),
+        });
+
+        let write = match slice.kind {
+            ExtensionSliceKind::Value(ref variants) => {
+                let value = write_extension_value(base, variants, &slice_enum(slice), quote!(value))?;
+                quote!(new_extension(#slice_url, Vec::new(), Some(#value)))
+            }
+            ExtensionSliceKind::Nested(_) | ExtensionSliceKind::Profile(_) => quote!(value.into_extension()),
+        };
+        writes.push(match slice.cardinality {
+            Cardinality::Repeated => quote! {
+                for value in self.#name {
+                    extension.push(#write);
+                }
+            },
+            Cardinality::Optional => quote! {
+                if let Some(value) = self.#name {
+                    extension.push(#write);
+                }
+            },
+            _ => quote! {
+                {
+                    let value = self.#name;
+                    extension.push(#write);
+                }
+            },
+        });
+    }
+    if let Some((cardinality, ref variants)) = value {
+        let type_name = value_type(variants, value_enum.clone())?;
+        fields.push(match cardinality {
+            Cardinality::Required => quote!(pub value: #type_name),
+            _ => quote!(pub value: Option<#type_name>),
+        });
+        let element = format!("{}.value[x]", prefix);
+        let optional = cardinality != Cardinality::Required;
+        let read = read_extension_value(base, variants, &value_enum, &def.url, &element, optional)?;
+        inits.push(quote!(value: #read,));
+    }
+
+    let mut doc = TokenStream::new();
+    if let Some(definition) = el.and_then(|el| el.definition.as_ref()) {
+        doc.extend(doc_comment(definition));
+        doc.extend(quote!(#[doc = ""]));
+    }
+    let url_doc = format!(" Extension `{}`.", url);
+    let name = ident(typename)?;
+    let param = if slices.is_empty() && value.is_none() {
+        quote!(_extension)
+    } else {
+        quote!(extension)
+    };
+    let read_slices = (!slices.is_empty()).then(|| {
+        quote! {
+            for extension in &extension.extension {
+                match extension_url(extension) {
+                    #(#reads)*
+                    _ => {}
+                }
+            }
+        }
+    });
+    let allow_push = slices
+        .first()
+        .is_some_and(|s| s.cardinality == Cardinality::Required)
+        .then(|| quote!(#[allow(clippy::vec_init_then_push)]));
+    let (init_extension, extension) = if slices.is_empty() {
+        (None, quote!(Vec::new()))
+    } else {
+        (Some(quote!(let mut extension = Vec::new();)), quote!(extension))
+    };
+    let value_expr = match value {
+        Some((Cardinality::Required, ref variants)) => {
+            let value = write_extension_value(base, variants, &value_enum, quote!(self.value))?;
+            quote!(Some(#value))
+        }
+        Some((_, ref variants)) => match variants.as_slice() {
+            [variant] if !variant.boxed => {
+                let (base_enum, variant) = (ident(&base.value_enum)?, ident(&variant.variant)?);
+                quote!(self.value.map(#base_enum::#variant))
+            }
+            _ => {
+                let value = write_extension_value(base, variants, &value_enum, quote!(value))?;
+                quote!(self.value.map(|value| #value))
+            }
+        },
+        None => quote!(None),
+    };
+
+    let mut tokens = quote! {
+        #doc
+        #[doc = #url_doc]
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct #name {
+            #(#fields,)*
+        }
+
+        impl TypedExtension for #name {
+            const URL: &'static str = #url;
+
+            fn from_extension(#param: &Extension) -> Result<Self, crate::extension::ExtensionError> {
+                #(#locals)*
+                #read_slices
+                Ok(#name {
+                    #(#inits)*
+                })
+            }
+
+            #allow_push
+            fn into_extension(self) -> Extension {
+                #init_extension
+                #(#writes)*
+                new_extension(Self::URL, #extension, #value_expr)
+            }
+        }
+    };
+
+    if let Some((_, ref variants)) = value {
+        if variants.len() > 1 {
+            tokens.extend(generate_extension_value_enum(&value_enum, variants)?);
+        }
+    }
+    for slice in &slices {
+        match slice.kind {
+            ExtensionSliceKind::Value(ref variants) if variants.len() > 1 => {
+                tokens.extend(generate_extension_value_enum(&slice_enum(slice), variants)?);
+            }
+            ExtensionSliceKind::Nested(ref name) => {
+                tokens.extend(generate_extension_struct(
+                    base,
+                    def,
+                    &slice.el.id,
+                    Some(slice.el),
+                    name,
+                    &slice.url,
+                    taken,
+                )?);
+            }
+            _ => {}
+        }
+    }
+    Ok(tokens)
+}
+
+fn generate_extension_value_enum(enum_name: &str, variants: &[&ExtensionValueVariant]) -> Result<TokenStream> {
+    let name = ident(enum_name)?;
+    let mut enum_variants = Vec::new();
+    for variant in variants {
+        let (variant, type_name) = (ident(&variant.variant)?, rust_type(&variant.type_name)?);
+        enum_variants.push(quote!(#variant(#type_name)));
+    }
+    Ok(quote! {
+        #[derive(Debug, Clone, PartialEq)]
+        #[allow(clippy::large_enum_variant)]
+        pub enum #name {
+            #(#enum_variants,)*
+        }
+    })
+}
+
+/// Expression reading `extension.value` as the struct's value type: the one variant's type, or
+/// `enum_name` for several. A missing or unexpected value returns an error naming the definition
+/// `url` and `element`, unless `optional` lets it be missing.
+fn read_extension_value(
+    base: &ExtensionBase,
+    variants: &[&ExtensionValueVariant],
+    enum_name: &str,
+    url: &str,
+    element: &str,
+    optional: bool,
+) -> Result<TokenStream> {
+    let base_enum = ident(&base.value_enum)?;
+    let enum_name = ident(enum_name)?;
+    let mut arms = Vec::new();
+    for variant in variants {
+        let value = if variant.boxed {
+            quote!((**value).clone())
+        } else {
+            quote!(value.clone())
+        };
+        let variant = ident(&variant.variant)?;
+        let value = match variants {
+            [_] => value,
+            _ => quote!(#enum_name::#variant(#value)),
+        };
+        let value = if optional { quote!(Some(#value)) } else { value };
+        arms.push(quote!(Some(#base_enum::#variant(ref value)) => #value,));
+    }
+    if optional {
+        arms.push(quote!(None => None,));
+    }
+    // With every variant handled and none missing, nothing is unexpected.
+    if !(optional && variants.len() == base.variants.len()) {
+        arms.push(quote! {
+            _ => return Err(crate::extension::ExtensionError { url: #url, element: #element }),
+        });
+    }
+    Ok(quote! {
+        match extension.value {
+            #(#arms)*
+        }
+    })
+}
+
+/// Expression converting `value`, of the struct's value type, into the base `value[x]` enum.
+fn write_extension_value(
+    base: &ExtensionBase,
+    variants: &[&ExtensionValueVariant],
+    enum_name: &str,
+    value: TokenStream,
+) -> Result<TokenStream> {
+    let base_enum = ident(&base.value_enum)?;
+    let wrap = |variant: &ExtensionValueVariant, value: &TokenStream| -> Result<TokenStream> {
+        let name = ident(&variant.variant)?;
+        Ok(if variant.boxed {
+            quote!(#base_enum::#name(Box::new(#value)))
+        } else {
+            quote!(#base_enum::#name(#value))
+        })
+    };
+    match variants {
+        [variant] => wrap(variant, &value),
+        _ => {
+            let enum_name = ident(enum_name)?;
+            let mut arms = Vec::new();
+            for variant in variants {
+                let wrapped = wrap(variant, &quote!(value))?;
+                let variant = ident(&variant.variant)?;
+                arms.push(quote!(#enum_name::#variant(value) => #wrapped,));
+            }
+            Ok(quote! {
+                match #value {
+                    #(#arms)*
+                }
+            })
+        }
+    }
+}
+
+/// A resource profile and the core definition of the resource it constrains.
+struct ResourceProfile<'a> {
+    def: &'a schema::StructureDefinition,
+    base: &'a schema::StructureDefinition,
+    name: String,
+}
+
+/// Generates the `profiles` module into `dir`: a wrapper type per resource profile, which checks
+/// that a resource conforms before wrapping it, and a registry of the profiles by canonical URL.
+fn generate_profiles(
+    config: &Config,
+    resources: &schema::Schema,
+    dir: &Path,
+    outputs: &mut BTreeMap<PathBuf, TokenStream>,
+) -> Result<()> {
+    let mut taken = get_reserved_type_names(resources);
+    taken.extend(["Profile", "ProfileInfo"].iter().map(|n| n.to_string()));
+    let mut profiles = Vec::new();
+    for def in resources
+        .structures_definitions
+        .iter()
+        .filter(|d| d.is_resource_profile())
+    {
+        let Some(base) = resources.get_type_definition(&def.r#type).filter(|d| d.is_resource()) else {
+            continue;
+        };
+        let name = take_name(&mut taken, def.get_structure_type_name());
+        profiles.push(ResourceProfile { def, base, name });
+    }
+    // The registry looks profiles up by binary search.
+    profiles.sort_by(|a, b| a.def.url.cmp(&b.def.url));
+
+    let mut module = generate_profiles_mod(config, &profiles)?;
+    for profile in &profiles {
+        let modname = profile.name.to_case(Case::Snake);
+        let mut file = quote! {
+            #[allow(unused_imports)]
+            use super::*;
+        };
+        file.extend(generate_profile(resources, profile)?);
+        outputs.insert(dir.join(format!("{}.rs", modname)), file);
+        let cfg = get_feature_cfg(config, profile.base);
+        let modname = ident(&modname)?;
+        module.extend(quote! {
+            #cfg
+            mod #modname;
+            #cfg
+            pub use #modname::*;
+        });
+    }
+    outputs.insert(dir.join("mod.rs"), module);
+    Ok(())
+}
+
+fn generate_profiles_mod(config: &Config, profiles: &[ResourceProfile]) -> Result<TokenStream> {
+    let mut entries = Vec::new();
+    for profile in profiles {
+        let name = ident(&profile.name)?;
+        let resource_type = ident(&profile.base.get_structure_type_name())?;
+        let cfg = get_feature_cfg(config, profile.base);
+        let element = &profile.base.r#type;
+        // The `_` arm is unreachable when the profile's resource is the only one enabled.
+        entries.push(quote! {
+            #cfg
+            ProfileInfo {
+                url: #name::URL,
+                resource_type: ResourceType::#resource_type,
+                check: |resource| match resource {
+                    Resource::#resource_type(resource) => #name::check(resource),
+                    #[allow(unreachable_patterns)]
+                    _ => Err(ProfileError {
+                        profile: #name::URL,
+                        element: #element,
+                        kind: ProfileErrorKind::ResourceType,
+                    }),
+                },
+            }
+        });
+    }
+    // The imports are unused when no profiled resource's feature is on.
+    Ok(quote! {
+        #[allow(unused_imports)]
+        use super::*;
+        #[allow(unused_imports)]
+        use crate::profile::{ProfileError, ProfileErrorKind};
+
+        /// A resource known to conform to a profile.
+        pub trait Profile: Sized {
+            /// The resource the profile constrains.
+            type Resource;
+            /// Canonical URL of the profile.
+            const URL: &'static str;
+
+            /// Checks that `resource` conforms to the profile.
+            fn check(resource: &Self::Resource) -> Result<(), ProfileError>;
+
+            /// Wraps `resource` if it conforms to the profile.
+            fn from_resource(resource: Self::Resource) -> Result<Self, ProfileError>;
+
+            fn into_resource(self) -> Self::Resource;
+        }
+
+        /// A profile in [`PROFILES`].
+        #[derive(Debug, Clone, Copy)]
+        pub struct ProfileInfo {
+            /// Canonical URL of the profile.
+            pub url: &'static str,
+            /// The resource the profile constrains.
+            pub resource_type: ResourceType,
+            /// Checks that a resource conforms to the profile, failing for other resource types.
+            pub check: fn(&Resource) -> Result<(), ProfileError>,
+        }
+
+        /// The profiles generated for this release, by canonical URL.
+        pub static PROFILES: &[ProfileInfo] = &[#(#entries),*];
+
+        /// The profile with the canonical `url`, which may end in a `|version`.
+        pub fn get_profile(url: &str) -> Option<&'static ProfileInfo> {
+            let url = url.split('|').next().unwrap_or(url);
+            let index = PROFILES.binary_search_by(|p| p.url.cmp(url)).ok()?;
+            Some(&PROFILES[index])
+        }
+
+        /// Checks `resource` against the profiles in its `meta.profile`, skipping those not in [`PROFILES`].
+        pub fn check_claimed_profiles(resource: &Resource) -> Result<(), ProfileError> {
+            let Some(meta) = traits::Resource::meta(resource) else {
+                return Ok(());
+            };
+            for url in meta.profile.iter().filter_map(|p| p.value()) {
+                if let Some(profile) = get_profile(url.as_ref()) {
+                    (profile.check)(resource)?;
+                }
+            }
+            Ok(())
+        }
+    })
+}
+
+/// The wrapper type for one profile: `Profile` with its conformance checks, accessors for the
+/// elements the profile narrows, and conversions from and to the base resource.
+fn generate_profile(resources: &schema::Schema, profile: &ResourceProfile) -> Result<TokenStream> {
+    let name = ident(&profile.name)?;
+    let base = profile.base;
+    let base_name = ident(&base.get_structure_type_name())?;
+
+    let mut checks = Vec::new();
+    let mut accessors = Vec::new();
+    for pel in profile.def.snapshot.element.iter().skip(1) {
+        if pel.id.contains(':') {
+            checks.extend(get_profile_slice_check(resources, base, pel)?);
+            continue;
+        }
+        let Some(path) = resolve_profile_field(resources, base, &pel.id)? else {
+            continue;
+        };
+        for check in get_profile_checks(resources, &path, pel)? {
+            checks.push(path.wrap(check));
+        }
+        if path.top_level {
+            accessors.extend(get_profile_accessor(base, path.el, pel)?);
+        }
+    }
+
+    let doc = format!(
+        " A `{}` conforming to the profile `{}`.",
+        base.get_structure_type_name(),
+        profile.def.url
+    );
+    let url = &profile.def.url;
+    let param = if checks.is_empty() {
+        quote!(_resource)
+    } else {
+        quote!(resource)
+    };
+    let accessors = (!accessors.is_empty()).then(|| {
+        quote! {
+            impl #name {
+                #(#accessors)*
+            }
+        }
+    });
+    Ok(quote! {
+        #[doc = #doc]
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct #name(#base_name);
+
+        impl Profile for #name {
+            type Resource = #base_name;
+            const URL: &'static str = #url;
+
+            fn check(#param: &#base_name) -> Result<(), ProfileError> {
+                #(#checks)*
+                Ok(())
+            }
+
+            fn from_resource(resource: #base_name) -> Result<Self, ProfileError> {
+                Self::check(&resource)?;
+                Ok(#name(resource))
+            }
+
+            fn into_resource(self) -> #base_name {
+                self.0
+            }
+        }
+
+        #accessors
+
+        impl std::ops::Deref for #name {
+            type Target = #base_name;
+            fn deref(&self) -> &#base_name {
+                &self.0
+            }
+        }
+
+        impl AsRef<#base_name> for #name {
+            fn as_ref(&self) -> &#base_name {
+                &self.0
+            }
+        }
+
+        impl TryFrom<#base_name> for #name {
+            type Error = ProfileError;
+            fn try_from(resource: #base_name) -> Result<Self, ProfileError> {
+                Self::from_resource(resource)
+            }
+        }
+
+        impl From<#name> for #base_name {
+            fn from(profile: #name) -> Self {
+                profile.0
+            }
+        }
+
+        impl serde::Serialize for #name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                serde::Serialize::serialize(&self.0, serializer)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for #name {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                let resource = <#base_name as serde::Deserialize>::deserialize(deserializer)?;
+                Self::from_resource(resource).map_err(serde::de::Error::custom)
+            }
+        }
+    })
+}
+
+/// Where the generated types hold the field of a profiled element.
+struct ProfileFieldPath<'a> {
+    /// The definition and element generating the field, which is a data type's for elements
+    /// inside a data type, e.g. `Identifier.system` for `Patient.identifier.system`.
+    def: &'a schema::StructureDefinition,
+    el: &'a schema::ElementDefinition,
+    /// `if let`s and `for`s binding the values of the element's ancestors from `resource`.
+    blocks: Vec<ProfileBlock>,
+    /// Expression for the struct holding the field.
+    parent: TokenStream,
+    /// Whether the field is the resource's own rather than a struct's in it.
+    top_level: bool,
+}
+
+/// Binds `var` to the value, or each of the values, of an optional or repeated ancestor `field`.
+struct ProfileBlock {
+    var: proc_macro2::Ident,
+    field: TokenStream,
+    repeated: bool,
+}
+
+impl ProfileFieldPath<'_> {
+    fn field(&self) -> Result<TokenStream> {
+        let parent = &self.parent;
+        let name = ident(&self.def.get_element_field_name(self.el))?;
+        Ok(quote!(#parent.#name))
+    }
+
+    /// `body` run for every parent of the field.
+    fn wrap(&self, body: TokenStream) -> TokenStream {
+        self.blocks.iter().rev().fold(body, |body, block| {
+            let (var, field) = (&block.var, &block.field);
+            if block.repeated {
+                quote! {
+                    for #var in #field.iter() {
+                        #body
+                    }
+                }
+            } else {
+                quote! {
+                    if let Some(#var) = &#field {
+                        #body
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Follows a profile element's id through the base resource's structs and the data types they
+/// hold. Elements inside choice types, primitives and content references aren't followed.
+fn resolve_profile_field<'a>(
+    resources: &'a schema::Schema,
+    base: &'a schema::StructureDefinition,
+    id: &str,
+) -> Result<Option<ProfileFieldPath<'a>>> {
+    use schema::Cardinality;
+
+    let mut segments = id.split('.');
+    let Some(root) = segments.next() else {
+        return Ok(None);
+    };
+    let mut parent_id = root.to_string();
+    let segments: Vec<&str> = segments.collect();
+    let mut def = base;
+    let mut blocks = Vec::new();
+    let mut parent = quote!(resource);
+    for (i, segment) in segments.iter().enumerate() {
+        let Some(el) = def.get_element_by_id(&format!("{}.{}", parent_id, segment)) else {
+            return Ok(None);
+        };
+        if i + 1 == segments.len() {
+            return Ok(Some(ProfileFieldPath {
+                def,
+                el,
+                blocks,
+                parent,
+                top_level: i == 0,
+            }));
+        }
+        if el.is_choice_type() {
+            return Ok(None);
+        }
+        let name = ident(&def.get_element_field_name(el))?;
+        let field = quote!(#parent.#name);
+        match el.get_cardinality() {
+            Cardinality::Prohibited => return Ok(None),
+            Cardinality::Required => parent = field,
+            cardinality => {
+                let var = format_ident!("v{}", blocks.len());
+                parent = quote!(#var);
+                blocks.push(ProfileBlock {
+                    var,
+                    field,
+                    repeated: cardinality == Cardinality::Repeated,
+                });
+            }
+        }
+        if def.is_struct_element(el) {
+            parent_id = el.id.clone();
+        } else {
+            let Some(r#type) = el.r#type.first().filter(|_| el.r#type.len() == 1) else {
+                return Ok(None);
+            };
+            let Some(type_def) = resources.get_type_definition(&r#type.code).filter(|d| d.is_datatype()) else {
+                return Ok(None);
+            };
+            let Some(root) = type_def.snapshot.element.first() else {
+                return Ok(None);
+            };
+            def = type_def;
+            parent_id = root.id.clone();
+        }
+    }
+    Ok(None)
+}
+
+fn get_profile_error(element: &str, kind: &str) -> TokenStream {
+    let kind = format_ident!("{}", kind);
+    quote! {
+        return Err(ProfileError { profile: Self::URL, element: #element, kind: ProfileErrorKind::#kind });
+    }
+}
+
+/// Checks of the constraints a profile element adds to the base element: a higher `min`, a lower
+/// `max`, fixed and pattern values, and fewer types or reference targets.
+fn get_profile_checks(
+    resources: &schema::Schema,
+    path: &ProfileFieldPath,
+    pel: &schema::ElementDefinition,
+) -> Result<Vec<TokenStream>> {
+    use schema::{Cardinality, ElementMax};
+
+    let el = path.el;
+    let field = path.field()?;
+    let cardinality = el.get_cardinality();
+    let mut checks = Vec::new();
+    let min = pel.get_min();
+    if min > el.get_min() {
+        let missing = get_profile_error(&pel.id, "Missing");
+        match cardinality {
+            Cardinality::Optional => checks.push(quote!(if #field.is_none() { #missing })),
+            Cardinality::Repeated if min == 1 => checks.push(quote!(if #field.is_empty() { #missing })),
+            Cardinality::Repeated => {
+                let min = int(min);
+                checks.push(quote!(if #field.len() < #min { #missing }))
+            }
+            _ => {}
+        }
+    }
+    let max = pel.get_max();
+    if max < el.get_max() {
+        let too_many = get_profile_error(&pel.id, "TooMany");
+        match (cardinality, max) {
+            (Cardinality::Optional, _) => checks.push(quote!(if #field.is_some() { #too_many })),
+            (Cardinality::Repeated, ElementMax::Bounded(0)) => checks.push(quote!(if !#field.is_empty() { #too_many })),
+            (Cardinality::Repeated, ElementMax::Bounded(max)) => {
+                let max = int(max);
+                checks.push(quote!(if #field.len() > #max { #too_many }))
+            }
+            _ => {}
+        }
+    }
+    if cardinality == Cardinality::Prohibited || max == ElementMax::Bounded(0) {
+        return Ok(checks);
+    }
+
+    let mut value_checks = Vec::new();
+    if el.is_choice_type() {
+        let types = el.get_choice_types();
+        let allowed: Vec<&schema::ElementType> = types
+            .iter()
+            .copied()
+            .filter(|t| pel.r#type.iter().any(|p| p.code == t.code))
+            .collect();
+        if !allowed.is_empty() && allowed.len() < types.len() {
+            let enum_name = ident(&path.def.get_choice_type_name(el))?;
+            let mut patterns = Vec::new();
+            for r#type in allowed {
+                let variant = ident(&get_choice_variant_name(r#type))?;
+                patterns.push(quote!(#enum_name::#variant(_)));
+            }
+            let error = get_profile_error(&pel.id, "Type");
+            value_checks.push(quote!(if !matches!(value, #(#patterns)|*) { #error }));
+        }
+    } else {
+        let value = if el.is_extensible_primitive() {
+            quote!(&value.value)
+        } else {
+            quote!(value)
+        };
+        if let Some(fixed) = pel.fixed.as_ref().filter(|f| el.fixed.as_ref() != Some(*f)) {
+            let fixed = serde_json::to_string(&fixed.value)?;
+            let error = get_profile_error(&pel.id, "Fixed");
+            value_checks.push(quote!(if !crate::profile::matches_fixed(#value, #fixed) { #error }));
+        }
+        if let Some(pattern) = pel.pattern.as_ref().filter(|p| el.pattern.as_ref() != Some(*p)) {
+            let pattern = serde_json::to_string(&pattern.value)?;
+            let error = get_profile_error(&pel.id, "Pattern");
+            value_checks.push(quote!(if !crate::profile::matches_pattern(#value, #pattern) { #error }));
+        }
+        if let Some(targets) = get_narrowed_reference_targets(resources, el, pel) {
+            let targets = targets.iter().map(|t| ident(t)).collect::<Result<Vec<_>>>()?;
+            let error = get_profile_error(&pel.id, "ReferenceTarget");
+            value_checks.push(quote! {
+                if value.resource_type().is_some_and(|t| !matches!(t, #(ResourceType::#targets)|*)) {
+                    #error
+                }
+            });
+        }
+    }
+    if !value_checks.is_empty() {
+        checks.push(match cardinality {
+            Cardinality::Optional => quote! {
+                if let Some(value) = &#field {
+                    #(#value_checks)*
+                }
+            },
+            Cardinality::Repeated => quote! {
+                for value in #field.iter() {
+                    #(#value_checks)*
+                }
+            },
+            _ => quote! {
+                {
+                    let value = &#field;
+                    #(#value_checks)*
+                }
+            },
+        });
+    }
+    Ok(checks)
+}
+
+/// The resources a profile lets a typed reference point at, if fewer than the base element allows.
+fn get_narrowed_reference_targets(
+    resources: &schema::Schema,
+    el: &schema::ElementDefinition,
+    pel: &schema::ElementDefinition,
+) -> Option<Vec<String>> {
+    let r#type = el
+        .r#type
+        .first()
+        .filter(|t| el.r#type.len() == 1 && t.code == "Reference")?;
+    let base_targets = r#type
+        .get_reference_targets()
+        .filter(|t| !t.is_empty() && t.len() <= schema::MAX_REFERENCE_TARGETS)?;
+    let mut targets: Vec<String> = Vec::new();
+    for url in pel.r#type.iter().flat_map(|t| t.target_profile.iter().flatten()) {
+        // Targets may be profiles themselves, e.g. US Core Patient.
+        let target = resources.get_structure_definition(url)?.r#type.clone();
+        if !base_targets.contains(&target) {
+            return None;
+        }
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+    (!targets.is_empty() && targets.len() < base_targets.len()).then_some(targets)
+}
+
+/// A check that enough values of a sliced element match a slice fixing or patterning its value,
+/// e.g. the vital signs category among an observation's categories.
+fn get_profile_slice_check(
+    resources: &schema::Schema,
+    base: &schema::StructureDefinition,
+    pel: &schema::ElementDefinition,
+) -> Result<Option<TokenStream>> {
+    let Some((base_id, slice)) = pel.id.rsplit_once(':') else {
+        return Ok(None);
+    };
+    let min = pel.get_min();
+    if base_id.contains(':') || slice.contains('.') || min == 0 {
+        return Ok(None);
+    }
+    let (matcher, value) = match (&pel.fixed, &pel.pattern) {
+        (Some(fixed), _) => (quote!(matches_fixed), fixed),
+        (None, Some(pattern)) => (quote!(matches_pattern), pattern),
+        (None, None) => return Ok(None),
+    };
+    let Some(path) = resolve_profile_field(resources, base, base_id)? else {
+        return Ok(None);
+    };
+    if path.el.is_choice_type() || path.el.get_cardinality() != schema::Cardinality::Repeated {
+        return Ok(None);
+    }
+    let item = if path.el.is_extensible_primitive() {
+        quote!(&value.value)
+    } else {
+        quote!(value)
+    };
+    let value = serde_json::to_string(&value.value)?;
+    let predicate = quote!(|value| crate::profile::#matcher(#item, #value));
+    let field = path.field()?;
+    let condition = if min == 1 {
+        quote!(!#field.iter().any(#predicate))
+    } else {
+        let min = int(min);
+        quote!(#field.iter().filter(#predicate).count() < #min)
+    };
+    let missing = get_profile_error(&pel.id, "Missing");
+    Ok(Some(path.wrap(quote!(if #condition { #missing }))))
+}
+
+/// An accessor for a top-level element the profile narrows: one it requires returns the value
+/// rather than an `Option`, and a choice it restricts to one type returns that type.
+fn get_profile_accessor(
+    base: &schema::StructureDefinition,
+    el: &schema::ElementDefinition,
+    pel: &schema::ElementDefinition,
+) -> Result<Option<TokenStream>> {
+    use schema::Cardinality;
+
+    let name = ident(&base.get_element_field_name(el))?;
+    let required = pel.get_min() > 0;
+    if el.is_choice_type() {
+        let types = el.get_choice_types();
+        let allowed: Vec<&schema::ElementType> = types
+            .iter()
+            .copied()
+            .filter(|t| pel.r#type.iter().any(|p| p.code == t.code))
+            .collect();
+        let [r#type] = allowed[..] else {
+            return Ok(None);
+        };
+        if types.len() == 1 {
+            return Ok(None);
+        }
+        let doc = format!(
+            " `{}`, which the profile restricts to `{}`.",
+            el.path.replace("[x]", ""),
+            r#type.code
+        );
+        let enum_name = ident(&base.get_choice_type_name(el))?;
+        let variant = ident(&get_choice_variant_name(r#type))?;
+        let pattern = quote!(#enum_name::#variant(value));
+        let type_name = rust_type(&base.get_type_name(r#type))?;
+        let value = if r#type.boxed { quote!(&**value) } else { quote!(value) };
+        return Ok(match (el.get_cardinality(), required) {
+            (Cardinality::Optional, true) => Some(quote! {
+                #[doc = #doc]
+                pub fn #name(&self) -> &#type_name {
+                    match &self.0.#name {
+                        Some(#pattern) => #value,
+                        _ => unreachable!("checked by the profile"),
+                    }
+                }
+            }),
+            (Cardinality::Optional, false) => Some(quote! {
+                #[doc = #doc]
+                pub fn #name(&self) -> Option<&#type_name> {
+                    match &self.0.#name {
+                        Some(#pattern) => Some(#value),
+                        _ => None,
+                    }
+                }
+            }),
+            (Cardinality::Required, _) => Some(quote! {
+                #[doc = #doc]
+                pub fn #name(&self) -> &#type_name {
+                    match &self.0.#name {
+                        #pattern => #value,
+                        _ => unreachable!("checked by the profile"),
+                    }
+                }
+            }),
+            _ => None,
+        });
+    }
+    if !required || el.get_cardinality() != Cardinality::Optional {
+        return Ok(None);
+    }
+    let doc = format!(" `{}`, which the profile requires.", el.path);
+    let type_name = rust_type(&base.get_element_type_name(el))?;
+    let method = if el.boxed { quote!(as_deref) } else { quote!(as_ref) };
+    Ok(Some(quote! {
+        #[doc = #doc]
+        pub fn #name(&self) -> &#type_name {
+            self.0.#name.#method().expect("checked by the profile")
+        }
+    }))
+}
+
+/// An enum per value set bound to `code` elements. Each renders as its code and implements
+/// `ValueSetCode`, so non-required bindings can wrap it in `OpenCode`.
+fn generate_code_enums(code_enums: &[schema::CodeEnum]) -> Result<TokenStream> {
+    let mut tokens = quote! {
+        //! Codes of the value sets bound to `code` elements.
+
+        #[allow(unused_imports)]
+        use crate::primitives::*;
+    };
+    for code_enum in code_enums {
+        let mut seen = HashSet::new();
+        let mut variants = Vec::new();
+        for concept in &code_enum.concepts {
+            let base_name = concept.get_variant_name();
+            let mut name = base_name.clone();
+            let mut suffix = 2;
+            while !seen.insert(name.clone()) {
+                name = format!("{}{}", base_name, suffix);
+                suffix += 1;
+            }
+            variants.push((ident(&name)?, concept));
+        }
+        let name = ident(&code_enum.name)?;
+
+        let title = code_enum.title.as_ref().map(|title| {
+            let title = format!(" {}", title);
+            quote! {
+                #[doc = #title]
+                #[doc = ""]
+            }
+        });
+        let doc = format!(" Codes of `{}`.", code_enum.url);
+        let docs = variants.iter().map(|(_, concept)| {
+            doc_comment(
+                concept
+                    .definition
+                    .as_ref()
+                    .or(concept.display.as_ref())
+                    .unwrap_or(&concept.code),
+            )
+        });
+        let idents: Vec<_> = variants.iter().map(|(variant, _)| variant).collect();
+        let codes = variants.iter().map(|(_, concept)| &concept.code);
+        let systems = variants.iter().map(|(_, concept)| &concept.system);
+        let displays = variants.iter().map(|(_, concept)| match concept.display {
+            Some(ref display) => quote!(Some(#display)),
+            None => quote!(None),
+        });
+        // The same code from two systems reads as the first.
+        let mut codes_seen = HashSet::new();
+        let parsed: Vec<_> = variants
+            .iter()
+            .filter(|(_, concept)| codes_seen.insert(concept.code.as_str()))
+            .map(|(variant, concept)| {
+                let code = &concept.code;
+                quote!(#code => Ok(#name::#variant),)
+            })
+            .collect();
+        let type_name = &code_enum.name;
+        tokens.extend(quote! {
+            #title
+            #[doc = #doc]
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+            pub enum #name {
+                #(#docs #idents,)*
+            }
+
+            impl #name {
+                pub fn code(&self) -> &'static str {
+                    match self {
+                        #(#name::#idents => #codes,)*
+                    }
+                }
+
+                /// Canonical URL of the code system defining the code.
+                pub fn system(&self) -> &'static str {
+                    match self {
+                        #(#name::#idents => #systems,)*
+                    }
+                }
+
+                pub fn display(&self) -> Option<&'static str> {
+                    match self {
+                        #(#name::#idents => #displays,)*
+                    }
+                }
+            }
+
+            impl crate::code::ValueSetCode for #name {
+                fn code(&self) -> &'static str {
+                    #name::code(self)
+                }
+                fn system(&self) -> &'static str {
+                    #name::system(self)
+                }
+                fn display(&self) -> Option<&'static str> {
+                    #name::display(self)
+                }
+            }
+
+            impl std::fmt::Display for #name {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    f.write_str(self.code())
+                }
+            }
+
+            impl std::str::FromStr for #name {
+                type Err = PrimitiveError;
+                fn from_str(s: &str) -> Result<Self, Self::Err> {
+                    match s {
+                        #(#parsed)*
+                        _ => Err(PrimitiveError { type_name: #type_name, value: s.to_string() }),
+                    }
+                }
+            }
+
+            impl serde::Serialize for #name {
+                fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    serializer.serialize_str(self.code())
+                }
+            }
+
+            impl<'de> serde::Deserialize<'de> for #name {
+                fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    crate::serde_support::deserialize_from_str(deserializer)
+                }
+            }
+        });
+    }
+    Ok(tokens)
+}
+
+/// `TypedReference<T>`, the type of `Reference` fields, which records the resources the reference
+/// may point at in `T`: a resource, a tuple of up to `MAX_REFERENCE_TARGETS` of them, or `Resource`
+/// for any.
+fn generate_typed_reference(config: &Config, resources: &[&schema::StructureDefinition]) -> Result<TokenStream> {
+    let mut targets = Vec::new();
+    for def in resources {
+        let name = ident(&def.get_structure_type_name())?;
+        if config.output.features {
+            let doc = format!(
+                " Stands in for `{}` as a reference target while its feature is off.",
+                name
+            );
+            let feature = def.get_feature_name();
+            targets.push(quote! {
+                #[doc = #doc]
+                #[cfg(not(feature = #feature))]
+                pub enum #name {}
+            });
+        }
+        targets.push(quote! {
+            impl ReferenceTarget for #name {
+                fn allows(resource_type: ResourceType) -> bool {
+                    resource_type == ResourceType::#name
+                }
+            }
+        });
+    }
+    for arity in 2..=schema::MAX_REFERENCE_TARGETS {
+        let params: Vec<_> = (0..arity).map(|i| format_ident!("T{}", i)).collect();
+        targets.push(quote! {
+            impl<#(#params: ReferenceTarget),*> ReferenceTarget for (#(#params),*) {
+                fn allows(resource_type: ResourceType) -> bool {
+                    #(#params::allows(resource_type))||*
+                }
+            }
+        });
+    }
+
+    // `type` holds either a resource type or the canonical URL of its definition. The traits are
+    // written out rather than derived, which would require `T` to implement each of them.
+    Ok(quote! {
+        #[allow(unused_imports)]
+        use super::*;
+        #[allow(unused_imports)]
+        use crate::primitives::*;
+        use crate::reference::LiteralReference;
+
+        /// The resources a `TypedReference<T>` may point at: a resource such as `Patient`, a tuple of
+        /// them such as `(Patient, Group)`, or `Resource` for any.
+        pub trait ReferenceTarget {
+            fn allows(resource_type: ResourceType) -> bool;
+        }
+
+        impl ReferenceTarget for Resource {
+            fn allows(_: ResourceType) -> bool {
+                true
+            }
+        }
+
+        #(#targets)*
+
+        /// A `Reference` that may point at the resources `T`, e.g. `TypedReference<(Patient, Group)>`.
+        /// It dereferences to the `Reference` and reads and writes JSON as one.
+        pub struct TypedReference<T = Resource> {
+            reference: Reference,
+            target: std::marker::PhantomData<fn() -> T>,
+        }
+
+        impl<T> TypedReference<T> {
+            pub fn new(reference: Reference) -> Self {
+                TypedReference {
+                    reference,
+                    target: std::marker::PhantomData,
+                }
+            }
+
+            pub fn into_inner(self) -> Reference {
+                self.reference
+            }
+
+            pub fn is_empty(&self) -> bool {
+                self.reference.is_empty()
+            }
+
+            /// The `reference` string split into its parts, if it has one in a form FHIR defines.
+            pub fn literal(&self) -> Option<LiteralReference<'_>> {
+                self.reference
+                    .reference
+                    .as_ref()?
+                    .value()
+                    .and_then(|reference| LiteralReference::parse(reference))
+            }
+
+            #[doc(hidden)]
+            pub fn collect_invariant_issues(
+                &self,
+                value: &serde_json::Value,
+                resource: &serde_json::Value,
+                path: &str,
+                issues: &mut Vec<crate::invariant::InvariantIssue>,
+            ) {
+                self.reference.collect_invariant_issues(value, resource, path, issues)
+            }
+        }
+
+        impl<T: ReferenceTarget> TypedReference<T> {
+            /// The type of the referenced resource, taken from `type` or else the literal reference,
+            /// provided it is known and one `T` allows.
+            pub fn resource_type(&self) -> Option<ResourceType> {
+                let name = match self.reference.r#type.as_ref().and_then(|t| t.value()) {
+                    Some(uri) => uri.as_ref().rsplit('/').next()?,
+                    None => self.literal()?.resource_type()?,
+                };
+                name.parse().ok().filter(|resource_type| T::allows(*resource_type))
+            }
+        }
+
+        impl<T> From<Reference> for TypedReference<T> {
+            fn from(reference: Reference) -> Self {
+                TypedReference::new(reference)
+            }
+        }
+
+        impl<T> std::ops::Deref for TypedReference<T> {
+            type Target = Reference;
+            fn deref(&self) -> &Reference {
+                &self.reference
+            }
+        }
+
+        impl<T> std::ops::DerefMut for TypedReference<T> {
+            fn deref_mut(&mut self) -> &mut Reference {
+                &mut self.reference
+            }
+        }
+
+        impl<T> std::fmt::Debug for TypedReference<T> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                self.reference.fmt(f)
+            }
+        }
+
+        impl<T> Clone for TypedReference<T> {
+            fn clone(&self) -> Self {
+                TypedReference::new(self.reference.clone())
+            }
+        }
+
+        impl<T> PartialEq for TypedReference<T> {
+            fn eq(&self, other: &Self) -> bool {
+                self.reference == other.reference
+            }
+        }
+
+        impl<T> serde::Serialize for TypedReference<T> {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                self.reference.serialize(serializer)
+            }
+        }
+
+        impl<'de, T> serde::Deserialize<'de> for TypedReference<T> {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                Reference::deserialize(deserializer).map(TypedReference::new)
+            }
+        }
+    })
+}
+
+/// `Resource`, an enum with a variant per concrete resource that serde tags with `resourceType`,
+/// and the matching `ResourceType`. Variants are behind their resource's feature, while
+/// `ResourceType` names every resource so that references can name any. The enum also implements
+/// the traits every resource implements, such as `traits::Resource`.
+fn generate_resource_enum(
+    config: &Config,
+    resources: &[&schema::StructureDefinition],
+    shared_traits: &[&ResourceTrait],
+) -> Result<TokenStream> {
+    let names = resources
+        .iter()
+        .map(|d| ident(&d.get_structure_type_name()))
+        .collect::<Result<Vec<_>>>()?;
+    let cfgs: Vec<TokenStream> = resources.iter().map(|d| get_feature_cfg(config, d)).collect();
+    let types: Vec<&str> = resources.iter().map(|d| d.r#type.as_str()).collect();
+
+    let mut trait_impls = Vec::new();
+    for resource_trait in shared_traits {
+        let trait_name = ident(&resource_trait.name)?;
+        let mut accessors = Vec::new();
+        for accessor in &resource_trait.accessors {
+            let name = ident(&accessor.name)?;
+            let return_type = get_accessor_return_type(accessor, &rust_type(&accessor.type_name)?);
+            accessors.push(quote! {
+                fn #name(&self) -> #return_type {
+                    match self {
+                        #(
+                            #cfgs
+                            Resource::#names(resource) => traits::#trait_name::#name(resource),
+                        )*
+                    }
+                }
+            });
+        }
+        trait_impls.push(quote! {
+            impl traits::#trait_name for Resource {
+                #(#accessors)*
+            }
+        });
+    }
+
+    // `resourceType` may come after other properties, so deserializing buffers the object first.
+    Ok(quote! {
+        #[allow(unused_imports)]
+        use super::*;
+        #[allow(unused_imports)]
+        use crate::primitives::*;
+
+        /// Any resource, told apart in JSON by its `resourceType`. There is a variant for each
+        /// resource whose cargo feature is on.
+        #[derive(Debug, Clone, PartialEq)]
+        #[allow(clippy::large_enum_variant)]
+        pub enum Resource {
+            #(
+                #cfgs
+                #names(#names),
+            )*
+        }
+
+        impl Resource {
+            pub fn resource_type(&self) -> ResourceType {
+                match self {
+                    #(
+                        #cfgs
+                        Resource::#names(_) => ResourceType::#names,
+                    )*
+                }
+            }
+        }
+
+        impl Resource {
+            /// Evaluates the invariants over the resource and every value in it, returning the broken
+            /// ones and those that couldn't be evaluated. Fails if the resource can't be serialized to
+            /// the JSON they're evaluated over.
+            pub fn validate_invariants(&self) -> Result<Vec<crate::invariant::InvariantIssue>, serde_json::Error> {
+                match self {
+                    #(
+                        #cfgs
+                        Resource::#names(resource) => resource.validate_invariants(),
+                    )*
+                }
+            }
+
+            #[doc(hidden)]
+            pub fn collect_invariant_issues(
+                &self,
+                value: &serde_json::Value,
+                resource: &serde_json::Value,
+                path: &str,
+                issues: &mut Vec<crate::invariant::InvariantIssue>,
+            ) {
+                match self {
+                    #(
+                        #cfgs
+                        Resource::#names(inner) => inner.collect_invariant_issues(value, resource, path, issues),
+                    )*
+                }
+            }
+        }
+
+        #(
+            #cfgs
+            impl From<#names> for Resource {
+                fn from(resource: #names) -> Self {
+                    Resource::#names(resource)
+                }
+            }
+        )*
+
+        impl serde::Serialize for Resource {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                match self {
+                    #(
+                        #cfgs
+                        Resource::#names(resource) => resource.serialize(serializer),
+                    )*
+                }
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for Resource {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                use serde::de::Error;
+                let value = serde_json::Value::deserialize(deserializer)?;
+                let resource_type: ResourceType = value
+                    .get("resourceType")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| D::Error::missing_field("resourceType"))?
+                    .parse()
+                    .map_err(D::Error::custom)?;
+                match resource_type {
+                    #(
+                        #cfgs
+                        ResourceType::#names => #names::deserialize(value).map(Resource::#names),
+                    )*
+                    #[allow(unreachable_patterns)]
+                    _ => Err(serde_json::Error::custom(format!("the cargo feature for {} is off", resource_type))),
+                }
+                .map_err(D::Error::custom)
+            }
+        }
+
+        #(#trait_impls)*
+
+        /// The type of a resource, as written in its `resourceType`.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub enum ResourceType {
+            #(#names,)*
+        }
+
+        impl ResourceType {
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    #(ResourceType::#names => #types,)*
+                }
+            }
+        }
+
+        impl std::fmt::Display for ResourceType {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl std::str::FromStr for ResourceType {
+            type Err = PrimitiveError;
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    #(#types => Ok(ResourceType::#names),)*
+                    _ => Err(PrimitiveError { type_name: "ResourceType", value: s.to_string() }),
+                }
+            }
+        }
+    })
+}
+
+/// Convenience accessors on `Bundle` over its entries' resources.
+fn generate_bundle_impl() -> TokenStream {
+    quote! {
+        impl Bundle {
+            /// The resources of the entries that have one.
+            pub fn resources(&self) -> impl Iterator<Item = &Resource> {
+                self.entry.iter().filter_map(|entry| entry.resource.as_ref())
+            }
+        }
+    }
+}
+
+/// A trait generated for an abstract resource or interface, e.g. `DomainResource`.
+struct ResourceTrait<'a> {
+    def: &'a schema::StructureDefinition,
+    name: String,
+    supertrait: Option<String>,
+    accessors: Vec<Accessor<'a>>,
+}
+
+/// A getter for an element the trait's definition adds to its supertrait's.
+struct Accessor<'a> {
+    el: &'a schema::ElementDefinition,
+    name: String,
+    type_name: String,
+    /// The widest cardinality among the trait and its implementors, so that each of them can
+    /// return its own field.
+    cardinality: schema::Cardinality,
+}
+
+/// URLs of the abstract resources and interfaces `def` derives from or implements, transitively.
+fn get_trait_urls<'a>(resources: &'a schema::Schema, def: &'a schema::StructureDefinition) -> Vec<&'a str> {
+    let mut urls: Vec<&str> = Vec::new();
+    let mut pending: Vec<&str> = def.base_definition.iter().map(String::as_str).collect();
+    pending.extend(def.get_implements());
+    while let Some(url) = pending.pop() {
+        let Some(base) = resources.get_structure_definition(url) else {
+            continue;
+        };
+        if !base.is_abstract_resource() || urls.contains(&url) {
+            continue;
+        }
+        urls.push(url);
+        pending.extend(base.base_definition.as_deref());
+        pending.extend(base.get_implements());
+    }
+    urls
+}
+
+fn get_root_fields(def: &schema::StructureDefinition) -> Vec<Field<'_>> {
+    match def.snapshot.element.first() {
+        Some(root) => def
+            .get_direct_children(root)
+            .into_iter()
+            .filter_map(|el| Field::new(def, el))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Works out each abstract resource's accessors. Elements inherited from a supertrait are left to
+/// it, and choice and backbone elements are skipped since every resource has its own type for them.
+/// So are elements that some implementor gives a different type.
+fn resolve_traits(resources: &schema::Schema) -> Vec<ResourceTrait<'_>> {
+    use schema::Cardinality;
+
+    fn rank(cardinality: Cardinality) -> u8 {
+        match cardinality {
+            Cardinality::Prohibited | Cardinality::Required => 0,
+            Cardinality::Optional => 1,
+            Cardinality::Repeated => 2,
+        }
+    }
+
+    let mut traits = Vec::new();
+    for def in resources
+        .structures_definitions
+        .iter()
+        .filter(|d| d.is_abstract_resource())
+    {
+        let ancestor_urls = get_trait_urls(resources, def);
+        let inherited: Vec<String> = ancestor_urls
+            .iter()
+            .filter_map(|url| resources.get_structure_definition(url))
+            .flat_map(|ancestor| get_root_fields(ancestor).into_iter().map(|f| f.name))
+            .collect();
+        let implementors: Vec<Vec<Field>> = resources
+            .structures_definitions
+            .iter()
+            .filter(|r| r.is_resource() && get_trait_urls(resources, r).contains(&def.url.as_str()))
+            .map(get_root_fields)
+            .collect();
+
+        let mut accessors = Vec::new();
+        'fields: for field in get_root_fields(def) {
+            if inherited.contains(&field.name) || field.el.is_choice_type() || field.el.is_container() {
+                continue;
+            }
+            let mut cardinality = field.cardinality;
+            for fields in &implementors {
+                let implemented = match fields.iter().find(|f| f.name == field.name) {
+                    Some(f) if f.type_name != field.type_name => continue 'fields,
+                    Some(f) => f.cardinality,
+                    None => Cardinality::Optional,
+                };
+                if rank(implemented) > rank(cardinality) {
+                    cardinality = implemented;
+                }
+            }
+            accessors.push(Accessor {
+                el: field.el,
+                name: field.name,
+                type_name: field.type_name,
+                cardinality,
+            });
+        }
+
+        let supertrait = def
+            .base_definition
+            .as_deref()
+            .and_then(|url| resources.get_structure_definition(url))
+            .filter(|base| base.is_abstract_resource())
+            .map(|base| base.get_structure_type_name());
+        traits.push(ResourceTrait {
+            def,
+            name: def.get_structure_type_name(),
+            supertrait,
+            accessors,
+        });
+    }
+    traits
+}
+
+fn get_accessor_return_type(accessor: &Accessor, type_name: &syn::Type) -> TokenStream {
+    match accessor.cardinality {
+        schema::Cardinality::Repeated => quote!(&[#type_name]),
+        schema::Cardinality::Optional => quote!(Option<&#type_name>),
+        _ => quote!(&#type_name),
+    }
+}
+
+fn generate_traits(traits: &[ResourceTrait]) -> Result<TokenStream> {
+    let mut tokens = quote! {
+        #[allow(unused_imports)]
+        use super::*;
+        #[allow(unused_imports)]
+        use crate::primitives::*;
+    };
+    for resource_trait in traits {
+        let doc = format!(
+            " Elements of `{}`, implemented by every resource derived from or implementing it.",
+            resource_trait.def.id
+        );
+        let name = ident(&resource_trait.name)?;
+        let supertrait = match resource_trait.supertrait {
+            Some(ref supertrait) => {
+                let supertrait = ident(supertrait)?;
+                Some(quote!(: #supertrait))
+            }
+            None => None,
+        };
+        let mut accessors = Vec::new();
+        for accessor in &resource_trait.accessors {
+            let doc = docs::element_doc(resource_trait.def, accessor.el);
+            // The traits shadow any type of the same name, e.g. `Resource` for `contained`.
+            let type_name = if traits.iter().any(|t| t.name == accessor.type_name) {
+                rust_type(&format!("super::{}", accessor.type_name))?
+            } else {
+                rust_type(&accessor.type_name)?
+            };
+            let accessor_name = ident(&accessor.name)?;
+            let return_type = get_accessor_return_type(accessor, &type_name);
+            accessors.push(quote! {
+                #doc
+                fn #accessor_name(&self) -> #return_type;
+            });
+        }
+        tokens.extend(quote! {
+            #[doc = #doc]
+            pub trait #name #supertrait {
+                #(#accessors)*
+            }
+        });
+    }
+    Ok(tokens)
+}
+
+fn generate_trait_impl(def: &schema::StructureDefinition, resource_trait: &ResourceTrait) -> Result<TokenStream> {
+    use schema::Cardinality;
+
+    let fields = get_root_fields(def);
+    let mut accessors = Vec::new();
+    for accessor in &resource_trait.accessors {
+        let name = ident(&accessor.name)?;
+        let field = fields.iter().find(|f| f.name == accessor.name);
+        let body = match (accessor.cardinality, field.map(|f| f.cardinality)) {
+            (Cardinality::Repeated, None) => quote!(&[]),
+            (_, None) => quote!(None),
+            (Cardinality::Repeated, Some(Cardinality::Optional)) => quote!(self.#name.as_slice()),
+            (Cardinality::Repeated, Some(Cardinality::Required)) => quote!(std::slice::from_ref(&self.#name)),
+            (Cardinality::Optional, Some(Cardinality::Optional)) => quote!(self.#name.as_ref()),
+            (Cardinality::Optional, Some(Cardinality::Required)) => quote!(Some(&self.#name)),
+            _ => quote!(&self.#name),
+        };
+        let return_type = get_accessor_return_type(accessor, &rust_type(&accessor.type_name)?);
+        accessors.push(quote! {
+            fn #name(&self) -> #return_type {
+                #body
+            }
+        });
+    }
+    let trait_name = ident(&resource_trait.name)?;
+    let typename = ident(&def.get_structure_type_name())?;
+    Ok(quote! {
+        impl traits::#trait_name for #typename {
+            #(#accessors)*
+        }
+    })
+}
+
+/// A field of a generated struct and how it maps onto FHIR JSON.
+struct Field<'a> {
+    el: &'a schema::ElementDefinition,
+    name: String,
+    json_name: String,
+    /// The element's type wrapped in `Option`/`Vec` by cardinality.
+    field_type: String,
+    /// The element's type without `Option`/`Vec`, e.g. `Element<Date>`, `ObservationValue` or a
+    /// boxed `Box<Identifier>`.
+    type_name: String,
+    cardinality: schema::Cardinality,
+}
+
+impl<'a> Field<'a> {
+    fn new(def: &schema::StructureDefinition, el: &'a schema::ElementDefinition) -> Option<Self> {
+        Some(Field {
+            el,
+            name: def.get_element_field_name(el),
+            json_name: el.get_json_name(),
+            field_type: def.get_element_field_type(el)?,
+            type_name: def.get_element_value_type_name(el),
+            cardinality: el.get_cardinality(),
+        })
+    }
+
+    /// Whether the field's values have an `is_empty()`, see [`type_has_is_empty`].
+    fn has_is_empty(&self) -> bool {
+        self.el.is_choice_type()
+            || self.el.content_reference.is_some()
+            || self.el.is_container()
+            || self.el.r#type.first().is_some_and(type_has_is_empty)
+    }
+
+    /// Expression for whether the field holds nothing worth writing to JSON, or `None` if it always
+    /// holds something.
+    fn is_empty_expr(&self) -> Result<Option<TokenStream>> {
+        use schema::Cardinality;
+
+        let name = ident(&self.name)?;
+        Ok(match (self.cardinality, self.has_is_empty()) {
+            (Cardinality::Optional, true) => Some(quote!(self.#name.as_ref().is_none_or(|v| v.is_empty()))),
+            (Cardinality::Repeated, true) => Some(quote!(self.#name.iter().all(|v| v.is_empty()))),
+            (_, true) => Some(quote!(self.#name.is_empty())),
+            (Cardinality::Optional, false) => Some(quote!(self.#name.is_none())),
+            (Cardinality::Repeated, false) => Some(quote!(self.#name.is_empty())),
+            (_, false) => None,
+        })
+    }
+
+    /// Whether the field's values are generated types that collect invariant issues, rather than
+    /// primitives.
+    fn has_invariants(&self) -> bool {
+        if self.el.is_choice_type() {
+            self.el.get_choice_types().into_iter().any(|t| !t.is_primitive())
+        } else {
+            self.el.content_reference.is_some()
+                || self.el.is_container()
+                || self.el.r#type.first().is_some_and(|t| !t.is_primitive())
+        }
+    }
+
+    /// The local holding the field while deserializing, kept apart from the visitor's own locals.
+    fn local_name(&self) -> Result<proc_macro2::Ident> {
+        ident(&format!("field_{}", self.name.trim_start_matches("r#")))
+    }
+}
+
+/// Whether values of `type` have an `is_empty()`: generated structs and enums and `Element<T>` do,
+/// bare primitives and resources always hold something.
+fn type_has_is_empty(r#type: &schema::ElementType) -> bool {
+    r#type.code != "Resource" && (!r#type.is_primitive() || r#type.is_extensible_primitive())
+}
+
+fn generate_structs(
+    config: &Config,
+    def: &schema::StructureDefinition,
+    el: &schema::ElementDefinition,
+) -> Result<TokenStream> {
+    let typename = def.get_container_type_name(el);
+    let children = def.get_direct_children(el);
+    let fields: Vec<Field> = children.iter().filter_map(|child| Field::new(def, child)).collect();
+
+    let mut field_defs = Vec::new();
+    for field in &fields {
+        let doc = docs::element_doc(def, field.el);
+        let name = ident(&field.name)?;
+        let field_type = rust_type(&field.field_type)?;
+        field_defs.push(quote! {
+            #doc
+            pub #name: #field_type
+        });
+    }
+    let doc = docs::element_doc(def, el);
+    let name = ident(&typename)?;
+    let (derives, attributes) = config.get_type_options(&typename);
+    let mut tokens = quote! {
+        #doc
+        #[derive(Debug, Clone, PartialEq #(, #derives)*)]
+        #(#attributes)*
+        pub struct #name {
+            #(#field_defs,)*
+        }
+    };
+    tokens.extend(generate_is_empty(&typename, &fields)?);
+    // Only a resource's root object carries `resourceType`.
+    let resource_type = (def.is_resource() && el.path == def.r#type).then_some(def.r#type.as_str());
+    tokens.extend(generate_serialize(def, &typename, resource_type, &fields)?);
+    tokens.extend(generate_deserialize(def, &typename, resource_type, &fields)?);
+    tokens.extend(generate_invariants(def, el, &typename, &fields)?);
+
+    for field in &fields {
+        if field.el.is_choice_type() {
+            tokens.extend(generate_choice_enum(config, def, field.el)?);
+            if field.has_invariants() {
+                tokens.extend(generate_choice_invariants(def, field.el)?);
+            }
+        } else if field.el.is_container() && field.el.content_reference.is_none() {
+            tokens.extend(generate_structs(config, def, field.el)?);
+        }
+    }
+    Ok(tokens)
+}
+
+/// `is_empty()` tells whether a value has no content at all, which FHIR JSON leaves out rather than
+/// writing `{}`.
+fn generate_is_empty(typename: &str, fields: &[Field]) -> Result<TokenStream> {
+    let terms = fields
+        .iter()
+        .map(Field::is_empty_expr)
+        .collect::<Result<Option<Vec<_>>>>()?;
+    let body = match terms {
+        Some(terms) if terms.is_empty() => quote!(true),
+        Some(terms) => quote!(#(#terms)&&*),
+        // A field that always holds something makes the value never empty.
+        None => quote!(false),
+    };
+    let name = ident(typename)?;
+    Ok(quote! {
+        impl #name {
+            pub fn is_empty(&self) -> bool {
+                #body
+            }
+        }
+    })
+}
+
+/// A `[x]` element becomes an enum with a variant per allowed type. The parent's serde impls read
+/// and write each variant under the element's name suffixed with the type, e.g. `valueQuantity`.
+fn generate_choice_enum(
+    config: &Config,
+    def: &schema::StructureDefinition,
+    el: &schema::ElementDefinition,
+) -> Result<TokenStream> {
+    let doc = format!(" Choice of types for `{}`.", el.path);
+    let typename = def.get_choice_type_name(el);
+    let name = ident(&typename)?;
+    let (derives, attributes) = config.get_type_options(&typename);
+    let mut variants = Vec::new();
+    let mut arms = Vec::new();
+    for r#type in el.get_choice_types() {
+        let variant = ident(&get_choice_variant_name(r#type))?;
+        let type_name = rust_type(&def.get_type_name(r#type))?;
+        variants.push(if r#type.boxed {
+            quote!(#variant(Box<#type_name>))
+        } else {
+            quote!(#variant(#type_name))
+        });
+        arms.push(if type_has_is_empty(r#type) {
+            quote!(#name::#variant(value) => value.is_empty(),)
+        } else {
+            quote!(#name::#variant(_) => false,)
+        });
+    }
+    Ok(quote! {
+        #[doc = #doc]
+        #[derive(Debug, Clone, PartialEq #(, #derives)*)]
+        #[allow(clippy::large_enum_variant)]
+        #(#attributes)*
+        pub enum #name {
+            #(#variants,)*
+        }
+
+        impl #name {
+            pub fn is_empty(&self) -> bool {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+    })
+}
+
+/// The constraints with an expression that the struct for `el` evaluates: those on the element
+/// itself and on its children, other than backbone elements, which have structs of their own.
+/// Below the root, constraints inherited from another definition, such as `ele-1` from `Element`
+/// or those of a child's data type, are left to the generated type for that definition.
+fn get_invariants<'a>(
+    def: &schema::StructureDefinition,
+    el: &'a schema::ElementDefinition,
+    fields: &[Field<'a>],
+) -> Vec<(&'a str, &'a schema::ElementConstraint)> {
+    let is_root = el.path == def.r#type;
+    let children = fields
+        .iter()
+        .filter(|f| !f.el.is_container() && f.el.content_reference.is_none())
+        .map(|f| f.el);
+    std::iter::once(el)
+        .chain(children)
+        .flat_map(|el| el.constraint.iter().map(move |c| (el, c)))
+        .filter(|(e, c)| {
+            c.expression.is_some() && ((is_root && e.id == el.id) || c.source.as_ref().is_none_or(|s| *s == def.url))
+        })
+        .map(|(el, c)| (el.id.as_str(), c))
+        .collect()
+}
+
+/// `INVARIANTS` and `validate_invariants()`, which evaluates them and those of every value in the
+/// struct over its JSON. Issues carry the JSON path of the value, e.g. `Patient.contact[0]`.
+fn generate_invariants(
+    def: &schema::StructureDefinition,
+    el: &schema::ElementDefinition,
+    typename: &str,
+    fields: &[Field],
+) -> Result<TokenStream> {
+    use schema::Cardinality;
+
+    let is_resource = def.is_resource() && el.path == def.r#type;
+    let type_name = if el.path == def.r#type {
+        def.r#type.as_str()
+    } else {
+        "BackboneElement"
+    };
+    let invariants = get_invariants(def, el, fields)
+        .into_iter()
+        .map(|(element, constraint)| {
+            let severity = match constraint.severity {
+                schema::ConstraintSeverity::Error => quote!(Error),
+                schema::ConstraintSeverity::Warning => quote!(Warning),
+            };
+            let (key, human) = (&constraint.key, &constraint.human);
+            let expression = constraint.expression.as_deref().unwrap_or_default();
+            quote! {
+                crate::invariant::Invariant {
+                    element: #element,
+                    key: #key,
+                    severity: crate::invariant::Severity::#severity,
+                    human: #human,
+                    expression: #expression,
+                }
+            }
+        });
+
+    let mut checks = Vec::new();
+    for field in fields.iter().filter(|f| f.has_invariants()) {
+        let name = ident(&field.name)?;
+        if field.el.is_choice_type() {
+            match field.cardinality {
+                Cardinality::Optional => checks.push(quote! {
+                    if let Some(item) = &self.#name {
+                        item.collect_invariant_issues(value, resource, path, issues);
+                    }
+                }),
+                Cardinality::Required => checks.push(quote! {
+                    self.#name.collect_invariant_issues(value, resource, path, issues);
+                }),
+                // Choice elements don't repeat.
+                _ => {}
+            }
+            continue;
+        }
+        let json_name = &field.json_name;
+        match field.cardinality {
+            Cardinality::Optional => {
+                let label = format!("{{}}.{}", json_name);
+                checks.push(quote! {
+                    if let (Some(item), Some(value)) = (&self.#name, value.get(#json_name)) {
+                        item.collect_invariant_issues(value, resource, &format!(#label, path), issues);
+                    }
+                });
+            }
+            Cardinality::Repeated => {
+                // Empty values aren't written to JSON, so skip them to keep the indexes in step.
+                let items = if field.has_is_empty() {
+                    quote!(self.#name.iter().filter(|v| !v.is_empty()))
+                } else {
+                    quote!(self.#name.iter())
+                };
+                let label = format!("{{}}.{}[{{}}]", json_name);
+                checks.push(quote! {
+                    if let Some(values) = value.get(#json_name).and_then(|v| v.as_array()) {
+                        for (i, (item, value)) in #items.zip(values).enumerate() {
+                            item.collect_invariant_issues(value, resource, &format!(#label, path, i), issues);
+                        }
+                    }
+                });
+            }
+            _ => {
+                let label = format!("{{}}.{}", json_name);
+                checks.push(quote! {
+                    if let Some(value) = value.get(#json_name) {
+                        self.#name.collect_invariant_issues(value, resource, &format!(#label, path), issues);
+                    }
+                });
+            }
+        }
+    }
+
+    let doc = format!(
+        " The invariants on `{}` and its children that this type evaluates.",
+        el.id
+    );
+    let (element, path) = (&el.id, &el.path);
+    // A resource, contained ones included, is the `%resource` of the values in it.
+    let (resource_param, resource_binding) = if is_resource {
+        (quote!(_resource), Some(quote!(let resource = value;)))
+    } else {
+        (quote!(resource), None)
+    };
+    let name = ident(typename)?;
+    Ok(quote! {
+        impl #name {
+            #[doc = #doc]
+            pub const INVARIANTS: &'static [crate::invariant::Invariant] = &[#(#invariants),*];
+
+            /// Evaluates the invariants over the value and every value in it, returning the broken ones
+            /// and those that couldn't be evaluated. Fails if the value can't be serialized to the JSON
+            /// they're evaluated over.
+            pub fn validate_invariants(&self) -> Result<Vec<crate::invariant::InvariantIssue>, serde_json::Error> {
+                let value = serde_json::to_value(self)?;
+                let mut issues = Vec::new();
+                self.collect_invariant_issues(&value, &value, #path, &mut issues);
+                Ok(issues)
+            }
+
+            #[doc(hidden)]
+            pub fn collect_invariant_issues(
+                &self,
+                value: &serde_json::Value,
+                #resource_param: &serde_json::Value,
+                path: &str,
+                issues: &mut Vec<crate::invariant::InvariantIssue>,
+            ) {
+                #resource_binding
+                crate::invariant::check_invariants(Self::INVARIANTS, #element, #type_name, value, resource, path, issues);
+                #(#checks)*
+            }
+        }
+    })
+}
+
+/// Collects the invariant issues of a choice element's value, read from the parent's JSON under
+/// the property for its type.
+fn generate_choice_invariants(
+    def: &schema::StructureDefinition,
+    el: &schema::ElementDefinition,
+) -> Result<TokenStream> {
+    let enum_name = ident(&def.get_choice_type_name(el))?;
+    let (primitives, complex): (Vec<_>, Vec<_>) = el.get_choice_types().into_iter().partition(|t| t.is_primitive());
+    let mut patterns = Vec::new();
+    let mut bodies = Vec::new();
+    for r#type in complex {
+        let variant = ident(&get_choice_variant_name(r#type))?;
+        let json_name = el.get_choice_json_name(r#type);
+        let label = format!("{{}}.{}", json_name);
+        patterns.push(quote!(#enum_name::#variant(item)));
+        bodies.push(quote! {
+            if let Some(value) = parent.get(#json_name) {
+                item.collect_invariant_issues(value, resource, &format!(#label, path), issues);
+            }
+        });
+    }
+    let body = match (patterns.as_slice(), bodies.as_slice()) {
+        ([pattern], [body]) if !primitives.is_empty() => quote! {
+            if let #pattern = self {
+                #body
+            }
+        },
+        _ => {
+            let rest = (!primitives.is_empty()).then(|| quote!(_ => {}));
+            quote! {
+                match self {
+                    #(#patterns => #bodies,)*
+                    #rest
+                }
+            }
+        }
+    };
+    Ok(quote! {
+        impl #enum_name {
+            #[doc(hidden)]
+            pub fn collect_invariant_issues(
+                &self,
+                parent: &serde_json::Value,
+                resource: &serde_json::Value,
+                path: &str,
+                issues: &mut Vec<crate::invariant::InvariantIssue>,
+            ) {
+                #body
+            }
+        }
+    })
+}
+
+fn get_choice_variant_name(r#type: &schema::ElementType) -> String {
+    r#type.code.to_case(Case::Pascal)
+}
+
+/// Writes each field under its JSON name, skipping absent and empty ones. Primitives also write
+/// their `_name` sibling when they have an id or extensions.
+fn generate_serialize(
+    def: &schema::StructureDefinition,
+    typename: &str,
+    resource_type: Option<&str>,
+    fields: &[Field],
+) -> Result<TokenStream> {
+    use schema::Cardinality;
+
+    let mut entries = Vec::new();
+    if let Some(resource_type) = resource_type {
+        entries.push(quote!(map.serialize_entry("resourceType", #resource_type)?;));
+    }
+    for field in fields {
+        let el = field.el;
+        let name = ident(&field.name)?;
+        let json_name = &field.json_name;
+        if el.is_choice_type() {
+            let enum_name = ident(&def.get_choice_type_name(el))?;
+            let mut arms = Vec::new();
+            for r#type in el.get_choice_types() {
+                let variant = ident(&get_choice_variant_name(r#type))?;
+                let pattern = match field.cardinality {
+                    Cardinality::Optional => quote!(Some(#enum_name::#variant(value))),
+                    _ => quote!(#enum_name::#variant(value)),
+                };
+                let json_name = el.get_choice_json_name(r#type);
+                arms.push(if r#type.is_extensible_primitive() {
+                    quote!(#pattern => crate::serde_support::serialize_primitive(&mut map, #json_name, value)?,)
+                } else if type_has_is_empty(r#type) {
+                    quote!(#pattern if !value.is_empty() => map.serialize_entry(#json_name, value)?,)
+                } else {
+                    quote!(#pattern => map.serialize_entry(#json_name, value)?,)
+                });
+            }
+            if field.cardinality == Cardinality::Optional || el.get_choice_types().into_iter().any(type_has_is_empty) {
+                arms.push(quote!(_ => {}));
+            }
+            entries.push(quote! {
+                match &self.#name {
+                    #(#arms)*
+                }
+            });
+        } else if el.is_extensible_primitive() {
+            entries.push(match field.cardinality {
+                Cardinality::Optional => quote! {
+                    if let Some(value) = &self.#name {
+                        crate::serde_support::serialize_primitive(&mut map, #json_name, value)?;
+                    }
+                },
+                Cardinality::Repeated => quote! {
+                    crate::serde_support::serialize_primitive_vec(&mut map, #json_name, &self.#name)?;
+                },
+                _ => quote! {
+                    crate::serde_support::serialize_primitive(&mut map, #json_name, &self.#name)?;
+                },
+            });
+        } else if field.has_is_empty() {
+            entries.push(match field.cardinality {
+                Cardinality::Optional => quote! {
+                    if let Some(value) = self.#name.as_ref().filter(|v| !v.is_empty()) {
+                        map.serialize_entry(#json_name, value)?;
+                    }
+                },
+                Cardinality::Repeated => {
+                    let type_name = rust_type(&field.type_name)?;
+                    quote! {
+                        {
+                            let values: Vec<&#type_name> = self.#name.iter().filter(|v| !v.is_empty()).collect();
+                            if !values.is_empty() {
+                                map.serialize_entry(#json_name, &values)?;
+                            }
+                        }
+                    }
+                }
+                _ => quote!(map.serialize_entry(#json_name, &self.#name)?;),
+            });
+        } else {
+            entries.push(match field.cardinality {
+                Cardinality::Optional => quote! {
+                    if let Some(value) = &self.#name {
+                        map.serialize_entry(#json_name, value)?;
+                    }
+                },
+                Cardinality::Repeated => quote! {
+                    if !self.#name.is_empty() {
+                        map.serialize_entry(#json_name, &self.#name)?;
+                    }
+                },
+                _ => quote!(map.serialize_entry(#json_name, &self.#name)?;),
+            });
+        }
+    }
+    let name = ident(typename)?;
+    Ok(quote! {
+        impl serde::Serialize for #name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                use serde::ser::SerializeMap;
+                let mut map = serializer.serialize_map(None)?;
+                #(#entries)*
+                map.end()
+            }
+        }
+    })
+}
+
+/// Collects the fields from a JSON object, merging each primitive's `name` and `_name` properties
+/// and picking choice variants by their suffixed property names. Unknown properties are an error.
+fn generate_deserialize(
+    def: &schema::StructureDefinition,
+    typename: &str,
+    resource_type: Option<&str>,
+    fields: &[Field],
+) -> Result<TokenStream> {
+    use schema::Cardinality;
+
+    let mut locals = Vec::new();
+    for field in fields {
+        let local = field.local_name()?;
+        let type_name = rust_type(&field.type_name)?;
+        locals.push(match field.cardinality {
+            Cardinality::Repeated => quote!(let mut #local: Vec<#type_name> = Vec::new();),
+            _ => quote!(let mut #local: Option<#type_name> = None;),
+        });
+    }
+    let mut json_names: Vec<String> = resource_type.map(|_| "resourceType".to_string()).into_iter().collect();
+    for field in fields {
+        if field.el.is_choice_type() {
+            for r#type in field.el.get_choice_types() {
+                json_names.push(field.el.get_choice_json_name(r#type));
+                if r#type.is_extensible_primitive() {
+                    json_names.push(format!("_{}", field.el.get_choice_json_name(r#type)));
+                }
+            }
+        } else {
+            json_names.push(field.json_name.clone());
+            if field.el.is_extensible_primitive() {
+                json_names.push(format!("_{}", field.json_name));
+            }
+        }
+    }
+
+    let mut arms = Vec::new();
+    if let Some(resource_type) = resource_type {
+        arms.push(quote! {
+            "resourceType" => {
+                let resource_type: String = map.next_value()?;
+                if resource_type != #resource_type {
+                    return Err(serde::de::Error::invalid_value(
+                        serde::de::Unexpected::Str(&resource_type),
+                        &#resource_type,
+                    ));
+                }
+            }
+        });
+    }
+    for field in fields {
+        let el = field.el;
+        let local = field.local_name()?;
+        let json_name = &field.json_name;
+        let extension_name = format!("_{}", json_name);
+        if el.is_choice_type() {
+            let enum_name = ident(&def.get_choice_type_name(el))?;
+            let choice_name = format!("{}[x]", json_name);
+            for r#type in el.get_choice_types() {
+                let variant = ident(&get_choice_variant_name(r#type))?;
+                let json_name = el.get_choice_json_name(r#type);
+                if r#type.is_extensible_primitive() {
+                    let extension_name = format!("_{}", json_name);
+                    let duplicate = (el.get_choice_types().len() > 1)
+                        .then(|| quote!(_ => return Err(serde::de::Error::duplicate_field(#choice_name)),));
+                    arms.push(quote! {
+                        #json_name | #extension_name => match #local.get_or_insert_with(|| #enum_name::#variant(Default::default())) {
+                            #enum_name::#variant(value) => crate::serde_support::deserialize_primitive(value, key.starts_with('_'), &mut map)?,
+                            #duplicate
+                        },
+                    });
+                } else {
+                    arms.push(quote! {
+                        #json_name => {
+                            if #local.is_some() {
+                                return Err(serde::de::Error::duplicate_field(#choice_name));
+                            }
+                            #local = Some(#enum_name::#variant(map.next_value()?));
+                        }
+                    });
+                }
+            }
+        } else if el.is_extensible_primitive() {
+            arms.push(match field.cardinality {
+                Cardinality::Repeated => quote! {
+                    #json_name | #extension_name => crate::serde_support::deserialize_primitive_vec(&mut #local, key.starts_with('_'), &mut map)?,
+                },
+                _ => quote! {
+                    #json_name | #extension_name => crate::serde_support::deserialize_primitive(#local.get_or_insert_with(Default::default), key.starts_with('_'), &mut map)?,
+                },
+            });
+        } else {
+            arms.push(match field.cardinality {
+                Cardinality::Repeated => quote!(#json_name => #local = map.next_value()?,),
+                _ => quote!(#json_name => #local = Some(map.next_value()?),),
+            });
+        }
+    }
+
+    let mut inits = Vec::new();
+    for field in fields {
+        let name = ident(&field.name)?;
+        let local = field.local_name()?;
+        inits.push(match field.cardinality {
+            Cardinality::Required => {
+                let json_name = if field.el.is_choice_type() {
+                    format!("{}[x]", field.json_name)
+                } else {
+                    field.json_name.clone()
+                };
+                quote!(#name: #local.ok_or_else(|| serde::de::Error::missing_field(#json_name))?,)
+            }
+            _ => quote!(#name: #local,),
+        });
+    }
+
+    let name = ident(typename)?;
+    Ok(quote! {
+        impl<'de> serde::Deserialize<'de> for #name {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                struct Visitor;
+                impl<'de> serde::de::Visitor<'de> for Visitor {
+                    type Value = #name;
+                    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                        formatter.write_str(#typename)
+                    }
+                    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                        #(#locals)*
+                        const FIELDS: &[&str] = &[#(#json_names),*];
+                        while let Some(key) = map.next_key::<String>()? {
+                            match key.as_str() {
+                                #(#arms)*
+                                _ => return Err(serde::de::Error::unknown_field(&key, FIELDS)),
+                            }
+                        }
+                        Ok(#name {
+                            #(#inits)*
+                        })
+                    }
+                }
+                deserializer.deserialize_map(Visitor)
+            }
+        }
+    })
+}
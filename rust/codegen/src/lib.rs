@@ -0,0 +1,61 @@
+//! Generates Rust types for FHIR resources, data types, extensions and profiles from their
+//! StructureDefinitions.
+//!
+//! The `codegen` binary generates the `fhir` crate's modules. Crates with definitions of their own,
+//! such as an implementation guide's profiles, can generate theirs from a build script, into a
+//! single file that they `include!`:
+//!
+//! ```no_run
+//! # fn main() -> anyhow::Result<()> {
+//! use std::path::Path;
+//!
+//! let cache = codegen::package::PackageCache::default_dir()
+//!     .map(codegen::package::PackageCache::new)
+//!     .expect("a home directory");
+//! let resources = codegen::load(&[], &[Path::new("packages/my-ig.tgz")], &cache)?;
+//! let mut config = codegen::Config::from_path(Path::new("sfhir-codegen.toml"))?;
+//! config.output.runtime = syn::parse_quote!(::fhir);
+//! config.output.features = false;
+//! let generated = codegen::generate(&config, vec![("r4", resources)])?;
+//! for (release, diagnostics) in &generated.diagnostics {
+//!     for diagnostic in diagnostics.sorted() {
+//!         println!("cargo:warning={} ({})", diagnostic, release);
+//!     }
+//! }
+//! generated.write_to_out_dir("fhir.rs")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! and then, in the crate, `mod fhir { include!(concat!(env!("OUT_DIR"), "/fhir.rs")); }`. The
+//! generated code uses the `fhir` crate, `serde` and `serde_json`, so the crate depends on those.
+
+use std::path::Path;
+
+use anyhow::Result;
+
+pub mod config;
+pub mod diagnostics;
+mod docs;
+mod generate;
+pub mod package;
+pub mod schema;
+mod tokens;
+
+pub use config::Config;
+pub use diagnostics::Diagnostics;
+pub use generate::{generate, Generated, ALL_RESOURCES_FEATURE};
+pub use schema::Schema;
+
+/// Loads the definitions in `definitions`, each a zip, a directory of JSON files or a JSON
+/// resource, and in the FHIR packages in `packages` along with the packages they depend on.
+pub fn load(definitions: &[&Path], packages: &[&Path], package_cache: &package::PackageCache) -> Result<Schema> {
+    let mut resources = Schema::default();
+    for path in definitions {
+        resources.extend(schema::from_path(path)?);
+    }
+    for path in packages {
+        resources.extend(package::load(path, package_cache)?);
+    }
+    Ok(resources)
+}
@@ -1,160 +1,516 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::File;
-use std::path::PathBuf;
-use std::process;
+use std::path::{Path, PathBuf};
 
-use anyhow::{anyhow, Context, Result};
-use clap::Parser;
-use convert_case::{Case, Casing};
-use zip::read::ZipArchive;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Parser, ValueEnum};
 
-mod schema;
+use codegen::diagnostics::Severity;
+use codegen::{package, schema, Config, Diagnostics, ALL_RESOURCES_FEATURE};
 
 #[derive(Debug, Parser)]
 pub struct Args {
-    /// Paths to files to open
+    /// The crate to generate into [default: `crate` from the config, else rust/fhir]
     crate_path: Option<PathBuf>,
+
+    /// Config tuning what is generated and how [default: ./sfhir-codegen.toml, if there is one]
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// FHIR releases to generate; each is written to its own module behind a cargo feature
+    #[arg(long = "release", value_enum, default_values_t = [FhirRelease::R5])]
+    releases: Vec<FhirRelease>,
+
+    /// Local definitions to load instead of `./resources/definitions-<release>.json.zip`. PATH may be
+    /// a zip, a directory of JSON files or a single JSON resource such as a Bundle; prefix it with
+    /// `RELEASE=` when generating more than one release
+    #[arg(long = "definitions", value_name = "[RELEASE=]PATH", value_parser = parse_definitions_source)]
+    definitions: Vec<DefinitionsSource>,
+
+    /// FHIR NPM packages (a `package.tgz` or an unpacked package directory) to load along with the
+    /// dependencies they declare; prefix with `RELEASE=` as for `--definitions`
+    #[arg(long = "package", value_name = "[RELEASE=]PATH", value_parser = parse_definitions_source)]
+    packages: Vec<DefinitionsSource>,
+
+    /// Package cache used to resolve package dependencies [default: ~/.fhir/packages]
+    #[arg(long)]
+    package_cache: Option<PathBuf>,
+
+    /// Download missing definitions from hl7.org into `./resources`
+    #[arg(long)]
+    download: bool,
+
+    /// List the generated files that are out of date with the definitions and fail, without
+    /// writing anything
+    #[arg(long, conflicts_with = "download")]
+    check: bool,
+
+    /// Fail if the definitions have anything the generated code leaves out or approximates, not
+    /// only definitions and elements it can't generate at all
+    #[arg(long)]
+    deny_warnings: bool,
+}
+
+#[derive(Debug, Clone)]
+struct DefinitionsSource {
+    release: Option<FhirRelease>,
+    path: PathBuf,
+}
+
+impl DefinitionsSource {
+    fn for_release(sources: &[DefinitionsSource], release: FhirRelease) -> Vec<&Path> {
+        sources
+            .iter()
+            .filter(|d| d.release.is_none() || d.release == Some(release))
+            .map(|d| d.path.as_path())
+            .collect()
+    }
+}
+
+fn parse_definitions_source(s: &str) -> Result<DefinitionsSource> {
+    if let Some((release, path)) = s.split_once('=') {
+        if let Ok(release) = FhirRelease::from_str(release, true) {
+            return Ok(DefinitionsSource {
+                release: Some(release),
+                path: PathBuf::from(path),
+            });
+        }
+    }
+    Ok(DefinitionsSource {
+        release: None,
+        path: PathBuf::from(s),
+    })
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 enum FhirRelease {
+    #[value(name = "r4")]
+    R4,
+    #[value(name = "r4b")]
+    R4B,
+    #[value(name = "r5")]
     R5,
+    #[value(name = "r6")]
+    R6,
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
-
-    let resources_dir = PathBuf::from("./resources");
-    ensure_dir(&resources_dir)?;
-
-    let crate_path = args.crate_path.unwrap_or(PathBuf::from("rust/fhir"));
-    let generated_dir = crate_path.join("src").join("generated");
-    std::fs::remove_dir_all(&generated_dir)?;
-    ensure_dir(&generated_dir)?;
-
-    let resources = get_fhir_resources(FhirRelease::R5, &resources_dir)?;
-    let mut generated_mod = File::create(generated_dir.join("mod.rs"))?;
-    for structure_definition in resources.structures_definitions {
-        use std::io::Write;
-
-        if structure_definition.kind != schema::StructureDefinitionKind::Resource
-            || structure_definition.derivation != Some(schema::TypeDerivationRule::Specialization)
-            || structure_definition.r#abstract
-        {
-            continue;
+impl FhirRelease {
+    /// Name of the generated module, which is also the name of the cargo feature gating it.
+    fn module_name(&self) -> &'static str {
+        match self {
+            FhirRelease::R4 => "r4",
+            FhirRelease::R4B => "r4b",
+            FhirRelease::R5 => "r5",
+            FhirRelease::R6 => "r6",
         }
+    }
 
-        let modname = structure_definition.get_structure_field_name();
-        let file = File::create(generated_dir.join(format!("{}.rs", modname)))?;
-        if let Some(el) = structure_definition.snapshot.element.get(0) {
-            generate_structs(&file, &structure_definition, el)?;
+    fn definitions_url(&self) -> &'static str {
+        match self {
+            FhirRelease::R4 => "http://hl7.org/fhir/R4/definitions.json.zip",
+            FhirRelease::R4B => "http://hl7.org/fhir/R4B/definitions.json.zip",
+            FhirRelease::R5 => "http://hl7.org/fhir/r5/definitions.json.zip",
+            FhirRelease::R6 => "http://build.fhir.org/definitions.json.zip",
         }
+    }
 
-        writeln!(generated_mod, "mod {};", modname)?;
-        writeln!(generated_mod, "pub use {}::*;", modname)?;
-        writeln!(generated_mod)?;
+    /// The official examples, which the `fhir` crate's tests round-trip.
+    fn examples_url(&self) -> &'static str {
+        match self {
+            FhirRelease::R4 => "http://hl7.org/fhir/R4/examples-json.zip",
+            FhirRelease::R4B => "http://hl7.org/fhir/R4B/examples-json.zip",
+            FhirRelease::R5 => "http://hl7.org/fhir/r5/examples-json.zip",
+            FhirRelease::R6 => "http://build.fhir.org/examples-json.zip",
+        }
     }
-    rust_fmt()?;
+}
 
-    // let
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let mut releases = args.releases;
+    releases.sort();
+    releases.dedup();
+    if releases.len() > 1
+        && args
+            .definitions
+            .iter()
+            .chain(&args.packages)
+            .any(|d| d.release.is_none())
+    {
+        bail!("--definitions and --package must be given as RELEASE=PATH when generating more than one release");
+    }
+    let package_cache = args
+        .package_cache
+        .or_else(package::PackageCache::default_dir)
+        .map(package::PackageCache::new)
+        .ok_or_else(|| anyhow!("no home directory, pass --package-cache"))?;
 
-    // let file = File::open(filepath).with_context(|| format!("path {}", filepath.as_os_str))?;
-    // let json_schema = json_schema::Schema::from_reader(file)
-    //     .with_context(|| format!("read JSON schema {}", filepath))?;
-    // let schema = ir::Schema::from_json_schema(json_schema)?;
+    let resources_dir = PathBuf::from("./resources");
 
-    // let resources_dir = generated_dir.join("resources");
-    // ensure_dir(&resources_dir)?;
+    let config = Config::load(args.config.as_deref())?;
+    let crate_path = args
+        .crate_path
+        .or_else(|| config.crate_path.clone())
+        .unwrap_or(PathBuf::from("rust/fhir"));
+    let mut schemas = Vec::new();
+    for release in releases {
+        let definitions = DefinitionsSource::for_release(&args.definitions, release);
+        let packages = DefinitionsSource::for_release(&args.packages, release);
+        let resources = if definitions.is_empty() && packages.is_empty() {
+            get_fhir_resources(release, &resources_dir, args.download)?
+        } else {
+            codegen::load(&definitions, &packages, &package_cache)?
+        };
+        schemas.push((release.module_name(), resources));
+    }
+    let generated = codegen::generate(&config, schemas)?;
 
-    // let mut resources_mod = File::create(resources_dir.join("mod.rs"))?;
-    // for name in schema.modules {
-    //     let s = &schema.types[&name];
-    //     let mut resource = File::create(resources_dir.join(format!("{}.rs", s.mod_name())))?;
-    //     if let Some(ref desc) = s.description {
-    //         writeln!(resource, "{}", textwrap::indent(&textwrap::fill(desc, 80), "/// "))?;
-    //     }
-    //     writeln!(resource, "#[derive(Debug,Clone,PartialEq)]")?;
-    //     writeln!(resource, "pub struct {} {{", s.type_name())?;
-    //     for prop in &s.properties {
-    //         writeln!(resource, "pub {}: {},", prop.name, prop.r#type)?;
-    //     }
-    //     writeln!(resource, "}}")?;
+    // Everything is generated in memory first, then only the files that changed are written.
+    let mut outputs: BTreeMap<PathBuf, Vec<u8>> = generated
+        .render()?
+        .into_iter()
+        .map(|(path, source)| (Path::new(GENERATED_DIR).join(path), source.into_bytes()))
+        .collect();
 
-    //     writeln!(resources_mod, "mod {};", s.mod_name())?;
-    //     writeln!(resources_mod, "pub use {}::*;", s.mod_name())?;
-    //     writeln!(resources_mod)?;
-    // }
+    let manifest_path = crate_path.join("Cargo.toml");
+    let manifest =
+        std::fs::read_to_string(&manifest_path).with_context(|| format!("read {}", manifest_path.display()))?;
+    outputs.insert(
+        PathBuf::from("Cargo.toml"),
+        update_features(&manifest, &generated.features)
+            .with_context(|| format!("update features in {}", manifest_path.display()))?
+            .into_bytes(),
+    );
 
-    // let mut generated_mod = File::create(generated_dir.join("mod.rs"))?;
-    // writeln!(generated_mod, "mod resources;")?;
+    let changes = get_changes(&crate_path, &outputs)?;
+    for (path, change) in &changes {
+        println!("{} {}", change.as_str(), crate_path.join(path).display());
+    }
+    if !args.check {
+        apply_changes(&crate_path, &outputs, &changes)?;
+    }
 
-    // rust_fmt()?;
+    let (errors, warnings) = report_diagnostics(&generated.diagnostics);
+    if args.check && !changes.is_empty() {
+        bail!(
+            "{} generated files are out of date with the definitions, run codegen without --check to update them",
+            changes.len()
+        );
+    }
+    if errors > 0 || (args.deny_warnings && warnings > 0) {
+        bail!(
+            "the definitions have {} errors and {} warnings{}",
+            errors,
+            warnings,
+            if errors > 0 {
+                "; what they affect wasn't generated"
+            } else {
+                " (--deny-warnings)"
+            }
+        );
+    }
 
     Ok(())
 }
 
-fn generate_structs(
-    mut w: impl std::io::Write,
-    def: &schema::StructureDefinition,
-    el: &schema::ElementDefinition,
-) -> Result<()> {
-    let typename = def.get_container_type_name(el);
-    let children = def.get_direct_children(el);
-    writeln!(w, "#[derive(Debug,Clone,PartialEq)]")?;
-    writeln!(w, "pub struct {} {{", typename)?;
-    for child in children {
-        // if let Some(ref short) = child.short {
-        //     writeln!(w, "{}", textwrap::indent(&textwrap::fill(short, 80), "/// "))?;
-        // }
-        if let Some(ref definition) = child.definition {
-            writeln!(w, "{}", textwrap::indent(&textwrap::fill(definition, 80), "/// "))?;
+/// Prints every diagnostic, then a count per release, and returns the total errors and warnings.
+fn report_diagnostics(diagnostics: &[(String, Diagnostics)]) -> (usize, usize) {
+    for (release, release_diagnostics) in diagnostics {
+        for diagnostic in release_diagnostics.sorted() {
+            eprintln!("{} ({})", diagnostic, release);
         }
-        writeln!(
-            w,
-            "pub {}: {},",
-            def.get_element_field_name(child),
-            def.get_element_type_name(child),
-        )?;
-    }
-    writeln!(w, "}}")?;
-    Ok(())
+    }
+    let (mut errors, mut warnings) = (0, 0);
+    for (release, release_diagnostics) in diagnostics {
+        let release_errors = release_diagnostics.count(Severity::Error);
+        let release_warnings = release_diagnostics.count(Severity::Warning);
+        if release_errors + release_warnings > 0 {
+            eprintln!("{}: {} errors, {} warnings", release, release_errors, release_warnings);
+        }
+        errors += release_errors;
+        warnings += release_warnings;
+    }
+    (errors, warnings)
 }
 
-fn get_fhir_resources(release: FhirRelease, resources_dir: &PathBuf) -> Result<schema::Schema> {
-    use std::io::Write;
+/// Where the generated modules live, relative to the crate.
+const GENERATED_DIR: &str = "src/generated";
 
-    let (filepath, url) = match release {
-        FhirRelease::R5 => ("definitions-r5.json.zip", "http://hl7.org/fhir/r5/definitions.json.zip"),
+/// Rewrites the features of the crate's manifest `manifest` that the generator owns: a feature per
+/// generated resource, enabling the features of the resources it depends on, `all-resources`, the
+/// release features and `default`, which gains `all-resources` and loses any resource feature but
+/// otherwise keeps its entries. Features for resources no longer generated, found in the old
+/// `all-resources`, are removed; any other feature is left as it is, after the generated ones.
+fn update_features(manifest: &str, features: &BTreeMap<String, BTreeSet<String>>) -> Result<String> {
+    use toml_edit::{value, Array};
+
+    let mut doc: toml_edit::DocumentMut = manifest.parse()?;
+    let releases: Vec<&str> = FhirRelease::value_variants().iter().map(|r| r.module_name()).collect();
+    let table = doc["features"].or_insert(toml_edit::table());
+    let table = table.as_table_mut().ok_or_else(|| anyhow!("features isn't a table"))?;
+    let feature_names = |name: &str| -> Vec<String> {
+        table
+            .get(name)
+            .and_then(|d| d.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|f| f.as_str())
+            .map(str::to_string)
+            .collect()
+    };
+    let previous_resources = feature_names(ALL_RESOURCES_FEATURE);
+    let is_resource = |name: &str| features.contains_key(name) || previous_resources.iter().any(|f| f == name);
+    let is_generated = |name: &str| {
+        name == "default" || name == ALL_RESOURCES_FEATURE || releases.contains(&name) || is_resource(name)
     };
 
-    let filepath = resources_dir.join(filepath);
+    // Resource features don't belong in `default`, `all-resources` does; anything else stays.
+    let mut default: Vec<String> = feature_names("default")
+        .into_iter()
+        .filter(|f| !is_resource(f))
+        .collect();
+    if !default.iter().any(|f| f == ALL_RESOURCES_FEATURE) {
+        default.push(ALL_RESOURCES_FEATURE.to_string());
+    }
+    let others: Vec<_> = table
+        .iter()
+        .map(|(name, _)| name.to_string())
+        .filter(|name| !is_generated(name))
+        .collect();
+    let others: Vec<_> = others.iter().filter_map(|name| table.remove_entry(name)).collect();
+
+    table.clear();
+    table.insert("default", value(Array::from_iter(default)));
+    for release in releases {
+        table.insert(release, value(Array::new()));
+    }
+    let mut all = Array::from_iter(features.keys());
+    for feature in all.iter_mut() {
+        feature.decor_mut().set_prefix("\n    ");
+    }
+    all.set_trailing("\n");
+    all.set_trailing_comma(true);
+    table.insert(ALL_RESOURCES_FEATURE, value(all));
+    for (feature, dependencies) in features {
+        table.insert(feature, value(Array::from_iter(dependencies)));
+    }
+    for (key, item) in others {
+        table.insert_formatted(&key, item);
+    }
+    Ok(doc.to_string())
+}
+
+/// How a file of the crate differs from what was generated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Added,
+    Changed,
+    /// A file under `src/generated` that is no longer generated.
+    Removed,
+}
+
+impl Change {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Change::Added => "added",
+            Change::Changed => "changed",
+            Change::Removed => "removed",
+        }
+    }
+}
+
+/// The files below `dir`, sorted.
+fn get_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(&dir).with_context(|| format!("read dir {}", dir.display()))? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Compares `outputs`, the generated files by path relative to the crate, with the crate's files.
+fn get_changes(crate_path: &Path, outputs: &BTreeMap<PathBuf, Vec<u8>>) -> Result<Vec<(PathBuf, Change)>> {
+    let mut changes = Vec::new();
+    for (path, contents) in outputs {
+        match std::fs::read(crate_path.join(path)) {
+            Ok(existing) if existing == *contents => {}
+            Ok(_) => changes.push((path.clone(), Change::Changed)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => changes.push((path.clone(), Change::Added)),
+            Err(e) => return Err(e).with_context(|| format!("read {}", crate_path.join(path).display())),
+        }
+    }
+    let generated_dir = crate_path.join(GENERATED_DIR);
+    if generated_dir.exists() {
+        for path in get_files(&generated_dir)? {
+            let path = path.strip_prefix(crate_path)?.to_path_buf();
+            if !outputs.contains_key(&path) {
+                changes.push((path, Change::Removed));
+            }
+        }
+    }
+    changes.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(changes)
+}
+
+/// Writes the added and changed files and deletes the removed ones, along with directories they
+/// leave empty.
+fn apply_changes(crate_path: &Path, outputs: &BTreeMap<PathBuf, Vec<u8>>, changes: &[(PathBuf, Change)]) -> Result<()> {
+    for (path, change) in changes {
+        let path_in_crate = crate_path.join(path);
+        match change {
+            Change::Added | Change::Changed => {
+                if let Some(parent) = path_in_crate.parent() {
+                    ensure_dir(parent)?;
+                }
+                std::fs::write(&path_in_crate, &outputs[path])?;
+            }
+            Change::Removed => {
+                std::fs::remove_file(&path_in_crate)?;
+                let generated_dir = crate_path.join(GENERATED_DIR);
+                let mut dir = path_in_crate.parent();
+                while let Some(d) = dir.filter(|d| *d != generated_dir) {
+                    if std::fs::read_dir(d)?.next().is_some() {
+                        break;
+                    }
+                    std::fs::remove_dir(d)?;
+                    dir = d.parent();
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn get_fhir_resources(release: FhirRelease, resources_dir: &Path, download: bool) -> Result<schema::Schema> {
+    let filepath = resources_dir.join(format!("definitions-{}.json.zip", release.module_name()));
     if !filepath.exists() {
-        let resp = reqwest::blocking::Client::builder()
-            .build()?
-            .get(url)
-            .header("Accept", "application/x-zip-compressed")
-            .header("User-Agent", "sfhir/dev")
-            .send()?;
-        let mut file = File::create(&filepath)?;
-        file.write_all(&resp.bytes()?)?;
+        if !download {
+            bail!(
+                "definitions for {} not found at {:?}; pass --definitions to use a local copy or --download to fetch {}",
+                release.module_name(),
+                filepath,
+                release.definitions_url()
+            );
+        }
+        download_file(release.definitions_url(), &filepath)?;
+    }
+    let examples_filepath = resources_dir.join(format!("examples-{}.json.zip", release.module_name()));
+    if download && !examples_filepath.exists() {
+        download_file(release.examples_url(), &examples_filepath)?;
     }
+    schema::from_path(&filepath)
+}
+
+fn download_file(url: &str, filepath: &Path) -> Result<()> {
+    use std::io::Write;
 
-    let file = File::open(&filepath).with_context(|| format!("path {:?}", filepath))?;
-    let zip = ZipArchive::new(file)?;
-    let resources = schema::from_zip(zip)?;
-    Ok(resources)
+    let resp = reqwest::blocking::Client::builder()
+        .build()?
+        .get(url)
+        .header("Accept", "application/x-zip-compressed")
+        .header("User-Agent", "sfhir/dev")
+        .send()?
+        .error_for_status()?;
+    if let Some(parent) = filepath.parent() {
+        ensure_dir(parent)?;
+    }
+    let mut file = File::create(filepath)?;
+    file.write_all(&resp.bytes()?)?;
+    Ok(())
 }
 
-fn ensure_dir(dir: &PathBuf) -> Result<()> {
+fn ensure_dir(dir: &Path) -> Result<()> {
     if !dir.exists() {
         std::fs::create_dir_all(dir)?;
     }
     Ok(())
 }
 
-fn rust_fmt() -> Result<()> {
-    let status = process::Command::new("cargo").args(["fmt"]).status()?;
-    if status.success() {
-        Ok(())
-    } else {
-        Err(anyhow!("cargo fmt failed!"))
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn features(resources: &[(&str, &[&str])]) -> BTreeMap<String, BTreeSet<String>> {
+        resources
+            .iter()
+            .map(|(name, dependencies)| (name.to_string(), dependencies.iter().map(|d| d.to_string()).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn update_features_keeps_other_features() {
+        let manifest = r#"[package]
+name = "fhir"
+
+[features]
+default = ["r4", "all-resources", "extra", "group"]
+r4 = []
+r5 = []
+# Validation of the examples.
+extra = ["patient"]
+all-resources = [
+    "group",
+    "patient",
+]
+group = []
+patient = []
+
+[dependencies]
+"#;
+        let updated = update_features(manifest, &features(&[("observation", &["patient"]), ("patient", &[])])).unwrap();
+        assert_eq!(
+            updated,
+            r#"[package]
+name = "fhir"
+
+[features]
+default = ["r4", "all-resources", "extra"]
+r4 = []
+r4b = []
+r5 = []
+r6 = []
+all-resources = [
+    "observation",
+    "patient",
+]
+observation = ["patient"]
+patient = []
+# Validation of the examples.
+extra = ["patient"]
+
+[dependencies]
+"#
+        );
+    }
+
+    #[test]
+    fn update_features_adds_table() {
+        let updated = update_features("[package]\nname = \"fhir\"\n", &features(&[("patient", &[])])).unwrap();
+        assert_eq!(
+            updated,
+            r#"[package]
+name = "fhir"
+
+[features]
+default = ["all-resources"]
+r4 = []
+r4b = []
+r5 = []
+r6 = []
+all-resources = [
+    "patient",
+]
+patient = []
+"#
+        );
     }
 }
@@ -0,0 +1,597 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use tar::Archive;
+
+use crate::schema::{self, Schema};
+
+const PACKAGE_DIR: &str = "package";
+const MANIFEST_FILENAME: &str = "package.json";
+const INDEX_FILENAME: &str = ".index.json";
+
+#[derive(Deserialize, Debug)]
+pub struct PackageManifest {
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PackageIndex {
+    files: Vec<PackageIndexFile>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PackageIndexFile {
+    filename: String,
+    resource_type: Option<String>,
+}
+
+/// A FHIR NPM package, either a `package.tgz` read into memory or an unpacked directory as found
+/// in the package cache (`<cache>/<name>#<version>/package`).
+pub struct Package {
+    pub manifest: PackageManifest,
+    contents: PackageContents,
+}
+
+enum PackageContents {
+    Tarball(BTreeMap<String, Vec<u8>>),
+    Directory(PathBuf),
+}
+
+impl Package {
+    pub fn open(path: &Path) -> Result<Self> {
+        let contents = if path.is_dir() {
+            let dir = if path.join(PACKAGE_DIR).join(MANIFEST_FILENAME).exists() {
+                path.join(PACKAGE_DIR)
+            } else {
+                path.to_path_buf()
+            };
+            PackageContents::Directory(dir)
+        } else {
+            PackageContents::Tarball(read_tarball(path)?)
+        };
+
+        let manifest = contents
+            .read(MANIFEST_FILENAME)?
+            .ok_or_else(|| anyhow!("no {} in package {:?}", MANIFEST_FILENAME, path))?;
+        let manifest: PackageManifest =
+            serde_json::from_slice(&manifest).with_context(|| format!("read {} in {:?}", MANIFEST_FILENAME, path))?;
+        Ok(Package { manifest, contents })
+    }
+
+    /// Collects the definitions in this package, ignoring its dependencies.
+    pub fn to_schema(&self) -> Result<Schema> {
+        let mut resources = Schema::default();
+        for filename in self.definition_filenames()? {
+            let data = self
+                .contents
+                .read(&filename)?
+                .ok_or_else(|| anyhow!("{} listed in {} but missing", filename, INDEX_FILENAME))?;
+            let schema = schema::from_reader(data.as_slice())
+                .with_context(|| format!("read {} in package {}", filename, self.id()))?;
+            resources.extend(schema);
+        }
+        Ok(resources)
+    }
+
+    pub fn id(&self) -> String {
+        format!("{}#{}", self.manifest.name, self.manifest.version)
+    }
+
+    /// Files that may hold definitions, taken from `.index.json` when the package has one.
+    fn definition_filenames(&self) -> Result<Vec<String>> {
+        match self.contents.read(INDEX_FILENAME)? {
+            Some(index) => {
+                let index: PackageIndex = serde_json::from_slice(&index)
+                    .with_context(|| format!("read {} in package {}", INDEX_FILENAME, self.id()))?;
+                Ok(index
+                    .files
+                    .into_iter()
+                    .filter(|f| {
+                        f.resource_type
+                            .as_deref()
+                            .is_some_and(schema::is_collected_resource_type)
+                    })
+                    .map(|f| f.filename)
+                    .collect())
+            }
+            None => Ok(self
+                .contents
+                .filenames()?
+                .into_iter()
+                .filter(|f| f.ends_with(".json") && f != MANIFEST_FILENAME)
+                .collect()),
+        }
+    }
+}
+
+impl PackageContents {
+    fn read(&self, filename: &str) -> Result<Option<Vec<u8>>> {
+        match self {
+            PackageContents::Tarball(files) => Ok(files.get(filename).cloned()),
+            PackageContents::Directory(dir) => {
+                let path = dir.join(filename);
+                if !path.exists() {
+                    return Ok(None);
+                }
+                let mut data = Vec::new();
+                BufReader::new(File::open(&path).with_context(|| format!("path {:?}", path))?)
+                    .read_to_end(&mut data)?;
+                Ok(Some(data))
+            }
+        }
+    }
+
+    fn filenames(&self) -> Result<Vec<String>> {
+        match self {
+            PackageContents::Tarball(files) => Ok(files.keys().cloned().collect()),
+            PackageContents::Directory(dir) => {
+                let mut filenames = Vec::new();
+                for entry in std::fs::read_dir(dir).with_context(|| format!("read dir {:?}", dir))? {
+                    let entry = entry?;
+                    if entry.file_type()?.is_file() {
+                        filenames.push(entry.file_name().to_string_lossy().into_owned());
+                    }
+                }
+                filenames.sort();
+                Ok(filenames)
+            }
+        }
+    }
+}
+
+/// Reads the files directly under `package/`; examples and other subfolders are never definitions.
+fn read_tarball(path: &Path) -> Result<BTreeMap<String, Vec<u8>>> {
+    let file = File::open(path).with_context(|| format!("path {:?}", path))?;
+    let mut archive = Archive::new(GzDecoder::new(BufReader::new(file)));
+    let mut files = BTreeMap::new();
+    for entry in archive.entries().with_context(|| format!("read package {:?}", path))? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let entry_path = entry.path()?.to_string_lossy().into_owned();
+        let Some(filename) = entry_path.strip_prefix("package/") else {
+            continue;
+        };
+        if filename.contains('/') {
+            continue;
+        }
+        let filename = filename.to_string();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        files.insert(filename, data);
+    }
+    Ok(files)
+}
+
+/// The local FHIR package cache, laid out as `<name>#<version>/package/...`.
+pub struct PackageCache {
+    dir: PathBuf,
+}
+
+impl PackageCache {
+    pub fn new(dir: PathBuf) -> Self {
+        PackageCache { dir }
+    }
+
+    /// `~/.fhir/packages`, the cache shared by the HL7 tooling.
+    pub fn default_dir() -> Option<PathBuf> {
+        std::env::var_os("HOME")
+            .or_else(|| std::env::var_os("USERPROFILE"))
+            .map(|home| PathBuf::from(home).join(".fhir").join("packages"))
+    }
+
+    /// Finds `name` at `version` in the cache. Versions ending in `.x` (e.g. `4.0.x`) resolve to the
+    /// highest cached patch release.
+    pub fn resolve(&self, name: &str, version: &str) -> Result<PathBuf> {
+        let exact = self.dir.join(format!("{}#{}", name, version));
+        if exact.is_dir() {
+            return Ok(exact);
+        }
+
+        if let Some(prefix) = version.strip_suffix('x') {
+            let mut candidates = Vec::new();
+            if self.dir.is_dir() {
+                for entry in std::fs::read_dir(&self.dir).with_context(|| format!("read dir {:?}", self.dir))? {
+                    let entry = entry?;
+                    let dirname = entry.file_name().to_string_lossy().into_owned();
+                    if let Some((cached_name, cached_version)) = dirname.split_once('#') {
+                        if cached_name == name && cached_version.starts_with(prefix) {
+                            candidates.push((version_key(cached_version), entry.path()));
+                        }
+                    }
+                }
+            }
+            if let Some((_, path)) = candidates.into_iter().max() {
+                return Ok(path);
+            }
+        }
+
+        bail!("package {}#{} not found in package cache {:?}", name, version, self.dir)
+    }
+}
+
+fn version_key(version: &str) -> Vec<u64> {
+    version
+        .split(['.', '-'])
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+/// Whether `version` is the `requested` version, which may end in `.x` as for [`PackageCache::resolve`].
+fn version_matches(requested: &str, version: &str) -> bool {
+    requested == version
+        || requested
+            .strip_suffix('x')
+            .is_some_and(|prefix| version.starts_with(prefix))
+}
+
+/// Loads the package at `path` and, breadth first, every package it depends on from `cache`.
+/// A package is loaded once by name; other versions of it are skipped with a warning in the schema's
+/// diagnostics.
+pub fn load(path: &Path, cache: &PackageCache) -> Result<Schema> {
+    let mut resources = Schema::default();
+    let mut loaded: HashMap<String, String> = HashMap::new();
+    let mut pending = VecDeque::from([Package::open(path)?]);
+
+    while let Some(package) = pending.pop_front() {
+        let PackageManifest { name, version, .. } = &package.manifest;
+        if let Some(loaded_version) = loaded.get(name) {
+            if loaded_version != version {
+                resources.diagnostics.warning(
+                    &package.id(),
+                    None,
+                    format!("package skipped, {}#{} already loaded", name, loaded_version),
+                );
+            }
+            continue;
+        }
+        loaded.insert(name.clone(), version.clone());
+
+        resources.extend(package.to_schema()?);
+        for (dependency, version) in &package.manifest.dependencies {
+            if let Some(loaded_version) = loaded.get(dependency) {
+                if !version_matches(version, loaded_version) {
+                    resources.diagnostics.warning(
+                        &format!("{}#{}", dependency, version),
+                        None,
+                        format!(
+                            "package skipped, {}#{} already loaded (dependency of {})",
+                            dependency,
+                            loaded_version,
+                            package.id()
+                        ),
+                    );
+                }
+                continue;
+            }
+            let dependency_path = cache
+                .resolve(dependency, version)
+                .with_context(|| format!("dependency of {}", package.id()))?;
+            pending.push_back(Package::open(&dependency_path)?);
+        }
+    }
+    Ok(resources)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use serde_json::json;
+
+    use crate::diagnostics::Severity;
+
+    /// A directory under the system temp dir, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("codegen-package-{}-{}", std::process::id(), name));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn manifest(name: &str, version: &str, dependencies: &[(&str, &str)]) -> (String, Vec<u8>) {
+        let dependencies: BTreeMap<&str, &str> = dependencies.iter().copied().collect();
+        let manifest = json!({"name": name, "version": version, "dependencies": dependencies});
+        ("package/package.json".to_string(), manifest.to_string().into_bytes())
+    }
+
+    fn code_system(path: &str, url: &str) -> (String, Vec<u8>) {
+        let code_system = json!({"resourceType": "CodeSystem", "url": url, "content": "complete"});
+        (path.to_string(), code_system.to_string().into_bytes())
+    }
+
+    fn index(files: &[(&str, &str)]) -> (String, Vec<u8>) {
+        let files: Vec<_> = files
+            .iter()
+            .map(|(filename, resource_type)| json!({"filename": filename, "resourceType": resource_type}))
+            .collect();
+        let index = json!({"index-version": 1, "files": files});
+        ("package/.index.json".to_string(), index.to_string().into_bytes())
+    }
+
+    fn write_tarball(path: &Path, files: &[(String, Vec<u8>)]) {
+        let mut builder = tar::Builder::new(GzEncoder::new(File::create(path).unwrap(), Compression::default()));
+        for (name, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, data.as_slice()).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    fn write_dir(dir: &Path, files: &[(String, Vec<u8>)]) {
+        for (name, data) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, data).unwrap();
+        }
+    }
+
+    fn code_system_urls(schema: &Schema) -> Vec<&str> {
+        let mut urls: Vec<&str> = schema.code_systems.iter().map(|cs| cs.url.as_str()).collect();
+        urls.sort();
+        urls
+    }
+
+    /// A package with files the index lists, files it doesn't, and files outside `package/`.
+    fn package_files(with_index: bool) -> Vec<(String, Vec<u8>)> {
+        let mut files = vec![
+            manifest("example.fhir.core", "1.0.0", &[]),
+            code_system("package/CodeSystem-a.json", "http://example.org/a"),
+            code_system("package/CodeSystem-b.json", "http://example.org/b"),
+            code_system("package/example/CodeSystem-c.json", "http://example.org/c"),
+            code_system("other/CodeSystem-d.json", "http://example.org/d"),
+            (
+                "package/Patient-p.json".to_string(),
+                br#"{"resourceType": "Patient"}"#.to_vec(),
+            ),
+        ];
+        if with_index {
+            files.push(index(&[
+                ("CodeSystem-a.json", "CodeSystem"),
+                ("Patient-p.json", "Patient"),
+            ]));
+        }
+        files
+    }
+
+    #[test]
+    fn tarball_with_index() {
+        let dir = TempDir::new("tarball-with-index");
+        let path = dir.0.join("package.tgz");
+        write_tarball(&path, &package_files(true));
+
+        let package = Package::open(&path).unwrap();
+        assert_eq!(package.id(), "example.fhir.core#1.0.0");
+        assert_eq!(
+            package.definition_filenames().unwrap(),
+            ["CodeSystem-a.json"],
+            "only the index's definitions"
+        );
+        assert_eq!(
+            code_system_urls(&package.to_schema().unwrap()),
+            ["http://example.org/a"]
+        );
+    }
+
+    #[test]
+    fn tarball_without_index() {
+        let dir = TempDir::new("tarball-without-index");
+        let path = dir.0.join("package.tgz");
+        write_tarball(&path, &package_files(false));
+
+        let package = Package::open(&path).unwrap();
+        let schema = package.to_schema().unwrap();
+        assert_eq!(
+            code_system_urls(&schema),
+            ["http://example.org/a", "http://example.org/b"]
+        );
+    }
+
+    #[test]
+    fn unpacked_directory() {
+        let dir = TempDir::new("unpacked");
+        write_dir(&dir.0, &package_files(true));
+
+        for path in [dir.0.clone(), dir.0.join(PACKAGE_DIR)] {
+            let package = Package::open(&path).unwrap();
+            assert_eq!(package.id(), "example.fhir.core#1.0.0");
+            assert_eq!(
+                code_system_urls(&package.to_schema().unwrap()),
+                ["http://example.org/a"]
+            );
+        }
+    }
+
+    #[test]
+    fn missing_manifest_and_indexed_file() {
+        let dir = TempDir::new("missing");
+        let path = dir.0.join("no-manifest.tgz");
+        write_tarball(
+            &path,
+            &[code_system("package/CodeSystem-a.json", "http://example.org/a")],
+        );
+        let err = Package::open(&path).err().unwrap();
+        assert!(err.to_string().contains("no package.json"), "{}", err);
+
+        let path = dir.0.join("missing-file.tgz");
+        write_tarball(
+            &path,
+            &[
+                manifest("example.fhir.core", "1.0.0", &[]),
+                index(&[("CodeSystem-z.json", "CodeSystem")]),
+            ],
+        );
+        let err = Package::open(&path).unwrap().to_schema().err().unwrap();
+        assert!(
+            err.to_string()
+                .contains("CodeSystem-z.json listed in .index.json but missing"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn resolve_versions() {
+        let dir = TempDir::new("resolve");
+        for package in [
+            "hl7.fhir.r4.core#4.0.1",
+            "hl7.fhir.r4.core#4.0.9",
+            "hl7.fhir.r4.core#4.0.10",
+            "hl7.fhir.r4.core#4.1.0",
+            "hl7.fhir.r4.core.extra#4.0.20",
+        ] {
+            std::fs::create_dir_all(dir.0.join(package).join(PACKAGE_DIR)).unwrap();
+        }
+        let cache = PackageCache::new(dir.0.clone());
+        let resolved = |version| {
+            cache
+                .resolve("hl7.fhir.r4.core", version)
+                .map(|path| path.file_name().unwrap().to_owned())
+        };
+
+        assert_eq!(resolved("4.0.1").unwrap(), "hl7.fhir.r4.core#4.0.1");
+        assert_eq!(resolved("4.0.x").unwrap(), "hl7.fhir.r4.core#4.0.10");
+        assert_eq!(resolved("4.1.x").unwrap(), "hl7.fhir.r4.core#4.1.0");
+        assert!(resolved("4.0.2").is_err());
+        assert!(resolved("4.2.x").is_err());
+        assert!(PackageCache::new(dir.0.join("missing"))
+            .resolve("hl7.fhir.r4.core", "4.0.x")
+            .is_err());
+    }
+
+    #[test]
+    fn load_follows_dependencies() {
+        let dir = TempDir::new("dependencies");
+        let cache_dir = dir.0.join("cache");
+        // root depends on a and b; a depends on another version of b, which is skipped.
+        write_dir(
+            &cache_dir.join("example.a#1.0.2"),
+            &[
+                manifest("example.a", "1.0.2", &[("example.b", "3.0.0")]),
+                code_system("package/CodeSystem-a.json", "http://example.org/a"),
+            ],
+        );
+        for version in ["2.0.0", "3.0.0"] {
+            write_dir(
+                &cache_dir.join(format!("example.b#{}", version)),
+                &[
+                    manifest("example.b", version, &[]),
+                    code_system(
+                        "package/CodeSystem-b.json",
+                        &format!("http://example.org/b/{}", version),
+                    ),
+                ],
+            );
+        }
+        let path = dir.0.join("root.tgz");
+        write_tarball(
+            &path,
+            &[
+                manifest(
+                    "example.root",
+                    "0.1.0",
+                    &[("example.a", "1.0.x"), ("example.b", "2.0.0")],
+                ),
+                code_system("package/CodeSystem-root.json", "http://example.org/root"),
+            ],
+        );
+
+        let schema = load(&path, &PackageCache::new(cache_dir)).unwrap();
+        assert_eq!(
+            code_system_urls(&schema),
+            [
+                "http://example.org/a",
+                "http://example.org/b/2.0.0",
+                "http://example.org/root"
+            ]
+        );
+        assert_eq!(schema.diagnostics.count(Severity::Warning), 1);
+        let warning = schema.diagnostics.sorted()[0];
+        assert_eq!(warning.url, "example.b#3.0.0");
+        assert_eq!(warning.message, "package skipped, example.b#2.0.0 already loaded");
+    }
+
+    #[test]
+    fn load_reports_dependency_on_loaded_version() {
+        let dir = TempDir::new("loaded-dependency");
+        let cache_dir = dir.0.join("cache");
+        // root wants b 2.0.x and, through c, a; b is loaded by the time a, which wants b 3.0.0, is.
+        write_dir(
+            &cache_dir.join("example.b#2.0.1"),
+            &[manifest("example.b", "2.0.1", &[("example.root", "0.1.0")])],
+        );
+        write_dir(
+            &cache_dir.join("example.a#1.0.0"),
+            &[manifest(
+                "example.a",
+                "1.0.0",
+                &[("example.b", "3.0.0"), ("example.root", "0.1.0")],
+            )],
+        );
+        let path = dir.0.join("root.tgz");
+        write_tarball(
+            &path,
+            &[manifest(
+                "example.root",
+                "0.1.0",
+                &[("example.b", "2.0.x"), ("example.c", "1.0.0")],
+            )],
+        );
+        write_dir(
+            &cache_dir.join("example.c#1.0.0"),
+            &[manifest("example.c", "1.0.0", &[("example.a", "1.0.0")])],
+        );
+
+        let schema = load(&path, &PackageCache::new(cache_dir)).unwrap();
+        let warnings = schema.diagnostics.sorted();
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert_eq!(warnings[0].url, "example.b#3.0.0");
+        assert_eq!(
+            warnings[0].message,
+            "package skipped, example.b#2.0.1 already loaded (dependency of example.a#1.0.0)"
+        );
+    }
+
+    #[test]
+    fn load_missing_dependency() {
+        let dir = TempDir::new("missing-dependency");
+        let path = dir.0.join("root.tgz");
+        write_tarball(
+            &path,
+            &[manifest("example.root", "0.1.0", &[("example.gone", "1.0.0")])],
+        );
+
+        let err = load(&path, &PackageCache::new(dir.0.join("cache"))).err().unwrap();
+        assert_eq!(err.to_string(), "dependency of example.root#0.1.0");
+        assert!(
+            format!("{:#}", err).contains("package example.gone#1.0.0 not found"),
+            "{:#}",
+            err
+        );
+    }
+}
@@ -1,44 +1,725 @@
 use std::collections::{HashMap, HashSet};
-use std::io::BufReader;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::Path;
 
 use anyhow::{anyhow, Context, Result};
 use convert_case::{Case, Casing};
-use lazy_static::lazy_static;
 use serde::{de::Visitor, Deserialize};
 use zip::ZipArchive;
 
-lazy_static! {
-    #[rustfmt::skip]
-    static ref STRUCTURE_DEFINITION_RENAMES: HashMap<&'static str, &'static str> = [
-        ("http://hl7.org/fhir/StructureDefinition/valueset-reference", "ValueSetReference"),
-        ("http://hl7.org/fhir/StructureDefinition/codesystem-reference", "CodeSystemReference"),
-        ("http://hl7.org/fhir/StructureDefinition/request-statusReason", "StatusReasonExtension"),
-        ("http://hl7.org/fhir/StructureDefinition/workflow-relatedArtifact", "RelatedArtifactExtension"),
-        ("http://hl7.org/fhir/StructureDefinition/event-location", "LocationExtension"),
-        ("http://hl7.org/fhir/StructureDefinition/workflow-episodeOfCare", "EpisodeOfCareExtension"),
-        ("http://hl7.org/fhir/StructureDefinition/workflow-researchStudy", "ResearchStudyExtension"),
-        ("http://hl7.org/fhir/us/core/StructureDefinition/us-core-condition", "UsCoreCondition"),
-        ("http://hl7.org/fhir/us/core/StructureDefinition/us-core-direct", "UsCoreDirectEmail"),
-        ("http://hl7.org/fhir/StructureDefinition/hdlcholesterol", "HdlCholesterol"),
-        ("http://hl7.org/fhir/StructureDefinition/ldlcholesterol", "LdlCholesterol"),
-        ("http://hl7.org/fhir/StructureDefinition/lipidprofile", "LipidProfile"),
-        ("http://hl7.org/fhir/StructureDefinition/cholesterol", "Cholesterol"),
-        ("http://hl7.org/fhir/StructureDefinition/triglyceride", "Triglyceride"),
-        ("http://hl7.org/fhir/StructureDefinition/cqf-expression", "CqfExpression"),
-        ("http://hl7.org/fhir/StructureDefinition/cqf-library", "CqfLibrary"),
-    ]
-    .iter()
-    .copied()
-    .collect();
-}
+use crate::diagnostics::Diagnostics;
 
+const CORE_STRUCTURE_DEFINITION_URL_PREFIX: &str = "http://hl7.org/fhir/StructureDefinition/";
 const BUNDLE_STRUCTURE_DEFINITION_URL: &str = "http://hl7.org/fhir/StructureDefinition/Bundle";
+pub const EXTENSION_STRUCTURE_DEFINITION_URL: &str = "http://hl7.org/fhir/StructureDefinition/Extension";
 const EXPLICIT_TYPE_NAME_EXTENSION_URL: &str =
     "http://hl7.org/fhir/StructureDefinition/structuredefinition-explicit-type-name";
+const IMPLEMENTS_EXTENSION_URL: &str = "http://hl7.org/fhir/StructureDefinition/structuredefinition-implements";
+const FHIR_TYPE_EXTENSION_URL: &str = "http://hl7.org/fhir/StructureDefinition/structuredefinition-fhir-type";
+const FHIRPATH_SYSTEM_TYPE_PREFIX: &str = "http://hl7.org/fhirpath/System.";
 
-#[derive(Debug)]
+/// Abstract resources a `Reference` may target, standing for any resource derived from them.
+const ABSTRACT_RESOURCE_NAMES: &[&str] = &["Resource", "DomainResource", "CanonicalResource", "MetadataResource"];
+/// The most targets a `TypedReference` spells out as a tuple; wider references accept any resource.
+pub const MAX_REFERENCE_TARGETS: usize = 12;
+
+/// Codes of the FHIR primitive types, each of which has a counterpart in `fhir::primitives`.
+pub const PRIMITIVE_TYPE_CODES: &[&str] = &[
+    "base64Binary",
+    "boolean",
+    "canonical",
+    "code",
+    "date",
+    "dateTime",
+    "decimal",
+    "id",
+    "instant",
+    "integer",
+    "integer64",
+    "markdown",
+    "oid",
+    "positiveInt",
+    "string",
+    "time",
+    "unsignedInt",
+    "uri",
+    "url",
+    "uuid",
+    "xhtml",
+];
+
+#[derive(Debug, Default)]
 pub struct Schema {
     pub structures_definitions: Vec<StructureDefinition>,
+    pub value_sets: Vec<ValueSet>,
+    pub code_systems: Vec<CodeSystem>,
+    /// Problems found loading the definitions and by [`Schema::remove_unsupported`].
+    pub diagnostics: Diagnostics,
+}
+
+impl Schema {
+    pub fn get_structure_definition(&self, url: &str) -> Option<&StructureDefinition> {
+        self.structures_definitions.iter().find(|sd| sd.url == url)
+    }
+
+    /// The core definition of the type or resource `code`, e.g. `Patient`.
+    pub fn get_type_definition(&self, code: &str) -> Option<&StructureDefinition> {
+        self.get_structure_definition(&format!("{}{}", CORE_STRUCTURE_DEFINITION_URL_PREFIX, code))
+    }
+
+    /// Looks up a value set by canonical URL, ignoring any `|version` suffix.
+    pub fn get_value_set(&self, url: &str) -> Option<&ValueSet> {
+        let url = strip_canonical_version(url);
+        self.value_sets.iter().find(|vs| vs.url == url)
+    }
+
+    /// Looks up a code system by canonical URL, ignoring any `|version` suffix.
+    pub fn get_code_system(&self, url: &str) -> Option<&CodeSystem> {
+        let url = strip_canonical_version(url);
+        self.code_systems.iter().find(|cs| cs.url == url)
+    }
+
+    /// Orders the definitions by canonical URL, so that what is generated from them doesn't depend on
+    /// the order they were loaded in.
+    pub fn sort(&mut self) {
+        self.structures_definitions.sort_by(|a, b| a.url.cmp(&b.url));
+        self.value_sets.sort_by(|a, b| a.url.cmp(&b.url));
+        self.code_systems.sort_by(|a, b| a.url.cmp(&b.url));
+    }
+
+    /// Merges `other` into this schema; definitions whose canonical URL is already present are skipped.
+    pub fn extend(&mut self, other: Schema) {
+        let mut urls: HashSet<String> = self.structures_definitions.iter().map(|sd| sd.url.clone()).collect();
+        for structure_definition in other.structures_definitions {
+            if urls.insert(structure_definition.url.clone()) {
+                self.structures_definitions.push(structure_definition);
+            }
+        }
+        let mut urls: HashSet<String> = self.value_sets.iter().map(|vs| vs.url.clone()).collect();
+        for value_set in other.value_sets {
+            if urls.insert(value_set.url.clone()) {
+                self.value_sets.push(value_set);
+            }
+        }
+        let mut urls: HashSet<String> = self.code_systems.iter().map(|cs| cs.url.clone()).collect();
+        for code_system in other.code_systems {
+            if urls.insert(code_system.url.clone()) {
+                self.code_systems.push(code_system);
+            }
+        }
+        self.diagnostics.extend(other.diagnostics);
+    }
+
+    /// Records the names the config gives generated types, by canonical URL, and fields, by element
+    /// id, on the definitions.
+    pub fn apply_renames(&mut self, type_names: &HashMap<String, String>, field_names: &HashMap<String, String>) {
+        for def in &mut self.structures_definitions {
+            def.type_name = type_names.get(&def.url).cloned();
+            if def.is_resource() || def.is_datatype() {
+                for el in &mut def.snapshot.element {
+                    el.field_name = field_names.get(&el.id).cloned();
+                }
+            }
+        }
+    }
+
+    /// Reports the definitions and elements that can't be generated and removes them, so that the
+    /// rest of the schema still can be. Removing an element removes its children and slices too.
+    pub fn remove_unsupported(&mut self) {
+        let defined: HashSet<&str> = self.structures_definitions.iter().map(|d| d.url.as_str()).collect();
+        let mut diagnostics = Diagnostics::default();
+        let mut type_names: HashMap<String, &str> = HashMap::new();
+        let mut removed_definitions: HashSet<String> = HashSet::new();
+        let mut removed_elements: Vec<(usize, Vec<String>)> = Vec::new();
+        for (i, def) in self.structures_definitions.iter().enumerate() {
+            let typed = def.is_resource() || def.is_datatype();
+            if typed {
+                let name = def.get_structure_type_name();
+                if let Some(other) = type_names.get(&name) {
+                    let message = format!("generates the type `{}`, as does {}", name, other);
+                    diagnostics.error(&def.url, None, message);
+                    removed_definitions.insert(def.url.clone());
+                    continue;
+                }
+                type_names.insert(name, &def.url);
+            } else if def.is_resource_profile() {
+                if !self.get_type_definition(&def.r#type).is_some_and(|d| d.is_resource()) {
+                    let message = format!(
+                        "profiles `{}`, which isn't generated, so the profile isn't either",
+                        def.r#type
+                    );
+                    diagnostics.warning(&def.url, None, message);
+                    continue;
+                }
+            } else if !def.is_extension() {
+                continue;
+            }
+
+            let mut removed: Vec<String> = Vec::new();
+            for el in def.snapshot.element.iter().skip(1) {
+                if removed.iter().any(|id| is_element_within(&el.id, id)) {
+                    continue;
+                }
+                if let Some(message) = def.get_unsupported_reason(el, typed, &defined) {
+                    diagnostics.error(&def.url, Some(&el.id), message);
+                    removed.push(el.id.clone());
+                }
+            }
+            if !removed.is_empty() {
+                removed_elements.push((i, removed));
+            }
+        }
+
+        for (i, removed) in removed_elements {
+            let elements = &mut self.structures_definitions[i].snapshot.element;
+            elements.retain(|el| !removed.iter().any(|id| is_element_within(&el.id, id)));
+        }
+        self.structures_definitions
+            .retain(|d| !removed_definitions.contains(&d.url));
+        self.diagnostics.extend(diagnostics);
+    }
+
+    /// The codes of a value set, or `None` if they can't be enumerated from the loaded definitions,
+    /// e.g. because the value set selects codes by filter or from a code system that isn't loaded.
+    pub fn expand_value_set(&self, url: &str) -> Option<Vec<Concept>> {
+        self.expand_value_set_nested(url, 0)
+    }
+
+    fn expand_value_set_nested(&self, url: &str, depth: usize) -> Option<Vec<Concept>> {
+        // Value sets may include each other; give up on cycles and absurdly deep nesting.
+        if depth > 8 {
+            return None;
+        }
+        let value_set = self.get_value_set(url)?;
+        let mut concepts = match (&value_set.compose, &value_set.expansion) {
+            (Some(compose), _) => {
+                let mut concepts = Vec::new();
+                for include in &compose.include {
+                    concepts.extend(self.expand_concept_set(include, depth)?);
+                }
+                for exclude in &compose.exclude {
+                    let excluded = self.expand_concept_set(exclude, depth)?;
+                    concepts.retain(|c| !excluded.iter().any(|e| e.system == c.system && e.code == c.code));
+                }
+                concepts
+            }
+            (None, Some(expansion)) => {
+                let mut concepts = Vec::new();
+                let mut pending: Vec<&ValueSetContains> = expansion.contains.iter().rev().collect();
+                while let Some(contains) = pending.pop() {
+                    if let (Some(system), Some(code)) = (&contains.system, &contains.code) {
+                        concepts.push(Concept {
+                            system: system.clone(),
+                            code: code.clone(),
+                            display: contains.display.clone(),
+                            definition: None,
+                        });
+                    }
+                    pending.extend(contains.contains.iter().rev());
+                }
+                concepts
+            }
+            (None, None) => return None,
+        };
+        let mut seen = HashSet::new();
+        concepts.retain(|c| seen.insert((c.system.clone(), c.code.clone())));
+        (!concepts.is_empty()).then_some(concepts)
+    }
+
+    /// The codes one `compose.include` or `compose.exclude` entry selects.
+    fn expand_concept_set(&self, set: &ConceptSet, depth: usize) -> Option<Vec<Concept>> {
+        if !set.filter.is_empty() {
+            return None;
+        }
+        match set.system {
+            // The entry's value sets and system intersect; we only handle either on its own.
+            Some(_) if !set.value_set.is_empty() => None,
+            Some(ref system) if set.concept.is_empty() => self.get_code_system(system)?.get_concepts(),
+            Some(ref system) => {
+                let code_system = self.get_code_system(system);
+                let concepts = set
+                    .concept
+                    .iter()
+                    .map(|c| {
+                        let defined = code_system.and_then(|cs| cs.get_concept(&c.code));
+                        Concept {
+                            system: system.clone(),
+                            code: c.code.clone(),
+                            display: c.display.clone().or_else(|| defined.and_then(|d| d.display.clone())),
+                            definition: defined.and_then(|d| d.definition.clone()),
+                        }
+                    })
+                    .collect();
+                Some(concepts)
+            }
+            None => {
+                let (first, rest) = set.value_set.split_first()?;
+                let mut concepts = self.expand_value_set_nested(first, depth + 1)?;
+                for url in rest {
+                    let other = self.expand_value_set_nested(url, depth + 1)?;
+                    concepts.retain(|c| other.iter().any(|o| o.system == c.system && o.code == c.code));
+                }
+                Some(concepts)
+            }
+        }
+    }
+
+    /// The other resources whose generated types `def`'s type needs, directly or through the data
+    /// types it uses. References and `Resource` fields need none: reference targets that aren't
+    /// generated have stand-ins, and `Resource` only has variants for those that are.
+    pub fn get_resource_dependencies<'a>(&'a self, def: &'a StructureDefinition) -> Vec<&'a StructureDefinition> {
+        let types: HashMap<&str, &StructureDefinition> = self
+            .structures_definitions
+            .iter()
+            .filter(|d| d.is_resource() || d.is_datatype())
+            .map(|d| (d.r#type.as_str(), d))
+            .collect();
+        let mut seen: HashSet<&str> = HashSet::from([def.r#type.as_str()]);
+        let mut queue = vec![def];
+        let mut dependencies = Vec::new();
+        while let Some(def) = queue.pop() {
+            let codes = def
+                .snapshot
+                .element
+                .iter()
+                .flat_map(|el| &el.r#type)
+                .map(|t| t.code.as_str());
+            for code in codes.filter(|code| *code != "Resource") {
+                let Some(used) = types.get(code) else {
+                    continue;
+                };
+                if seen.insert(code) {
+                    if used.is_resource() {
+                        dependencies.push(*used);
+                    }
+                    queue.push(used);
+                }
+            }
+        }
+        dependencies.sort_by_key(|d| d.get_feature_name());
+        dependencies
+    }
+
+    /// Boxes the fields, or choice variants, that would otherwise make a generated type contain
+    /// itself, e.g. through `Bundle.issues`, a `Resource` that may be another `Bundle`. Elements whose
+    /// `contentReference` names a typed element first take that element's types.
+    pub fn resolve_recursive_types(&mut self) {
+        for def in &mut self.structures_definitions {
+            def.inline_content_references();
+        }
+
+        let mut graph = TypeGraph::default();
+        // Starting from `Resource` puts its variants on the search tree, so the edges that close
+        // cycles through it are the fields holding a `Resource`, not its variants.
+        graph.add_node("Resource".to_string());
+        let aliases: HashMap<String, String> = self
+            .structures_definitions
+            .iter()
+            .filter(|d| d.is_datatype_profile())
+            .map(|d| (d.id.clone(), d.r#type.clone()))
+            .collect();
+        let generated = |d: &&StructureDefinition| d.is_resource() || d.is_datatype();
+        for def in self.structures_definitions.iter().filter(generated) {
+            for el in def.snapshot.element.iter().filter(|e| def.is_struct_element(e)) {
+                graph.add_node(def.get_container_type_name(el));
+            }
+            if def.is_resource() {
+                graph.add_edge("Resource", def.get_structure_type_name(), None);
+            }
+        }
+
+        let get_node = |type_name: String| -> Option<String> {
+            let type_name = match type_name.split_once('<') {
+                Some(("TypedReference", _)) => "Reference".to_string(),
+                None if type_name == "TypedReference" => "Reference".to_string(),
+                _ => type_name,
+            };
+            let type_name = aliases.get(&type_name).cloned().unwrap_or(type_name);
+            graph.edges.contains_key(&type_name).then_some(type_name)
+        };
+        let mut edges: Vec<(String, String, TypeEdgeSource)> = Vec::new();
+        for (i, def) in self
+            .structures_definitions
+            .iter()
+            .enumerate()
+            .filter(|(_, d)| generated(d))
+        {
+            for el in def.snapshot.element.iter().filter(|e| def.is_struct_element(e)) {
+                let from = def.get_container_type_name(el);
+                for child in def.get_direct_children(el) {
+                    if !matches!(child.get_cardinality(), Cardinality::Optional | Cardinality::Required) {
+                        continue;
+                    }
+                    let Some(element) = def.snapshot.element.iter().position(|e| std::ptr::eq(e, child)) else {
+                        continue;
+                    };
+                    if child.is_choice_type() {
+                        for r#type in child.get_choice_types() {
+                            if let Some(to) = get_node(def.get_type_name(r#type)) {
+                                let source = TypeEdgeSource {
+                                    definition: i,
+                                    element,
+                                    choice_type: Some(r#type.code.clone()),
+                                };
+                                edges.push((from.clone(), to, source));
+                            }
+                        }
+                    } else if let Some(to) = get_node(def.get_element_type_name(child)) {
+                        let source = TypeEdgeSource {
+                            definition: i,
+                            element,
+                            choice_type: None,
+                        };
+                        edges.push((from.clone(), to, source));
+                    }
+                }
+            }
+        }
+        for (from, to, source) in edges {
+            graph.add_edge(&from, to, Some(source));
+        }
+
+        for source in graph.get_back_edges() {
+            let el = &mut self.structures_definitions[source.definition].snapshot.element[source.element];
+            match source.choice_type {
+                Some(code) => el
+                    .r#type
+                    .iter_mut()
+                    .filter(|t| t.code == code)
+                    .for_each(|t| t.boxed = true),
+                None => el.boxed = true,
+            }
+        }
+    }
+
+    /// Picks the value sets bound to `code` elements whose codes can be enumerated, names an enum
+    /// for each and records that name on the bindings, where [`StructureDefinition::get_element_type_name`]
+    /// finds it. Returns the enums sorted by name.
+    pub fn resolve_code_bindings(&mut self) -> Vec<CodeEnum> {
+        let mut enums: Vec<CodeEnum> = Vec::new();
+        let mut names: HashMap<String, Option<String>> = HashMap::new();
+        let mut taken: HashSet<String> = HashSet::new();
+        for def in self
+            .structures_definitions
+            .iter()
+            .filter(|d| d.is_resource() || d.is_datatype())
+        {
+            for el in &def.snapshot.element {
+                let Some(url) = el.get_code_binding().and_then(|b| b.value_set.as_deref()) else {
+                    continue;
+                };
+                let url = strip_canonical_version(url);
+                if names.contains_key(url) {
+                    continue;
+                }
+                let name = match (self.get_value_set(url), self.expand_value_set(url)) {
+                    (Some(value_set), Some(concepts)) => {
+                        let base_name = value_set.get_type_name();
+                        let mut name = base_name.clone();
+                        let mut suffix = 2;
+                        while !taken.insert(name.clone()) {
+                            name = format!("{}{}", base_name, suffix);
+                            suffix += 1;
+                        }
+                        enums.push(CodeEnum {
+                            name: name.clone(),
+                            url: url.to_string(),
+                            title: value_set.title.clone().or_else(|| value_set.name.clone()),
+                            concepts,
+                        });
+                        Some(name)
+                    }
+                    _ => None,
+                };
+                names.insert(url.to_string(), name);
+            }
+        }
+
+        for def in &mut self.structures_definitions {
+            for el in &mut def.snapshot.element {
+                let Some(url) = el.get_code_binding().and_then(|b| b.value_set.as_deref()) else {
+                    continue;
+                };
+                let type_name = names.get(strip_canonical_version(url)).cloned().flatten();
+                if let Some(ref mut binding) = el.binding {
+                    binding.type_name = type_name;
+                }
+            }
+        }
+
+        enums.sort_by(|a, b| a.name.cmp(&b.name));
+        enums
+    }
+}
+
+/// Where a graph edge comes from: the field at `element` of definition `definition`, or one variant
+/// of it if the field is a choice.
+#[derive(Debug, Clone)]
+struct TypeEdgeSource {
+    definition: usize,
+    element: usize,
+    choice_type: Option<String>,
+}
+
+/// Which generated types hold which others inline. Only single-valued fields count: a `Vec`
+/// already puts its items on the heap.
+#[derive(Default)]
+struct TypeGraph {
+    nodes: Vec<String>,
+    /// Edges out of each node, with the field behind each; `Resource`'s variants have none.
+    edges: HashMap<String, Vec<(String, Option<TypeEdgeSource>)>>,
+}
+
+impl TypeGraph {
+    fn add_node(&mut self, name: String) {
+        if !self.edges.contains_key(&name) {
+            self.edges.insert(name.clone(), Vec::new());
+            self.nodes.push(name);
+        }
+    }
+
+    fn add_edge(&mut self, from: &str, to: String, source: Option<TypeEdgeSource>) {
+        self.add_node(from.to_string());
+        self.edges.entry(from.to_string()).or_default().push((to, source));
+    }
+
+    /// Sources of the edges whose target is still on the depth-first search's stack, i.e. the
+    /// edges that close a cycle. Boxing them leaves no cycle behind.
+    fn get_back_edges(&self) -> Vec<TypeEdgeSource> {
+        #[derive(PartialEq)]
+        enum State {
+            OnStack,
+            Done,
+        }
+
+        let mut states: HashMap<&str, State> = HashMap::new();
+        let mut back_edges = Vec::new();
+        for root in &self.nodes {
+            if states.contains_key(root.as_str()) {
+                continue;
+            }
+            // An explicit stack of (node, index of the next edge to follow), since the graph of a
+            // whole release is deep enough to worry about recursion.
+            states.insert(root, State::OnStack);
+            let mut stack: Vec<(&str, usize)> = vec![(root, 0)];
+            while let Some((node, next)) = stack.last_mut() {
+                let edges = &self.edges[*node];
+                let Some((target, source)) = edges.get(*next) else {
+                    states.insert(node, State::Done);
+                    stack.pop();
+                    continue;
+                };
+                *next += 1;
+                match states.get(target.as_str()) {
+                    None => {
+                        states.insert(target, State::OnStack);
+                        stack.push((target, 0));
+                    }
+                    // Edges out of `Resource` have no field to box; they can only close a cycle if
+                    // a generated type is itself named `Resource`.
+                    Some(State::OnStack) => back_edges.extend(source.clone()),
+                    Some(State::Done) => {}
+                }
+            }
+        }
+        back_edges
+    }
+}
+
+/// Field names that are Rust keywords become raw identifiers, or get an underscore where raw
+/// identifiers aren't allowed.
+fn escape_keyword(name: String) -> String {
+    match name.as_str() {
+        "self" | "super" | "crate" => format!("{}_", name),
+        "abstract" | "as" | "async" | "await" | "become" | "box" | "break" | "const" | "continue" | "do" | "dyn"
+        | "else" | "enum" | "extern" | "false" | "final" | "fn" | "for" | "gen" | "if" | "impl" | "in" | "let"
+        | "loop" | "macro" | "match" | "mod" | "move" | "mut" | "override" | "priv" | "pub" | "ref" | "return"
+        | "static" | "struct" | "trait" | "true" | "try" | "type" | "typeof" | "unsafe" | "unsized" | "use"
+        | "virtual" | "where" | "while" | "yield" => format!("r#{}", name),
+        _ => name,
+    }
+}
+
+/// `http://hl7.org/fhir/ValueSet/x|5.0.0` without the `|5.0.0`.
+fn strip_canonical_version(url: &str) -> &str {
+    url.split_once('|').map_or(url, |(url, _)| url)
+}
+
+/// Whether `id` is the element `ancestor`, one of its descendants or one of its slices.
+fn is_element_within(id: &str, ancestor: &str) -> bool {
+    id.strip_prefix(ancestor)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(['.', ':']))
+}
+
+/// A value set generated as an enum of its codes.
+#[derive(Debug)]
+pub struct CodeEnum {
+    pub name: String,
+    pub url: String,
+    pub title: Option<String>,
+    pub concepts: Vec<Concept>,
+}
+
+/// A code with its code system, as enumerated from a value set.
+#[derive(Debug, Clone)]
+pub struct Concept {
+    pub system: String,
+    pub code: String,
+    pub display: Option<String>,
+    pub definition: Option<String>,
+}
+
+impl Concept {
+    /// Name of the enum variant for the code: the code's words Pascal-cased, e.g. `EnteredInError`
+    /// for `entered-in-error`, else the display's for symbolic codes such as `<=`. Numeric codes keep
+    /// their parts apart, e.g. `V4_0_1`.
+    pub fn get_variant_name(&self) -> String {
+        let words = |s: &str| -> Vec<String> {
+            s.split(|c: char| !c.is_ascii_alphanumeric())
+                .filter(|w| !w.is_empty())
+                .map(|w| w.to_case(Case::Pascal))
+                .collect()
+        };
+        let mut parts = words(&self.code);
+        if parts.is_empty() {
+            parts = self.display.as_deref().map(words).unwrap_or_default();
+        }
+        let name = match parts.first() {
+            None => "Code".to_string(),
+            Some(first) if first.starts_with(|c: char| c.is_ascii_digit()) => format!("V{}", parts.join("_")),
+            Some(_) => parts.concat(),
+        };
+        match name.as_str() {
+            "Self" => "Self_".to_string(),
+            _ => name,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ValueSet {
+    pub id: String,
+    pub url: String,
+    pub name: Option<String>,
+    pub title: Option<String>,
+    pub compose: Option<ValueSetCompose>,
+    pub expansion: Option<ValueSetExpansion>,
+}
+
+impl ValueSet {
+    /// Name of the enum generated for the value set, e.g. `AdministrativeGender`.
+    pub fn get_type_name(&self) -> String {
+        let name = self.name.as_deref().unwrap_or(&self.id);
+        let name: String = name
+            .to_case(Case::Pascal)
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect();
+        if name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            name
+        } else {
+            format!("V{}", name)
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ValueSetCompose {
+    #[serde(default)]
+    pub include: Vec<ConceptSet>,
+    #[serde(default)]
+    pub exclude: Vec<ConceptSet>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConceptSet {
+    pub system: Option<String>,
+    #[serde(default)]
+    pub concept: Vec<ConceptReference>,
+    #[serde(default)]
+    pub filter: Vec<serde_json::Value>,
+    #[serde(default)]
+    pub value_set: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConceptReference {
+    pub code: String,
+    pub display: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ValueSetExpansion {
+    #[serde(default)]
+    pub contains: Vec<ValueSetContains>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ValueSetContains {
+    pub system: Option<String>,
+    pub code: Option<String>,
+    pub display: Option<String>,
+    #[serde(default)]
+    pub contains: Vec<ValueSetContains>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeSystem {
+    pub url: String,
+    pub content: Option<String>,
+    #[serde(default)]
+    pub concept: Vec<CodeSystemConcept>,
+}
+
+impl CodeSystem {
+    /// Every code the system defines, flattening the hierarchy, or `None` if the definition only
+    /// carries some of them.
+    pub fn get_concepts(&self) -> Option<Vec<Concept>> {
+        if self.content.as_deref() != Some("complete") {
+            return None;
+        }
+        let mut concepts = Vec::new();
+        let mut pending: Vec<&CodeSystemConcept> = self.concept.iter().rev().collect();
+        while let Some(concept) = pending.pop() {
+            concepts.push(Concept {
+                system: self.url.clone(),
+                code: concept.code.clone(),
+                display: concept.display.clone(),
+                definition: concept.definition.clone(),
+            });
+            pending.extend(concept.concept.iter().rev());
+        }
+        Some(concepts)
+    }
+
+    fn get_concept(&self, code: &str) -> Option<&CodeSystemConcept> {
+        let mut pending: Vec<&CodeSystemConcept> = self.concept.iter().collect();
+        while let Some(concept) = pending.pop() {
+            if concept.code == code {
+                return Some(concept);
+            }
+            pending.extend(concept.concept.iter());
+        }
+        None
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeSystemConcept {
+    pub code: String,
+    pub display: Option<String>,
+    pub definition: Option<String>,
+    #[serde(default)]
+    pub concept: Vec<CodeSystemConcept>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -46,106 +727,297 @@ pub struct Schema {
 pub struct StructureDefinition {
     pub id: String,
     pub url: String,
+    pub name: Option<String>,
     pub kind: StructureDefinitionKind,
+    pub r#type: String,
     pub derivation: Option<TypeDerivationRule>,
     pub r#abstract: bool,
+    pub base_definition: Option<String>,
+    pub fhir_version: Option<String>,
     pub snapshot: Snapshot,
     pub extension: Option<Vec<Extension>>,
+    /// Name of the generated type given in the config, set by [`Schema::apply_renames`].
+    #[serde(skip)]
+    pub type_name: Option<String>,
 }
 
 impl StructureDefinition {
+    /// A concrete resource, i.e. one that gets its own struct.
+    pub fn is_resource(&self) -> bool {
+        self.kind == StructureDefinitionKind::Resource && self.is_concrete_specialization()
+    }
+
+    /// A concrete complex data type such as `HumanName` or `Dosage`.
+    pub fn is_datatype(&self) -> bool {
+        self.kind == StructureDefinitionKind::ComplexType && self.is_concrete_specialization()
+    }
+
+    /// A core constraint on a data type that other definitions use as a type code, e.g.
+    /// `SimpleQuantity` or `Duration` on `Quantity`.
+    pub fn is_datatype_profile(&self) -> bool {
+        self.kind == StructureDefinitionKind::ComplexType
+            && self.derivation == Some(TypeDerivationRule::Constraint)
+            && self.r#type != "Extension"
+            && self.url == format!("{}{}", CORE_STRUCTURE_DEFINITION_URL_PREFIX, self.id)
+            && self.id.starts_with(|c: char| c.is_ascii_uppercase())
+            && self.id.chars().all(|c| c.is_ascii_alphanumeric())
+    }
+
+    /// An extension definition, i.e. a constraint on `Extension` fixing its `url`.
+    pub fn is_extension(&self) -> bool {
+        self.kind == StructureDefinitionKind::ComplexType
+            && self.derivation == Some(TypeDerivationRule::Constraint)
+            && self.r#type == "Extension"
+    }
+
+    /// A profile on a resource, e.g. US Core Patient, which gets a wrapper type checking conformance.
+    pub fn is_resource_profile(&self) -> bool {
+        self.kind == StructureDefinitionKind::Resource
+            && self.derivation == Some(TypeDerivationRule::Constraint)
+            && !self.r#abstract
+    }
+
+    pub fn is_bundle(&self) -> bool {
+        self.url == BUNDLE_STRUCTURE_DEFINITION_URL
+    }
+
+    /// An abstract resource such as `DomainResource`, or an interface such as `CanonicalResource`.
+    pub fn is_abstract_resource(&self) -> bool {
+        self.kind == StructureDefinitionKind::Resource && self.r#abstract
+    }
+
+    /// Canonical URLs of the interfaces the definition declares it implements, outside its
+    /// `baseDefinition` chain.
+    pub fn get_implements(&self) -> Vec<&str> {
+        self.extension
+            .iter()
+            .flatten()
+            .filter_map(|e| match e.value {
+                ExtensionValue::Uri(ref url) | ExtensionValue::Canonical(ref url)
+                    if e.url == IMPLEMENTS_EXTENSION_URL =>
+                {
+                    Some(url.as_str())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn is_concrete_specialization(&self) -> bool {
+        self.derivation == Some(TypeDerivationRule::Specialization) && !self.r#abstract
+    }
+
     pub fn get_structure_type_name(&self) -> String {
-        self.get_structure_name().to_case(Case::Pascal)
+        let name = self.get_structure_name().to_case(Case::Pascal);
+        name.chars().filter(|c| c.is_ascii_alphanumeric()).collect()
     }
 
     pub fn get_structure_field_name(&self) -> String {
-        self.get_structure_name().to_case(Case::Snake)
+        let name = self.get_structure_name().to_case(Case::Snake);
+        name.chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .collect()
+    }
+
+    /// Name of the cargo feature gating a resource's module, e.g. `medication-request`.
+    pub fn get_feature_name(&self) -> String {
+        self.get_structure_field_name().replace('_', "-")
+    }
+
+    /// Where the pages relative links in the definition's markdown point at are: the spec of its
+    /// FHIR version for a core definition, else the implementation guide its canonical URL is in,
+    /// e.g. `http://hl7.org/fhir/us/core/`.
+    pub fn get_web_base(&self) -> &str {
+        if self.url.starts_with(CORE_STRUCTURE_DEFINITION_URL_PREFIX) {
+            return match self.fhir_version.as_deref() {
+                Some(v) if v.starts_with("4.0.") => "https://hl7.org/fhir/R4/",
+                Some(v) if v.starts_with("4.3.") => "https://hl7.org/fhir/R4B/",
+                Some(v) if v.starts_with("5.0.") => "https://hl7.org/fhir/R5/",
+                Some(_) => "https://build.fhir.org/",
+                None => "https://hl7.org/fhir/",
+            };
+        }
+        match self.url.find("StructureDefinition/") {
+            Some(i) => &self.url[..i],
+            None => &self.url,
+        }
+    }
+
+    /// The page documenting `el`: its entry among the core resource's or data type's definitions in
+    /// the spec, else the definition's canonical URL, which resolves to its page.
+    pub fn get_spec_url(&self, el: &ElementDefinition) -> String {
+        if !self.url.starts_with(CORE_STRUCTURE_DEFINITION_URL_PREFIX) {
+            return self.url.clone();
+        }
+        let page = if self.is_resource() {
+            self.r#type.to_lowercase()
+        } else if self.is_datatype() {
+            match self.r#type.as_str() {
+                "Reference" => "references",
+                "Narrative" => "narrative",
+                "Extension" => "extensibility",
+                "Meta" => "resource",
+                "Dosage" => "dosage",
+                "ElementDefinition" => "elementdefinition",
+                "ContactDetail"
+                | "Contributor"
+                | "DataRequirement"
+                | "Expression"
+                | "ParameterDefinition"
+                | "RelatedArtifact"
+                | "TriggerDefinition"
+                | "UsageContext"
+                | "ExtendedContactDetail"
+                | "Availability"
+                | "VirtualServiceDetail"
+                | "MonetaryComponent" => "metadatatypes",
+                _ => "datatypes",
+            }
+            .to_string()
+        } else {
+            return self.url.clone();
+        };
+        // The pages' anchors spell choice elements `value_x_` rather than `value[x]`.
+        let anchor = el.id.replace("[x]", "_x_");
+        format!("{}{}-definitions.html#{}", self.get_web_base(), page, anchor)
     }
 
     fn get_structure_name(&self) -> &str {
-        STRUCTURE_DEFINITION_RENAMES
-            .get(self.url.as_str())
-            .map(|s| *s)
-            .unwrap_or_else(|| {
-                if self.derivation == Some(TypeDerivationRule::Constraint) {
-                    panic!("constraint: {}", self.id)
-                } else {
-                    self.id.as_str()
-                }
-            })
+        if let Some(ref type_name) = self.type_name {
+            return type_name;
+        }
+        // Profile ids are often URL slugs, their computable names make better type names.
+        match self.name {
+            Some(ref name) if self.is_resource_profile() => name.as_str(),
+            _ => self.id.as_str(),
+        }
     }
 
     pub fn get_element_type_name(&self, el: &ElementDefinition) -> String {
-        if !el.is_choice_type()
-            && el
-                .r#type
-                .get(0)
-                // .as_ref()
-                // .and_then(|t| t.get(0))
-                .map(|t| t.code.as_str())
-                .unwrap_or_default()
-                == "Reference"
-        {
+        if el.is_choice_type() {
+            return self.get_choice_type_name(el);
+        }
+        if el.content_reference.is_some() || el.is_container() {
             return self.get_container_type_name(el);
         }
-
-        if let Some(r#type) = el.r#type.get(0) {
-            match r#type.code.as_str() {
-                "http://hl7.org/fhirpath/System.String" | "string" => return "String".to_string(),
-                _ => {}
+        if let Some(binding) = el.get_code_binding() {
+            if let Some(ref type_name) = binding.type_name {
+                return self.get_code_type_name(el, binding, type_name);
             }
         }
-
-        // if el.is_choice_type() || el.is_container() {
-        //     self.get_container_type_name(el)
-        // } else {
-        //     assert!(el.r#type.len() == 1);
-        //     let code = &el.r#type[0].code;
-        //     if code == "http://hl7.org/fhirpath/System.String" {
-        //         return "String".to_string();
-        //     }
-        //     self.get_container_type_name(el)
-        //     // unimplemented!()
-        // }
-        self.get_container_type_name(el)
+        match el.r#type.first() {
+            Some(r#type) => self.get_type_name(r#type),
+            None => self.get_container_type_name(el),
+        }
     }
 
+    /// Name of the struct generated for a resource, data type or backbone element. Backbone elements
+    /// are prefixed with the structure's name, e.g. `PatientContact`, since their explicit type names
+    /// are only unique within one structure.
     pub fn get_container_type_name(&self, el: &ElementDefinition) -> String {
-        match el.content_reference {
-            Some(ref content_reference) => {
-                let referenced_id = content_reference[1..].to_string();
-                let referenced_el = self
-                    .get_element_by_id(&referenced_id)
-                    .expect("referenced element not found");
-                if !referenced_el.is_container() {
-                    unimplemented!()
-                }
-                self.get_element_type_name(referenced_el)
-            }
-            None => {
-                let explicit_type_name: Option<&ExtensionValue> = el.extension.as_ref().and_then(|extensions| {
-                    extensions
-                        .iter()
-                        .find(|e| {
-                            e.url == EXPLICIT_TYPE_NAME_EXTENSION_URL && matches!(e.value, ExtensionValue::String(_))
-                        })
-                        .map(|e| &e.value)
-                });
-                let type_name = if let Some(v) = explicit_type_name {
-                    match v {
-                        ExtensionValue::String(s) => s.clone(),
-                        _ => unreachable!(),
-                    }
-                } else {
-                    el.get_element_name(Case::Pascal)
-                };
-                type_name
-            }
+        // `Schema::remove_unsupported` removes the elements whose reference doesn't resolve, or
+        // resolves to another reference.
+        let referenced_el = el
+            .content_reference
+            .as_deref()
+            .and_then(|r| self.get_referenced_element(r))
+            .filter(|r| r.content_reference.is_none());
+        if let Some(referenced_el) = referenced_el {
+            return self.get_element_type_name(referenced_el);
+        }
+
+        let Some((_, path)) = el.id.split_once('.') else {
+            return self.get_structure_type_name();
+        };
+        let explicit_type_name = el.extension.iter().flatten().find_map(|e| match e.value {
+            ExtensionValue::String(ref s) if e.url == EXPLICIT_TYPE_NAME_EXTENSION_URL => Some(s.clone()),
+            _ => None,
+        });
+        let name = explicit_type_name.unwrap_or_else(|| {
+            path.split('.')
+                .map(|part| part.split(':').next().unwrap_or(part).to_case(Case::Pascal))
+                .collect()
+        });
+        format!("{}{}", self.get_structure_type_name(), name)
+    }
+
+    /// Name of the enum generated for a `[x]` element, built from its whole path so that e.g.
+    /// `Observation.value[x]` and `Observation.component.value[x]` don't collide.
+    pub fn get_choice_type_name(&self, el: &ElementDefinition) -> String {
+        el.path
+            .split('.')
+            .map(|part| part.trim_end_matches("[x]").to_case(Case::Pascal))
+            .collect()
+    }
+
+    /// Name of the Rust type holding a value of `type`, with extensible primitives wrapped in
+    /// `Element<T>` so their id and extensions have somewhere to go.
+    pub fn get_type_name(&self, r#type: &ElementType) -> String {
+        let type_name = Self::get_type_code_name(&r#type.get_fhir_type());
+        if r#type.code == "Reference" {
+            return match r#type.get_reference_targets().as_deref() {
+                None | Some([]) => "TypedReference".to_string(),
+                Some([target]) => format!("TypedReference<{}>", target),
+                Some(targets) if targets.len() > MAX_REFERENCE_TARGETS => "TypedReference".to_string(),
+                Some(targets) => format!("TypedReference<({})>", targets.join(", ")),
+            };
+        }
+        if r#type.is_extensible_primitive() {
+            format!("Element<{}>", type_name)
+        } else {
+            type_name
+        }
+    }
+
+    /// Type of a `code` element bound to the value set generated as `codes::{type_name}`. Only
+    /// required bindings restrict the element to the value set's codes; others may carry any code.
+    fn get_code_type_name(&self, el: &ElementDefinition, binding: &ElementBinding, type_name: &str) -> String {
+        let type_name = match binding.strength {
+            BindingStrength::Required => format!("codes::{}", type_name),
+            _ => format!("crate::code::OpenCode<codes::{}>", type_name),
+        };
+        if el.is_extensible_primitive() {
+            format!("Element<{}>", type_name)
+        } else {
+            type_name
+        }
+    }
+
+    /// Name of the Rust type holding a value of the FHIR type `code`. Primitives resolve to the
+    /// types in `fhir::primitives`, whose names are the Pascal-cased codes, except `string`.
+    pub fn get_type_code_name(code: &str) -> String {
+        match code {
+            "string" => "String".to_string(),
+            _ => code.to_case(Case::Pascal),
         }
     }
 
     pub fn get_element_field_name(&self, el: &ElementDefinition) -> String {
-        el.get_element_name(Case::Snake)
+        match el.field_name {
+            Some(ref field_name) => escape_keyword(field_name.clone()),
+            None => el.get_element_name(Case::Snake),
+        }
+    }
+
+    /// The element's type, boxed if the element closes a cycle of types.
+    pub fn get_element_value_type_name(&self, el: &ElementDefinition) -> String {
+        let type_name = self.get_element_type_name(el);
+        if el.boxed {
+            format!("Box<{}>", type_name)
+        } else {
+            type_name
+        }
+    }
+
+    /// The element's type wrapped according to its cardinality, or `None` if the element is prohibited.
+    pub fn get_element_field_type(&self, el: &ElementDefinition) -> Option<String> {
+        let type_name = self.get_element_value_type_name(el);
+        match el.get_cardinality() {
+            Cardinality::Prohibited => None,
+            Cardinality::Optional => Some(format!("Option<{}>", type_name)),
+            Cardinality::Required => Some(type_name),
+            Cardinality::Repeated => Some(format!("Vec<{}>", type_name)),
+        }
     }
 
     pub fn get_direct_children<'a>(&'a self, el: &ElementDefinition) -> Vec<&'a ElementDefinition> {
@@ -167,9 +1039,107 @@ impl StructureDefinition {
             .collect()
     }
 
-    fn get_element_by_id<'a>(&'a self, id: &str) -> Option<&'a ElementDefinition> {
+    pub fn get_element_by_id<'a>(&'a self, id: &str) -> Option<&'a ElementDefinition> {
         self.snapshot.element.iter().find(|e| e.id == id)
     }
+
+    /// The sub-extensions of the (part of an) extension whose elements have ids starting with
+    /// `prefix`, e.g. `Extension.extension:code` for `Extension`.
+    pub fn get_extension_slices(&self, prefix: &str) -> Vec<&ElementDefinition> {
+        let slice_prefix = format!("{}.extension:", prefix);
+        self.snapshot
+            .element
+            .iter()
+            .filter(|e| {
+                e.id.strip_prefix(&slice_prefix)
+                    .is_some_and(|name| !name.is_empty() && !name.contains('.'))
+            })
+            .collect()
+    }
+
+    /// The `value[x]` of the (part of an) extension at `prefix`, unless the definition prohibits it.
+    pub fn get_extension_value(&self, prefix: &str) -> Option<&ElementDefinition> {
+        self.get_element_by_id(&format!("{}.value[x]", prefix))
+            .filter(|el| el.get_cardinality() != Cardinality::Prohibited)
+    }
+
+    /// The `url` fixed for the (part of an) extension at `prefix`.
+    pub fn get_extension_url(&self, prefix: &str) -> Option<&str> {
+        let el = self.get_element_by_id(&format!("{}.url", prefix))?;
+        el.fixed.as_ref()?.value.as_str()
+    }
+
+    /// The element a `contentReference` such as `#Questionnaire.item` points at.
+    fn get_referenced_element(&self, content_reference: &str) -> Option<&ElementDefinition> {
+        let referenced_id = content_reference.rsplit('#').next().unwrap_or(content_reference);
+        self.get_element_by_id(referenced_id)
+    }
+
+    /// Why `el` can't be generated, if it can't. The types are only checked for elements that are
+    /// generated as fields (`typed`), against `defined`, the canonical URLs of the loaded definitions.
+    fn get_unsupported_reason(&self, el: &ElementDefinition, typed: bool, defined: &HashSet<&str>) -> Option<String> {
+        let name = el.path.rsplit('.').next().unwrap_or(&el.path);
+        let name = name.strip_suffix("[x]").unwrap_or(name);
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic())
+            || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Some(format!("`{}` isn't a supported element name", name));
+        }
+        if let Some(ref content_reference) = el.content_reference {
+            return match self.get_referenced_element(content_reference) {
+                None => Some(format!(
+                    "contentReference `{}` doesn't resolve to an element",
+                    content_reference
+                )),
+                Some(referenced_el) if referenced_el.content_reference.is_some() => Some(format!(
+                    "contentReference `{}` resolves to another contentReference",
+                    content_reference
+                )),
+                Some(_) => None,
+            };
+        }
+        if !typed {
+            return None;
+        }
+        if el.r#type.is_empty() {
+            return Some("has neither a type nor a contentReference".to_string());
+        }
+        el.r#type
+            .iter()
+            .map(|t| t.get_fhir_type())
+            .find(|code| {
+                !PRIMITIVE_TYPE_CODES.contains(&code.as_str())
+                    && !defined.contains(format!("{}{}", CORE_STRUCTURE_DEFINITION_URL_PREFIX, code).as_str())
+            })
+            .map(|code| format!("type `{}` isn't defined by any of the loaded definitions", code))
+    }
+
+    /// Whether a struct is generated for the element: the root, or a backbone element defined in place.
+    pub fn is_struct_element(&self, el: &ElementDefinition) -> bool {
+        !el.id.contains('.') || (el.is_container() && el.content_reference.is_none())
+    }
+
+    /// Gives elements whose `contentReference` points at a typed element rather than a structure
+    /// the referenced element's types and binding, so they're generated like any other typed element.
+    fn inline_content_references(&mut self) {
+        let types: Vec<Option<(Vec<ElementType>, Option<ElementBinding>)>> = self
+            .snapshot
+            .element
+            .iter()
+            .map(|el| {
+                let referenced_el = self.get_referenced_element(el.content_reference.as_deref()?)?;
+                (!referenced_el.is_container() && referenced_el.content_reference.is_none())
+                    .then(|| (referenced_el.r#type.clone(), referenced_el.binding.clone()))
+            })
+            .collect();
+        for (el, types) in self.snapshot.element.iter_mut().zip(types) {
+            if let Some((types, binding)) = types {
+                el.content_reference = None;
+                el.r#type = types;
+                el.binding = binding;
+            }
+        }
+    }
 }
 
 #[derive(Deserialize, Debug, PartialEq, Eq)]
@@ -191,30 +1161,50 @@ pub struct Snapshot {
 #[serde(rename_all = "camelCase")]
 pub struct ElementDefinition {
     pub id: String,
-    pub content_reference: Option<String>,
-    pub extension: Option<Vec<Extension>>,
+    pub path: String,
     #[serde(default)]
-    pub r#type: Vec<ElementType>,
+    pub representation: Vec<PropertyRepresentation>,
+    pub slice_name: Option<String>,
+    pub slicing: Option<ElementSlicing>,
     pub short: Option<String>,
     pub definition: Option<String>,
-}
-
-#[derive(Debug)]
-struct IdToken {}
-
-impl IdToken {
-    fn from_str(s: &str) -> Self {
-        Self {}
-    }
+    pub comment: Option<String>,
+    pub requirements: Option<String>,
+    pub min: Option<u32>,
+    pub max: Option<ElementMax>,
+    pub base: Option<ElementBase>,
+    pub content_reference: Option<String>,
+    #[serde(default)]
+    pub r#type: Vec<ElementType>,
+    /// `fixed[x]`: the value this element must have exactly.
+    #[serde(flatten, deserialize_with = "deserialize_fixed")]
+    pub fixed: Option<ElementValue>,
+    /// `pattern[x]`: a value whose populated parts this element must match.
+    #[serde(flatten, deserialize_with = "deserialize_pattern")]
+    pub pattern: Option<ElementValue>,
+    pub max_length: Option<u32>,
+    #[serde(default)]
+    pub constraint: Vec<ElementConstraint>,
+    #[serde(default)]
+    pub must_support: bool,
+    #[serde(default)]
+    pub is_modifier: bool,
+    pub is_modifier_reason: Option<String>,
+    #[serde(default)]
+    pub is_summary: bool,
+    pub binding: Option<ElementBinding>,
+    pub extension: Option<Vec<Extension>>,
+    /// Whether the field is boxed to break a cycle of types, set by [`Schema::resolve_recursive_types`].
+    #[serde(skip)]
+    pub boxed: bool,
+    /// Name of the generated field given in the config, set by [`Schema::apply_renames`].
+    #[serde(skip)]
+    pub field_name: Option<String>,
 }
 
 impl ElementDefinition {
-    // pub fn get_element_field_name(&self) -> String {
-    //     self.get_element_name(Case::Snake)
-    // }
-
     fn get_element_name(&self, casing: Case) -> String {
-        let name = if let Some(part) = self.id.split('.').last() {
+        let name = if let Some(part) = self.id.split('.').next_back() {
             part
         } else {
             &self.id
@@ -226,14 +1216,47 @@ impl ElementDefinition {
             name
         };
 
-        match name.as_str() {
-            "type" | "use" | "for" | "abstract" | "const" => format!("r#{}", name),
-            _ => name,
+        escape_keyword(name)
+    }
+
+    /// Name of a slice, e.g. `code` for `Extension.extension:code`, falling back to the part of the
+    /// id after the colon.
+    fn get_slice_name(&self) -> &str {
+        match self.slice_name {
+            Some(ref slice_name) => slice_name,
+            None => self.id.rsplit(':').next().unwrap_or(&self.id),
+        }
+    }
+
+    pub fn get_slice_field_name(&self) -> String {
+        let name: String = self
+            .get_slice_name()
+            .to_case(Case::Snake)
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .collect();
+        escape_keyword(name)
+    }
+
+    pub fn get_slice_type_name(&self) -> String {
+        let name = self.get_slice_name().to_case(Case::Pascal);
+        name.chars().filter(|c| c.is_ascii_alphanumeric()).collect()
+    }
+
+    /// The binding of a `code` element that a generated enum may type: one that isn't merely an
+    /// example and names a value set.
+    pub fn get_code_binding(&self) -> Option<&ElementBinding> {
+        if self.is_choice_type() || self.r#type.len() != 1 || self.r#type[0].code != "code" {
+            return None;
         }
+        self.binding
+            .as_ref()
+            .filter(|b| b.strength != BindingStrength::Example && b.value_set.is_some())
     }
 
-    fn is_container(&self) -> bool {
-        // if self.r#type.as_ref().map(|t| t.len()).unwrap_or(0) != 1 {
+    /// Whether the element defines an inline structure of its own: the root, or one typed
+    /// `BackboneElement` or `Element`.
+    pub fn is_container(&self) -> bool {
         if self.r#type.len() != 1 {
             return false;
         }
@@ -241,42 +1264,291 @@ impl ElementDefinition {
             return true;
         }
         let r#type = &self.r#type[0].code;
-        return r#type == "BackboneElement" || r#type == "Element";
+        r#type == "BackboneElement" || r#type == "Element"
     }
 
-    fn is_choice_type(&self) -> bool {
-        let id = self.id.split(".").last().unwrap();
-        id.ends_with("[x]")
+    /// Missing bounds fall back to the base element's, then to `0..1`.
+    pub fn get_cardinality(&self) -> Cardinality {
+        let min = self.get_min();
+        match self.get_max() {
+            ElementMax::Bounded(0) => Cardinality::Prohibited,
+            ElementMax::Bounded(1) if min == 0 => Cardinality::Optional,
+            ElementMax::Bounded(1) => Cardinality::Required,
+            _ => Cardinality::Repeated,
+        }
+    }
+
+    /// Lower bound of the element's cardinality, falling back to the base element's.
+    pub fn get_min(&self) -> u32 {
+        self.min.or(self.base.as_ref().map(|b| b.min)).unwrap_or(0)
     }
 
-    fn get_distinct_type_count(&self) -> usize {
-        let len = self.r#type.len();
-        if len < 2 {
-            return len;
+    /// Upper bound of the element's cardinality, falling back to the base element's.
+    pub fn get_max(&self) -> ElementMax {
+        self.max
+            .or(self.base.as_ref().map(|b| b.max))
+            .unwrap_or(ElementMax::Bounded(1))
+    }
+
+    /// The distinct type codes a choice element allows, in definition order.
+    pub fn get_choice_types(&self) -> Vec<&ElementType> {
+        let mut seen = HashSet::new();
+        self.r#type.iter().filter(|t| seen.insert(t.code.as_str())).collect()
+    }
+
+    /// JSON property carrying a choice element's value of `type`, e.g. `valueQuantity`.
+    pub fn get_choice_json_name(&self, r#type: &ElementType) -> String {
+        let name = self.get_json_name();
+        let mut code = r#type.code.chars();
+        match code.next() {
+            Some(first) => format!("{}{}{}", name, first.to_ascii_uppercase(), code.as_str()),
+            None => name.to_string(),
         }
-        self.r#type.iter().collect::<HashSet<_>>().len()
     }
 
-    // fn is_container(&self) -> bool {
-    //     if self.r#type.as_ref().map(|t| t.len()).unwrap_or(0) != 1 {
-    //         return false;
-    //     }
-    //     if self.id.find(".").is_none() {
-    //         return true;
-    //     }
-    //     let r#type = &self.r#type.as_ref().unwrap()[0].code;
-    //     return r#type == "BackboneElement" || r#type == "Element";
-    // }
+    /// Name of the element's JSON property; for choice elements the name before the type suffix.
+    pub fn get_json_name(&self) -> String {
+        let name = self.path.rsplit('.').next().unwrap_or(&self.path);
+        name.trim_end_matches("[x]").to_string()
+    }
+
+    /// Whether the element is a single FHIR primitive, serialized with a `_name` sibling.
+    pub fn is_extensible_primitive(&self) -> bool {
+        !self.is_choice_type() && self.r#type.len() == 1 && self.r#type[0].is_extensible_primitive()
+    }
+
+    pub fn is_choice_type(&self) -> bool {
+        let id = self.id.rsplit('.').next().unwrap_or(&self.id);
+        id.ends_with("[x]")
+    }
 }
 
-#[derive(Deserialize, Debug, PartialEq, Eq, Hash)]
+#[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ElementType {
     pub code: String,
-    pub targetProfile: Option<Vec<String>>,
+    pub target_profile: Option<Vec<String>>,
+    pub profile: Option<Vec<String>>,
+    pub extension: Option<Vec<Extension>>,
+    /// Whether a choice element's variant of this type is boxed, set by
+    /// [`Schema::resolve_recursive_types`].
+    #[serde(skip)]
+    pub boxed: bool,
 }
 
-#[derive(Debug)]
+impl ElementType {
+    /// Names of the resources a `Reference` may point at, or `None` if it may point at any, either
+    /// because it targets `Resource` or because a target is a profile rather than a core resource.
+    pub fn get_reference_targets(&self) -> Option<Vec<String>> {
+        let mut targets: Vec<String> = Vec::new();
+        for url in self.target_profile.iter().flatten() {
+            let name = url.strip_prefix(CORE_STRUCTURE_DEFINITION_URL_PREFIX)?;
+            if ABSTRACT_RESOURCE_NAMES.contains(&name) || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return None;
+            }
+            if !targets.iter().any(|t| t == name) {
+                targets.push(name.to_string());
+            }
+        }
+        Some(targets)
+    }
+
+    /// The FHIR type code. Elements such as `Resource.id` are typed with a FHIRPath system type
+    /// (`http://hl7.org/fhirpath/System.String`) and name their FHIR type in an extension.
+    pub fn get_fhir_type(&self) -> String {
+        let fhir_type = self.extension.iter().flatten().find_map(|e| match e.value {
+            ExtensionValue::Url(ref url) | ExtensionValue::Uri(ref url) if e.url == FHIR_TYPE_EXTENSION_URL => {
+                Some(url.clone())
+            }
+            _ => None,
+        });
+        if let Some(fhir_type) = fhir_type {
+            return fhir_type;
+        }
+        match self.code.strip_prefix(FHIRPATH_SYSTEM_TYPE_PREFIX) {
+            Some(system_type) => system_type.to_case(Case::Camel),
+            None => self.code.clone(),
+        }
+    }
+
+    pub fn is_primitive(&self) -> bool {
+        PRIMITIVE_TYPE_CODES.contains(&self.get_fhir_type().as_str())
+    }
+
+    /// FHIR primitives may carry an id and extensions; FHIRPath system types such as `Element.id` can't.
+    pub fn is_extensible_primitive(&self) -> bool {
+        self.is_primitive() && !self.code.starts_with(FHIRPATH_SYSTEM_TYPE_PREFIX)
+    }
+}
+
+/// How an element's `min`/`max` map onto the shape of a generated field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cardinality {
+    /// `0..0`, no field is generated.
+    Prohibited,
+    /// `0..1`, an `Option<T>`.
+    Optional,
+    /// `1..1`, a bare `T`.
+    Required,
+    /// `0..*` or `1..*`, a `Vec<T>`.
+    Repeated,
+}
+
+/// Upper bound of an element's cardinality, `*` or a number. `*` orders above every number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ElementMax {
+    Bounded(u32),
+    Unbounded,
+}
+
+impl<'de> Deserialize<'de> for ElementMax {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let max = String::deserialize(deserializer)?;
+        if max == "*" {
+            Ok(ElementMax::Unbounded)
+        } else {
+            max.parse()
+                .map(ElementMax::Bounded)
+                .map_err(|_| serde::de::Error::invalid_value(serde::de::Unexpected::Str(&max), &"`*` or a number"))
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ElementBase {
+    pub path: String,
+    pub min: u32,
+    pub max: ElementMax,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ElementBinding {
+    pub strength: BindingStrength,
+    pub description: Option<String>,
+    pub value_set: Option<String>,
+    /// Name of the enum generated for the value set, set by [`Schema::resolve_code_bindings`].
+    #[serde(skip)]
+    pub type_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub enum BindingStrength {
+    Required,
+    Extensible,
+    Preferred,
+    Example,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ElementConstraint {
+    pub key: String,
+    pub requirements: Option<String>,
+    pub severity: ConstraintSeverity,
+    pub human: String,
+    pub expression: Option<String>,
+    pub source: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub enum ConstraintSeverity {
+    Error,
+    Warning,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ElementSlicing {
+    #[serde(default)]
+    pub discriminator: Vec<SlicingDiscriminator>,
+    pub description: Option<String>,
+    pub ordered: Option<bool>,
+    pub rules: SlicingRules,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SlicingDiscriminator {
+    pub r#type: DiscriminatorType,
+    pub path: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub enum DiscriminatorType {
+    Value,
+    Exists,
+    Pattern,
+    Type,
+    Profile,
+    Position,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub enum SlicingRules {
+    Closed,
+    Open,
+    OpenAtEnd,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub enum PropertyRepresentation {
+    XmlAttr,
+    XmlText,
+    TypeAttr,
+    CdaText,
+    Xhtml,
+}
+
+/// The value of a `fixed[x]` or `pattern[x]` element, e.g. `fixedUri` becomes type `Uri`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementValue {
+    pub r#type: String,
+    pub value: serde_json::Value,
+}
+
+fn deserialize_fixed<'de, D>(deserializer: D) -> std::result::Result<Option<ElementValue>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_element_value(deserializer, "fixed")
+}
+
+fn deserialize_pattern<'de, D>(deserializer: D) -> std::result::Result<Option<ElementValue>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_element_value(deserializer, "pattern")
+}
+
+fn deserialize_element_value<'de, D>(
+    deserializer: D,
+    prefix: &str,
+) -> std::result::Result<Option<ElementValue>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let properties = HashMap::<String, serde_json::Value>::deserialize(deserializer)?;
+    Ok(properties.into_iter().find_map(|(key, value)| {
+        key.strip_prefix(prefix)
+            .filter(|r#type| r#type.starts_with(|c: char| c.is_ascii_uppercase()))
+            .map(|r#type| ElementValue {
+                r#type: r#type.to_string(),
+                value,
+            })
+    }))
+}
+
+#[derive(Debug, Clone)]
 pub struct Extension {
     pub url: String,
     pub value: ExtensionValue,
@@ -287,18 +1559,6 @@ impl<'de> Deserialize<'de> for Extension {
     where
         D: serde::Deserializer<'de>,
     {
-        #[derive(Deserialize, Debug, PartialEq, Eq)]
-        #[serde(rename_all = "camelCase")]
-        enum Field {
-            Url,
-            ValueString,
-            ValueInteger,
-            ValueCode,
-            ValueUri,
-            ValueBoolean,
-            ValueMarkdown,
-        }
-
         struct ExtensionVisitor;
         impl<'de> Visitor<'de> for ExtensionVisitor {
             type Value = Extension;
@@ -314,27 +1574,35 @@ impl<'de> Deserialize<'de> for Extension {
                 let mut url = None;
                 let mut value = None;
 
-                while let Some(key) = map.next_key()? {
-                    if key == Field::Url {
-                        if url.is_some() {
-                            return Err(serde::de::Error::duplicate_field("url"));
+                while let Some(key) = map.next_key::<String>()? {
+                    let next = match key.as_str() {
+                        "url" => {
+                            if url.is_some() {
+                                return Err(serde::de::Error::duplicate_field("url"));
+                            }
+                            url = Some(map.next_value()?);
+                            continue;
                         }
-                        url = Some(map.next_value()?);
-                    } else {
-                        if value.is_some() {
-                            return Err(serde::de::Error::duplicate_field("valueString"));
-                        }
-
-                        match key {
-                            Field::Url => unreachable!(),
-                            Field::ValueString => value = Some(ExtensionValue::String(map.next_value()?)),
-                            Field::ValueInteger => value = Some(ExtensionValue::Integer(map.next_value()?)),
-                            Field::ValueCode => value = Some(ExtensionValue::Code(map.next_value()?)),
-                            Field::ValueUri => value = Some(ExtensionValue::Uri(map.next_value()?)),
-                            Field::ValueBoolean => value = Some(ExtensionValue::Boolean(map.next_value()?)),
-                            Field::ValueMarkdown => value = Some(ExtensionValue::Markdown(map.next_value()?)),
+                        "valueString" => ExtensionValue::String(map.next_value()?),
+                        "valueInteger" => ExtensionValue::Integer(map.next_value()?),
+                        "valueCode" => ExtensionValue::Code(map.next_value()?),
+                        "valueUri" => ExtensionValue::Uri(map.next_value()?),
+                        "valueBoolean" => ExtensionValue::Boolean(map.next_value()?),
+                        "valueMarkdown" => ExtensionValue::Markdown(map.next_value()?),
+                        "valueUrl" => ExtensionValue::Url(map.next_value()?),
+                        "valueCanonical" => ExtensionValue::Canonical(map.next_value()?),
+                        _ => {
+                            map.next_value::<serde::de::IgnoredAny>()?;
+                            if !key.starts_with("value") {
+                                continue;
+                            }
+                            ExtensionValue::Unsupported
                         }
+                    };
+                    if value.is_some() {
+                        return Err(serde::de::Error::duplicate_field("value[x]"));
                     }
+                    value = Some(next);
                 }
                 let url = url.ok_or_else(|| serde::de::Error::missing_field("url"))?;
                 let value = value.ok_or_else(|| serde::de::Error::missing_field("value[x]"))?;
@@ -346,7 +1614,7 @@ impl<'de> Deserialize<'de> for Extension {
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub enum ExtensionValue {
     String(String),
@@ -355,6 +1623,11 @@ pub enum ExtensionValue {
     Uri(String),
     Boolean(bool),
     Markdown(String),
+    Url(String),
+    Canonical(String),
+    /// A value of a type none of the extensions read here use.
+    #[serde(skip)]
+    Unsupported,
 }
 
 #[derive(Deserialize, Debug, PartialEq, Eq)]
@@ -368,12 +1641,79 @@ enum ResourceType {
     Ignore,
     Bundle,
     StructureDefinition,
+    ValueSet,
+    CodeSystem,
+}
+
+impl ResourceType {
+    fn from_name(name: &str) -> Self {
+        match name {
+            "StructureDefinition" => ResourceType::StructureDefinition,
+            "ValueSet" => ResourceType::ValueSet,
+            "CodeSystem" => ResourceType::CodeSystem,
+            "Bundle" => ResourceType::Bundle,
+            _ => ResourceType::Ignore,
+        }
+    }
+}
+
+/// Whether resources of this type contribute to a [`Schema`]; lets loaders skip files up front.
+pub fn is_collected_resource_type(name: &str) -> bool {
+    !matches!(ResourceType::from_name(name), ResourceType::Ignore)
 }
 
-pub fn from_zip(mut zip: ZipArchive<std::fs::File>) -> Result<Schema> {
-    let mut resources = Schema {
-        structures_definitions: Vec::new(),
-    };
+/// Loads definitions from a zip, a directory of JSON files or a single JSON resource.
+pub fn from_path(path: &Path) -> Result<Schema> {
+    if path.is_dir() {
+        return from_dir(path);
+    }
+
+    let file = File::open(path).with_context(|| format!("path {:?}", path))?;
+    if path.extension().is_some_and(|ext| ext == "zip") {
+        let zip = ZipArchive::new(file).with_context(|| format!("read zip file {:?}", path))?;
+        from_zip(zip)
+    } else {
+        from_reader(BufReader::new(file)).with_context(|| format!("read json file {:?}", path))
+    }
+}
+
+pub fn from_reader(reader: impl Read) -> Result<Schema> {
+    let mut resources = Schema::default();
+    let json_value: serde_json::Value = serde_json::from_reader(reader)?;
+    collect_structure_definitions(&mut resources, json_value)?;
+    Ok(resources)
+}
+
+/// Loads every `.json` file below `dir`, skipping files that aren't FHIR resources (e.g. `package.json`).
+pub fn from_dir(dir: &Path) -> Result<Schema> {
+    let mut resources = Schema::default();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let mut entries = std::fs::read_dir(&dir)
+            .with_context(|| format!("read dir {:?}", dir))?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort();
+
+        for path in entries {
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "json") {
+                let file = BufReader::new(File::open(&path).with_context(|| format!("path {:?}", path))?);
+                let json_value: serde_json::Value =
+                    serde_json::from_reader(file).with_context(|| format!("read json file {:?}", path))?;
+                if is_resource(&json_value) {
+                    collect_structure_definitions(&mut resources, json_value)
+                        .with_context(|| format!("read json file {:?}", path))?;
+                }
+            }
+        }
+    }
+    Ok(resources)
+}
+
+pub fn from_zip<R: Read + Seek>(mut zip: ZipArchive<R>) -> Result<Schema> {
+    let mut resources = Schema::default();
 
     for i in 0..zip.len() {
         let file = zip.by_index(i)?;
@@ -396,10 +1736,34 @@ pub fn from_zip(mut zip: ZipArchive<std::fs::File>) -> Result<Schema> {
 fn collect_structure_definitions(resources: &mut Schema, json_value: serde_json::Value) -> Result<()> {
     match get_resource_type(&json_value)? {
         ResourceType::Ignore => {}
+        // A definition that can't be read is reported and left out, so the others still load.
         ResourceType::StructureDefinition => {
-            resources
-                .structures_definitions
-                .push(serde_json::from_value(json_value)?);
+            let url = get_url(&json_value);
+            match serde_json::from_value(json_value) {
+                Ok(structure_definition) => resources.structures_definitions.push(structure_definition),
+                Err(err) => resources
+                    .diagnostics
+                    .error(&url, None, format!("can't be read: {}", err)),
+            }
+        }
+        // Elements bound to a value set that can't be read are generated with plain codes.
+        ResourceType::ValueSet => {
+            let url = get_url(&json_value);
+            match serde_json::from_value(json_value) {
+                Ok(value_set) => resources.value_sets.push(value_set),
+                Err(err) => resources
+                    .diagnostics
+                    .warning(&url, None, format!("can't be read: {}", err)),
+            }
+        }
+        ResourceType::CodeSystem => {
+            let url = get_url(&json_value);
+            match serde_json::from_value(json_value) {
+                Ok(code_system) => resources.code_systems.push(code_system),
+                Err(err) => resources
+                    .diagnostics
+                    .warning(&url, None, format!("can't be read: {}", err)),
+            }
         }
         ResourceType::Bundle => {
             let entries = get_bundle_entries(json_value)?;
@@ -412,15 +1776,21 @@ fn collect_structure_definitions(resources: &mut Schema, json_value: serde_json:
     Ok(())
 }
 
+fn is_resource(value: &serde_json::Value) -> bool {
+    matches!(value, serde_json::Value::Object(map) if map.contains_key("resourceType"))
+}
+
+/// The canonical URL of a resource that may not deserialize, else its id, for diagnostics.
+fn get_url(value: &serde_json::Value) -> String {
+    let url = value.get("url").or_else(|| value.get("id")).and_then(|v| v.as_str());
+    url.unwrap_or("<unidentified>").to_string()
+}
+
 fn get_resource_type(value: &serde_json::Value) -> Result<ResourceType> {
     match value {
         serde_json::Value::Object(map) => match map.get("resourceType") {
             Some(resource_type) => match resource_type {
-                serde_json::Value::String(resource_type) => match resource_type.as_str() {
-                    "StructureDefinition" => Ok(ResourceType::StructureDefinition),
-                    "Bundle" => Ok(ResourceType::Bundle),
-                    _ => Ok(ResourceType::Ignore),
-                },
+                serde_json::Value::String(resource_type) => Ok(ResourceType::from_name(resource_type)),
                 _ => Err(anyhow!("resourceType is not a string")),
             },
             None => Err(anyhow!("no resourceType field")),
@@ -429,6 +1799,7 @@ fn get_resource_type(value: &serde_json::Value) -> Result<ResourceType> {
     }
 }
 
+/// The entries of a Bundle, none if it has no `entry`.
 fn get_bundle_entries(value: serde_json::Value) -> Result<Vec<serde_json::Value>> {
     match value {
         serde_json::Value::Object(mut map) => match map.remove("entry") {
@@ -436,7 +1807,7 @@ fn get_bundle_entries(value: serde_json::Value) -> Result<Vec<serde_json::Value>
                 serde_json::Value::Array(entries) => Ok(entries),
                 _ => Err(anyhow!("entry is not an array")),
             },
-            None => Err(anyhow!("no entry field")),
+            None => Ok(Vec::new()),
         },
         _ => Err(anyhow!("not an object")),
     }
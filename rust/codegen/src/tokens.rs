@@ -0,0 +1,63 @@
+//! Helpers for building the generated code as token streams and printing it as source files.
+
+use anyhow::{anyhow, Context, Result};
+use proc_macro2::{Group, Ident, Literal, TokenStream, TokenTree};
+use quote::quote;
+
+/// `name` as an identifier, raw ones such as `r#type` included, failing for anything else, e.g. a
+/// keyword or a name with a `-` in it.
+pub fn ident(name: &str) -> Result<Ident> {
+    syn::parse_str(name).map_err(|_| anyhow!("`{}` is not a valid identifier", name))
+}
+
+/// `name` as a type, e.g. `Option<Vec<Element<Date>>>` or `TypedReference<(Patient, Group)>`.
+pub fn rust_type(name: &str) -> Result<syn::Type> {
+    syn::parse_str(name).map_err(|_| anyhow!("`{}` is not a valid type", name))
+}
+
+/// An integer literal without a type suffix.
+pub fn int(value: u32) -> Literal {
+    Literal::u32_unsuffixed(value)
+}
+
+/// `text` as doc comment lines, filled to 80 columns.
+pub fn doc_comment(text: &str) -> TokenStream {
+    let text = textwrap::fill(text, 80);
+    let lines = text.lines().map(|line| {
+        if line.trim().is_empty() {
+            line.to_string()
+        } else {
+            format!(" {}", line)
+        }
+    });
+    quote! { #(#[doc = #lines])* }
+}
+
+/// Prints `tokens`, the items of a source file, laid out in `rustfmt`'s style.
+pub fn render(tokens: TokenStream) -> Result<String> {
+    let file: syn::File = syn::parse2(tokens).context("parse the generated code")?;
+    Ok(prettyplease::unparse(&file))
+}
+
+/// `tokens` with the paths starting `crate::` starting `runtime::` instead, for code that uses the
+/// `fhir` crate from outside it.
+pub fn replace_crate(tokens: TokenStream, runtime: &syn::Path) -> TokenStream {
+    let mut replaced = TokenStream::new();
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Ident(ref i)
+                if i == "crate" && matches!(tokens.peek(), Some(TokenTree::Punct(p)) if p.as_char() == ':') =>
+            {
+                replaced.extend(quote!(#runtime));
+            }
+            TokenTree::Group(group) => {
+                let mut replaced_group = Group::new(group.delimiter(), replace_crate(group.stream(), runtime));
+                replaced_group.set_span(group.span());
+                replaced.extend([TokenTree::Group(replaced_group)]);
+            }
+            token => replaced.extend([token]),
+        }
+    }
+    replaced
+}
@@ -0,0 +1,101 @@
+//! Codes from the value sets bound to `code` elements.
+//!
+//! Codegen turns each value set it can enumerate into an enum in the release's `codes` module.
+//! Elements with a required binding hold that enum directly; elements bound less strictly hold an
+//! [`OpenCode`], which keeps codes from outside the value set.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::primitives::{Code, PrimitiveError};
+
+/// A code of a generated value set enum.
+pub trait ValueSetCode: Copy + FromStr<Err = PrimitiveError> + fmt::Display {
+    /// The code as written in JSON.
+    fn code(&self) -> &'static str;
+    /// Canonical URL of the code system defining the code.
+    fn system(&self) -> &'static str;
+    /// The code system's display text for the code, if it gives one.
+    fn display(&self) -> Option<&'static str>;
+}
+
+/// A code bound to the value set `T` by an `extensible` or `preferred` binding, so possibly one
+/// from outside it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum OpenCode<T> {
+    Known(T),
+    Other(Code),
+}
+
+impl<T: ValueSetCode> OpenCode<T> {
+    pub fn code(&self) -> &str {
+        match self {
+            OpenCode::Known(known) => known.code(),
+            OpenCode::Other(code) => code.as_str(),
+        }
+    }
+
+    /// The code system of a known code; other codes don't say which system they come from.
+    pub fn system(&self) -> Option<&'static str> {
+        match self {
+            OpenCode::Known(known) => Some(known.system()),
+            OpenCode::Other(_) => None,
+        }
+    }
+
+    pub fn display(&self) -> Option<&'static str> {
+        match self {
+            OpenCode::Known(known) => known.display(),
+            OpenCode::Other(_) => None,
+        }
+    }
+
+    /// The code as a member of the value set, if it is one.
+    pub fn known(&self) -> Option<T> {
+        match self {
+            OpenCode::Known(known) => Some(*known),
+            OpenCode::Other(_) => None,
+        }
+    }
+}
+
+impl<T> From<T> for OpenCode<T> {
+    fn from(known: T) -> Self {
+        OpenCode::Known(known)
+    }
+}
+
+impl<T: ValueSetCode> FromStr for OpenCode<T> {
+    type Err = PrimitiveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Ok(known) => Ok(OpenCode::Known(known)),
+            Err(_) => s.parse().map(OpenCode::Other),
+        }
+    }
+}
+
+impl<T: ValueSetCode> fmt::Display for OpenCode<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl<T: ValueSetCode> serde::Serialize for OpenCode<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.code())
+    }
+}
+
+impl<'de, T: ValueSetCode> serde::Deserialize<'de> for OpenCode<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        crate::serde_support::deserialize_from_str(deserializer)
+    }
+}
@@ -0,0 +1,52 @@
+/// A primitive value together with the `id` and `extension` that FHIR JSON carries in a sibling
+/// property prefixed with an underscore, e.g. `birthDate` and `_birthDate`. Either part may be
+/// absent: a value can be missing entirely with only an extension explaining why.
+///
+/// `E` is the release's `Extension` type; each generated release module exports an `Element<T>`
+/// alias that fills it in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Element<T, E> {
+    pub id: Option<String>,
+    pub extension: Vec<E>,
+    pub value: Option<T>,
+}
+
+impl<T, E> Element<T, E> {
+    pub fn new(value: T) -> Self {
+        Element {
+            id: None,
+            extension: Vec::new(),
+            value: Some(value),
+        }
+    }
+
+    pub fn value(&self) -> Option<&T> {
+        self.value.as_ref()
+    }
+
+    /// Whether the element has neither a value nor an `id` or extensions, and so is left out of JSON.
+    pub fn is_empty(&self) -> bool {
+        self.value.is_none() && !self.has_extensions()
+    }
+
+    /// Whether the element has an `id` or extensions, i.e. needs an `_name` property in JSON.
+    pub fn has_extensions(&self) -> bool {
+        self.id.is_some() || !self.extension.is_empty()
+    }
+}
+
+impl<T, E> Default for Element<T, E> {
+    fn default() -> Self {
+        Element {
+            id: None,
+            extension: Vec::new(),
+            value: None,
+        }
+    }
+}
+
+impl<T, E> From<T> for Element<T, E> {
+    fn from(value: T) -> Self {
+        Element::new(value)
+    }
+}
@@ -0,0 +1,21 @@
+//! Errors reading the typed extension structs generated from extension definitions.
+
+use std::fmt;
+
+/// An extension whose content doesn't match its definition, e.g. one with a `valueString` where
+/// the definition allows only `valueAddress`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtensionError {
+    /// Canonical URL of the extension definition.
+    pub url: &'static str,
+    /// Id of the element that is missing or unexpected, e.g. `Extension.value[x]`.
+    pub element: &'static str,
+}
+
+impl fmt::Display for ExtensionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "extension {}: missing or unexpected {}", self.url, self.element)
+    }
+}
+
+impl std::error::Error for ExtensionError {}
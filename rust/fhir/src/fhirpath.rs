@@ -0,0 +1,1681 @@
+//! A FHIRPath evaluator covering what the core invariants use, run over the JSON form of a value.
+//!
+//! Working on JSON rather than the generated types means element types are only known where the
+//! JSON shows them: in `resourceType`, in the suffix of choice elements such as `valueQuantity`,
+//! and in the kind of a primitive value. Type tests on other values go by the JSON kind alone.
+//! Functions that need a terminology server, a resolver or the clock, such as `memberOf()`,
+//! `resolve()` and `now()`, aren't supported and fail evaluation.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use serde_json::Value;
+
+/// An expression that doesn't parse, or uses something the evaluator doesn't support.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+fn error<T>(message: impl Into<String>) -> Result<T> {
+    Err(Error(message.into()))
+}
+
+/// Types whose values are JSON strings.
+const STRING_TYPES: &[&str] = &[
+    "string",
+    "uri",
+    "url",
+    "canonical",
+    "code",
+    "id",
+    "markdown",
+    "oid",
+    "uuid",
+    "base64Binary",
+    "date",
+    "dateTime",
+    "instant",
+    "time",
+    "xhtml",
+    "String",
+    "Date",
+    "DateTime",
+    "Time",
+];
+
+/// Types whose values are JSON integers.
+const INTEGER_TYPES: &[&str] = &["integer", "integer64", "positiveInt", "unsignedInt", "Integer"];
+
+/// A value in a FHIRPath collection.
+#[derive(Debug, Clone)]
+pub(crate) enum Item<'a> {
+    Node(Node<'a>),
+    Boolean(bool),
+    String(String),
+    Integer(i64),
+    Decimal(f64),
+    /// A date, date-time or time literal, without its `@`.
+    DateTime(String),
+}
+
+/// An element of the JSON being evaluated: an object, or a primitive's value together with the
+/// object in its `_name` sibling.
+#[derive(Debug, Clone)]
+pub(crate) struct Node<'a> {
+    value: Option<&'a Value>,
+    extra: Option<&'a Value>,
+    type_name: Option<String>,
+}
+
+impl<'a> Item<'a> {
+    /// The top-level value being evaluated, of the FHIR type `type_name`.
+    pub(crate) fn root(value: &'a Value, type_name: &str) -> Self {
+        Item::Node(Node {
+            value: Some(value),
+            extra: None,
+            type_name: Some(type_name.to_string()),
+        })
+    }
+
+    /// The values of the element `name`, e.g. `value` for `valueQuantity`, each with the part of
+    /// an element path leading to it, e.g. `name[1]`.
+    pub(crate) fn get_children(&self, name: &str) -> Vec<(String, Item<'a>)> {
+        let Some(object) = self.get_object() else {
+            return Vec::new();
+        };
+        if let Some(children) = get_property(object, name, None) {
+            return children;
+        }
+        let mut children = Vec::new();
+        for key in object.keys() {
+            let Some(suffix) = key.strip_prefix(name) else {
+                continue;
+            };
+            if suffix.starts_with(|c: char| c.is_ascii_uppercase()) {
+                children.extend(get_property(object, key, Some(suffix)).into_iter().flatten());
+            }
+        }
+        children
+    }
+
+    /// The object holding the item's children: an object node, or a primitive's `_name`.
+    fn get_object(&self) -> Option<&'a serde_json::Map<String, Value>> {
+        match self {
+            Item::Node(node) => match node.value {
+                Some(Value::Object(object)) => Some(object),
+                _ => node.extra.and_then(Value::as_object),
+            },
+            _ => None,
+        }
+    }
+
+    fn get_all_children(&self) -> Vec<Item<'a>> {
+        let Some(object) = self.get_object() else {
+            return Vec::new();
+        };
+        let mut children = Vec::new();
+        for key in object.keys() {
+            let name = match key.strip_prefix('_') {
+                Some(name) if object.contains_key(name) => continue,
+                Some(name) => name,
+                None if key == "resourceType" => continue,
+                None => key,
+            };
+            children.extend(
+                get_property(object, name, None)
+                    .into_iter()
+                    .flatten()
+                    .map(|(_, item)| item),
+            );
+        }
+        children
+    }
+
+    /// The item as a value to compare or compute with.
+    fn get_scalar(&self) -> Scalar<'_> {
+        match self {
+            Item::Boolean(b) => Scalar::Boolean(*b),
+            Item::String(s) | Item::DateTime(s) => Scalar::String(s),
+            Item::Integer(i) => Scalar::Integer(*i),
+            Item::Decimal(d) => Scalar::Decimal(*d),
+            Item::Node(node) => match node.value {
+                Some(Value::Bool(b)) => Scalar::Boolean(*b),
+                Some(Value::String(s)) => Scalar::String(s),
+                Some(Value::Number(n)) => match n.as_i64() {
+                    Some(i) if !n.to_string().contains('.') => Scalar::Integer(i),
+                    _ => Scalar::Decimal(n.as_f64().unwrap_or(f64::NAN)),
+                },
+                Some(value) => Scalar::Json(value),
+                None => Scalar::Absent,
+            },
+        }
+    }
+
+    /// Whether the item is of the type `name`, which may be qualified as `FHIR.name` or `System.name`.
+    fn is_type(&self, name: &str) -> bool {
+        let name = name
+            .strip_prefix("FHIR.")
+            .or_else(|| name.strip_prefix("System."))
+            .unwrap_or(name);
+        if let Item::Node(Node {
+            type_name: Some(ref type_name),
+            ..
+        }) = self
+        {
+            if type_name == name {
+                return true;
+            }
+        }
+        match self.get_scalar() {
+            Scalar::Boolean(_) => name.eq_ignore_ascii_case("boolean"),
+            Scalar::String(_) => STRING_TYPES.contains(&name),
+            Scalar::Integer(_) => INTEGER_TYPES.contains(&name) || name.eq_ignore_ascii_case("decimal"),
+            Scalar::Decimal(_) => name.eq_ignore_ascii_case("decimal"),
+            // Without a known type, any complex type is taken to match an object.
+            Scalar::Json(Value::Object(_)) => {
+                matches!(self, Item::Node(Node { type_name: None, .. }))
+                    && name.starts_with(|c: char| c.is_ascii_uppercase())
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The values of `object[key]`, paired with their `_key` siblings and labelled with their path.
+fn get_property<'a>(
+    object: &'a serde_json::Map<String, Value>,
+    key: &str,
+    choice_type: Option<&str>,
+) -> Option<Vec<(String, Item<'a>)>> {
+    let value = object.get(key);
+    let extra = object.get(&format!("_{}", key));
+    if value.is_none() && extra.is_none() {
+        return None;
+    }
+    let type_name = |value: Option<&Value>| match (value, choice_type) {
+        (Some(Value::Object(object)), _) if object.contains_key("resourceType") => {
+            object.get("resourceType").and_then(Value::as_str).map(str::to_string)
+        }
+        (Some(Value::Object(_)), Some(choice_type)) => Some(choice_type.to_string()),
+        // Primitive type codes start lower case, e.g. `dateTime` for `valueDateTime`.
+        (_, Some(choice_type)) => {
+            let mut chars = choice_type.chars();
+            chars
+                .next()
+                .map(|first| format!("{}{}", first.to_ascii_lowercase(), chars.as_str()))
+        }
+        _ => None,
+    };
+    let node = |value: Option<&'a Value>, extra: Option<&'a Value>| {
+        let value = value.filter(|v| !v.is_null());
+        let extra = extra.filter(|v| !v.is_null());
+        (value.is_some() || extra.is_some()).then(|| {
+            Item::Node(Node {
+                value,
+                extra,
+                type_name: type_name(value),
+            })
+        })
+    };
+    let mut items = Vec::new();
+    match (value, extra) {
+        (Some(Value::Array(_)), _) | (_, Some(Value::Array(_))) => {
+            let values = value.and_then(Value::as_array);
+            let extras = extra.and_then(Value::as_array);
+            let len = values.map_or(0, Vec::len).max(extras.map_or(0, Vec::len));
+            for i in 0..len {
+                let item = node(values.and_then(|v| v.get(i)), extras.and_then(|e| e.get(i)));
+                items.extend(item.map(|item| (format!("{}[{}]", key, i), item)));
+            }
+        }
+        _ => items.extend(node(value, extra).map(|item| (key.to_string(), item))),
+    }
+    Some(items)
+}
+
+/// What an item holds, for comparisons and arithmetic.
+#[derive(Debug, Clone, Copy)]
+enum Scalar<'a> {
+    Boolean(bool),
+    String(&'a str),
+    Integer(i64),
+    Decimal(f64),
+    Json(&'a Value),
+    /// A primitive with only an id or extensions.
+    Absent,
+}
+
+fn scalar_eq(a: Scalar, b: Scalar) -> bool {
+    match (a, b) {
+        (Scalar::Boolean(a), Scalar::Boolean(b)) => a == b,
+        // Dates and times in different time zones may still be the same instant.
+        (Scalar::String(a), Scalar::String(b)) => {
+            a == b
+                || match (Temporal::parse(a), Temporal::parse(b)) {
+                    (Some(a), Some(b)) => a.compare(&b) == Some(Ordering::Equal),
+                    _ => false,
+                }
+        }
+        (Scalar::Integer(a), Scalar::Integer(b)) => a == b,
+        (Scalar::Integer(_) | Scalar::Decimal(_), Scalar::Integer(_) | Scalar::Decimal(_)) => to_f64(a) == to_f64(b),
+        (Scalar::Json(a), Scalar::Json(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn to_f64(scalar: Scalar) -> Option<f64> {
+    match scalar {
+        Scalar::Integer(i) => Some(i as f64),
+        Scalar::Decimal(d) => Some(d),
+        _ => None,
+    }
+}
+
+/// How `a` compares to `b`, or `None` when that can't be told, e.g. for dates of different
+/// precision that agree as far as both go.
+fn scalar_cmp(a: Scalar, b: Scalar) -> Result<Option<Ordering>> {
+    match (a, b) {
+        (Scalar::String(a), Scalar::String(b)) => match (Temporal::parse(a), Temporal::parse(b)) {
+            (Some(a), Some(b)) => Ok(a.compare(&b)),
+            _ => Ok(Some(a.cmp(b))),
+        },
+        (Scalar::Integer(a), Scalar::Integer(b)) => Ok(Some(a.cmp(&b))),
+        (Scalar::Integer(_) | Scalar::Decimal(_), Scalar::Integer(_) | Scalar::Decimal(_)) => {
+            Ok(to_f64(a).partial_cmp(&to_f64(b)))
+        }
+        _ => error("can only compare numbers, strings, dates and times"),
+    }
+}
+
+/// A date, date-time or time of day, as far as its precision goes, for comparisons. Date-times
+/// with a time zone are taken to UTC, those without one are taken to be in UTC already.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Temporal {
+    /// A time of day, which compares only with other times.
+    is_time: bool,
+    /// The year, month, day, hour, minute and milliseconds into the minute, or the hour, minute
+    /// and milliseconds of a time, up to the value's precision. Seconds and milliseconds are one
+    /// precision, as FHIRPath has it.
+    parts: Vec<i64>,
+}
+
+impl Temporal {
+    /// Parses a FHIR date, dateTime, instant or time, or a FHIRPath literal without its `@`, e.g.
+    /// `2020-01`, `2020-01-01T10:00:00+01:00` or `T10:00`.
+    fn parse(value: &str) -> Option<Temporal> {
+        let (date, time) = match value.split_once('T') {
+            Some((date, time)) => (date, time),
+            None if value.contains(':') => ("", value),
+            None => (value, ""),
+        };
+        let is_time = date.is_empty();
+        let mut parts = Vec::new();
+        if !is_time {
+            let fields: Vec<&str> = date.split('-').collect();
+            if fields.len() > 3 || (fields.len() < 3 && !time.is_empty()) {
+                return None;
+            }
+            parts.push(parse_digits(fields[0], 4)?);
+            for (field, max) in fields[1..].iter().zip([12, 31]) {
+                parts.push(parse_digits(field, 2).filter(|v| (1..=max).contains(v))?);
+            }
+        }
+
+        let (time, offset) = match time.find(['Z', '+', '-']) {
+            Some(i) => (&time[..i], Some(parse_offset(&time[i..])?)),
+            None => (time, None),
+        };
+        if !time.is_empty() {
+            let mut fields = time.split(':');
+            parts.push(parse_digits(fields.next()?, 2).filter(|h| *h < 24)?);
+            if let Some(minute) = fields.next() {
+                parts.push(parse_digits(minute, 2).filter(|m| *m < 60)?);
+            }
+            if let Some(second) = fields.next() {
+                let (whole, fraction) = second.split_once('.').unwrap_or((second, ""));
+                let whole = parse_digits(whole, 2).filter(|s| *s < 61)?;
+                let millis = match fraction {
+                    "" => 0,
+                    f if f.chars().all(|c| c.is_ascii_digit()) => {
+                        format!("{:0<3}", &f[..f.len().min(3)]).parse().ok()?
+                    }
+                    _ => return None,
+                };
+                parts.push(whole * 1000 + millis);
+            }
+            if fields.next().is_some() {
+                return None;
+            }
+        } else if offset.is_some() {
+            return None;
+        }
+
+        // A time zone only moves the value once it's known to the minute.
+        if let (Some(offset), false, [year, month, day, hour, minute, ..]) = (offset, is_time, parts.as_mut_slice()) {
+            let minutes = days_from_civil(*year, *month, *day) * 1440 + *hour * 60 + *minute - offset;
+            let (y, m, d) = civil_from_days(minutes.div_euclid(1440));
+            (*year, *month, *day) = (y, m, d);
+            (*hour, *minute) = (minutes.rem_euclid(1440) / 60, minutes.rem_euclid(60));
+        }
+        Some(Temporal { is_time, parts })
+    }
+
+    /// Compares part by part as far as both values go, `None` when they agree that far but one
+    /// goes further, or when a time is compared with a date.
+    fn compare(&self, other: &Temporal) -> Option<Ordering> {
+        if self.is_time != other.is_time {
+            return None;
+        }
+        match self
+            .parts
+            .iter()
+            .zip(&other.parts)
+            .map(|(a, b)| a.cmp(b))
+            .find(|o| o.is_ne())
+        {
+            Some(ordering) => Some(ordering),
+            None if self.parts.len() == other.parts.len() => Some(Ordering::Equal),
+            None => None,
+        }
+    }
+}
+
+/// `value` as a number if it's exactly `len` ASCII digits.
+fn parse_digits(value: &str, len: usize) -> Option<i64> {
+    (value.len() == len && value.chars().all(|c| c.is_ascii_digit()))
+        .then(|| value.parse().ok())
+        .flatten()
+}
+
+/// A time zone, `Z` or e.g. `+10:00`, as minutes ahead of UTC.
+fn parse_offset(value: &str) -> Option<i64> {
+    if value == "Z" {
+        return Some(0);
+    }
+    let sign = match value.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let (hours, minutes) = value[1..].split_once(':')?;
+    Some(sign * (parse_digits(hours, 2)? * 60 + parse_digits(minutes, 2)?))
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The year, month and day `days` after 1970-01-01, the inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    String(String),
+    Number(String),
+    DateTime(String),
+    Variable(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: &[&str] = &[
+    "!=", "!~", "<=", ">=", ".", "(", ")", "[", "]", "{", "}", ",", "|", "+", "-", "*", "/", "&", "=", "~", "<", ">",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+        } else if c == '\'' || c == '`' {
+            let (text, end) = read_quoted(&chars, i)?;
+            tokens.push(if c == '\'' {
+                Token::String(text)
+            } else {
+                Token::Identifier(text)
+            });
+            i = end;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            if chars.get(i) == Some(&'.') && chars.get(i + 1).is_some_and(char::is_ascii_digit) {
+                i += 1;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+            tokens.push(Token::Number(chars[start..i].iter().collect()));
+        } else if c == '@' {
+            let start = i + 1;
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || "-:.+".contains(chars[i])) {
+                i += 1;
+            }
+            tokens.push(Token::DateTime(chars[start..i].iter().collect()));
+        } else if c == '%' {
+            match chars.get(i + 1) {
+                Some('\'') | Some('`') => {
+                    let (text, end) = read_quoted(&chars, i + 1)?;
+                    tokens.push(Token::Variable(text));
+                    i = end;
+                }
+                _ => {
+                    let start = i + 1;
+                    i += 1;
+                    while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '-') {
+                        i += 1;
+                    }
+                    tokens.push(Token::Variable(chars[start..i].iter().collect()));
+                }
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '$' {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Identifier(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) else {
+                return error(format!("unexpected character `{}`", c));
+            };
+            tokens.push(Token::Symbol(symbol));
+            i += symbol.len();
+        }
+    }
+    Ok(tokens)
+}
+
+/// Reads a string or delimited identifier starting at the quote `chars[start]`, returning its text
+/// and the index after the closing quote.
+fn read_quoted(chars: &[char], start: usize) -> Result<(String, usize)> {
+    let quote = chars[start];
+    let mut text = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            c if c == quote => return Ok((text, i + 1)),
+            '\\' => {
+                i += 1;
+                match chars.get(i) {
+                    Some('n') => text.push('\n'),
+                    Some('r') => text.push('\r'),
+                    Some('t') => text.push('\t'),
+                    Some('f') => text.push('\u{c}'),
+                    Some('u') => {
+                        let hex: String = chars.get(i + 1..i + 5).unwrap_or_default().iter().collect();
+                        let Some(c) = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) else {
+                            return error("invalid unicode escape");
+                        };
+                        text.push(c);
+                        i += 4;
+                    }
+                    Some(c) => text.push(*c),
+                    None => break,
+                }
+            }
+            c => text.push(c),
+        }
+        i += 1;
+    }
+    error("unterminated string")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Implies,
+    Or,
+    Xor,
+    And,
+    In,
+    Contains,
+    Equal,
+    NotEqual,
+    Equivalent,
+    NotEquivalent,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Union,
+    Is,
+    As,
+    Add,
+    Subtract,
+    Concatenate,
+    Multiply,
+    Divide,
+    Div,
+    Mod,
+}
+
+impl BinaryOp {
+    fn from_token(token: &Token) -> Option<Self> {
+        let op = match token {
+            Token::Identifier(name) => match name.as_str() {
+                "implies" => BinaryOp::Implies,
+                "or" => BinaryOp::Or,
+                "xor" => BinaryOp::Xor,
+                "and" => BinaryOp::And,
+                "in" => BinaryOp::In,
+                "contains" => BinaryOp::Contains,
+                "is" => BinaryOp::Is,
+                "as" => BinaryOp::As,
+                "div" => BinaryOp::Div,
+                "mod" => BinaryOp::Mod,
+                _ => return None,
+            },
+            Token::Symbol(symbol) => match *symbol {
+                "=" => BinaryOp::Equal,
+                "!=" => BinaryOp::NotEqual,
+                "~" => BinaryOp::Equivalent,
+                "!~" => BinaryOp::NotEquivalent,
+                "<" => BinaryOp::Less,
+                "<=" => BinaryOp::LessOrEqual,
+                ">" => BinaryOp::Greater,
+                ">=" => BinaryOp::GreaterOrEqual,
+                "|" => BinaryOp::Union,
+                "+" => BinaryOp::Add,
+                "-" => BinaryOp::Subtract,
+                "&" => BinaryOp::Concatenate,
+                "*" => BinaryOp::Multiply,
+                "/" => BinaryOp::Divide,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    /// Binding power, higher binding tighter.
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Implies => 1,
+            BinaryOp::Or | BinaryOp::Xor => 2,
+            BinaryOp::And => 3,
+            BinaryOp::In | BinaryOp::Contains => 4,
+            BinaryOp::Equal | BinaryOp::NotEqual | BinaryOp::Equivalent | BinaryOp::NotEquivalent => 5,
+            BinaryOp::Less | BinaryOp::LessOrEqual | BinaryOp::Greater | BinaryOp::GreaterOrEqual => 6,
+            BinaryOp::Union => 7,
+            BinaryOp::Is | BinaryOp::As => 8,
+            BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Concatenate => 9,
+            BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Div | BinaryOp::Mod => 10,
+        }
+    }
+}
+
+const UNARY_PRECEDENCE: u8 = 11;
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Empty,
+    Boolean(bool),
+    String(String),
+    Number(String),
+    DateTime(String),
+    Variable(String),
+    /// `$this`, `$index` or `$total`.
+    Special(String),
+    /// An element of the target, or of the input when there is no target.
+    Member(Option<Box<Expr>>, String),
+    Function(Option<Box<Expr>>, String, Vec<Expr>),
+    Indexer(Box<Expr>, Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Type(BinaryOp, Box<Expr>, String),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn parse(source: &str) -> Result<Expr> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+        };
+        let expr = parser.parse_expr(0)?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => error(format!("unexpected {:?}", token)),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        if self.peek()
+            == Some(&Token::Symbol(match SYMBOLS.iter().find(|s| **s == symbol) {
+                Some(s) => s,
+                None => return false,
+            }))
+        {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<()> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            error(format!("expected `{}`", symbol))
+        }
+    }
+
+    fn parse_expr(&mut self, min_precedence: u8) -> Result<Expr> {
+        let mut lhs = self.parse_term()?;
+        loop {
+            if self.eat(".") {
+                let Some(Token::Identifier(name)) = self.next() else {
+                    return error("expected a name after `.`");
+                };
+                lhs = self.parse_invocation(Some(lhs), name)?;
+                continue;
+            }
+            if self.eat("[") {
+                let index = self.parse_expr(0)?;
+                self.expect("]")?;
+                lhs = Expr::Indexer(Box::new(lhs), Box::new(index));
+                continue;
+            }
+            let Some(op) = self.peek().and_then(BinaryOp::from_token) else {
+                break;
+            };
+            if op.precedence() <= min_precedence {
+                break;
+            }
+            self.position += 1;
+            lhs = match op {
+                BinaryOp::Is | BinaryOp::As => Expr::Type(op, Box::new(lhs), self.parse_type_name()?),
+                _ => Expr::Binary(op, Box::new(lhs), Box::new(self.parse_expr(op.precedence())?)),
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn parse_type_name(&mut self) -> Result<String> {
+        let Some(Token::Identifier(mut name)) = self.next() else {
+            return error("expected a type name");
+        };
+        if self.eat(".") {
+            let Some(Token::Identifier(qualified)) = self.next() else {
+                return error("expected a type name");
+            };
+            name = qualified;
+        }
+        Ok(name)
+    }
+
+    fn parse_term(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::String(s)) => Ok(Expr::String(s)),
+            Some(Token::Number(n)) => {
+                if matches!(self.peek(), Some(Token::String(_))) {
+                    return error("quantity literals aren't supported");
+                }
+                Ok(Expr::Number(n))
+            }
+            Some(Token::DateTime(d)) => Ok(Expr::DateTime(d)),
+            Some(Token::Variable(v)) => Ok(Expr::Variable(v)),
+            Some(Token::Symbol("(")) => {
+                let expr = self.parse_expr(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(Token::Symbol("{")) => {
+                self.expect("}")?;
+                Ok(Expr::Empty)
+            }
+            Some(Token::Symbol("-")) => Ok(Expr::Negate(Box::new(self.parse_expr(UNARY_PRECEDENCE)?))),
+            Some(Token::Symbol("+")) => self.parse_expr(UNARY_PRECEDENCE),
+            Some(Token::Identifier(name)) => match name.as_str() {
+                "true" => Ok(Expr::Boolean(true)),
+                "false" => Ok(Expr::Boolean(false)),
+                _ if name.starts_with('$') => Ok(Expr::Special(name)),
+                _ => self.parse_invocation(None, name),
+            },
+            Some(token) => error(format!("unexpected {:?}", token)),
+            None => error("unexpected end of expression"),
+        }
+    }
+
+    fn parse_invocation(&mut self, target: Option<Expr>, name: String) -> Result<Expr> {
+        let target = target.map(Box::new);
+        if !self.eat("(") {
+            return Ok(Expr::Member(target, name));
+        }
+        let mut args = Vec::new();
+        if !self.eat(")") {
+            loop {
+                args.push(self.parse_expr(0)?);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        Ok(Expr::Function(target, name, args))
+    }
+}
+
+/// The type named by a function argument such as the `Quantity` in `ofType(Quantity)`.
+fn get_type_argument(expr: &Expr) -> Result<&str> {
+    match expr {
+        Expr::Member(None, name) | Expr::Member(Some(_), name) => Ok(name),
+        _ => error("expected a type name"),
+    }
+}
+
+/// Evaluates an invariant with `focus` as the context, returning whether it holds. An empty
+/// result counts as holding.
+pub(crate) fn evaluate_invariant<'a>(expression: &str, focus: &Item<'a>, resource: &Item<'a>) -> Result<bool> {
+    let expr = Parser::parse(expression)?;
+    let context = Context {
+        resource,
+        focus,
+        this: None,
+        index: None,
+    };
+    let result = context.eval(&expr, std::slice::from_ref(focus))?;
+    Ok(to_boolean(&result)? != Some(false))
+}
+
+struct Context<'c, 'a> {
+    resource: &'c Item<'a>,
+    focus: &'c Item<'a>,
+    this: Option<&'c Item<'a>>,
+    index: Option<usize>,
+}
+
+/// The singleton boolean a collection stands for in a condition: empty for an empty collection,
+/// and true for any single value that isn't a boolean.
+fn to_boolean(items: &[Item]) -> Result<Option<bool>> {
+    match items {
+        [] => Ok(None),
+        [item] => Ok(Some(match item.get_scalar() {
+            Scalar::Boolean(b) => b,
+            _ => true,
+        })),
+        _ => error("expected a single value"),
+    }
+}
+
+fn to_string(items: &[Item]) -> Result<Option<String>> {
+    match items {
+        [] => Ok(None),
+        [item] => match item.get_scalar() {
+            Scalar::String(s) => Ok(Some(s.to_string())),
+            Scalar::Boolean(b) => Ok(Some(b.to_string())),
+            Scalar::Integer(i) => Ok(Some(i.to_string())),
+            Scalar::Decimal(d) => Ok(Some(d.to_string())),
+            _ => error("expected a primitive value"),
+        },
+        _ => error("expected a single value"),
+    }
+}
+
+fn to_integer(items: &[Item]) -> Result<i64> {
+    match items {
+        [item] => match item.get_scalar() {
+            Scalar::Integer(i) => Ok(i),
+            _ => error("expected an integer"),
+        },
+        _ => error("expected a single integer"),
+    }
+}
+
+fn contains_item(items: &[Item], item: &Item) -> bool {
+    items.iter().any(|i| scalar_eq(i.get_scalar(), item.get_scalar()))
+}
+
+fn distinct<'a>(items: Vec<Item<'a>>) -> Vec<Item<'a>> {
+    let mut result: Vec<Item> = Vec::new();
+    for item in items {
+        if !contains_item(&result, &item) {
+            result.push(item);
+        }
+    }
+    result
+}
+
+/// The earliest or latest instant a date, date-time or time of partial precision stands for.
+fn get_boundary(value: &str, low: bool) -> String {
+    let (date, time) = match value.split_once('T') {
+        Some((date, time)) => (date, Some(time)),
+        None if value.contains(':') => ("", Some(value)),
+        None => (value, None),
+    };
+    let mut result = String::new();
+    if !date.is_empty() {
+        let parts: Vec<&str> = date.split('-').collect();
+        let year = parts[0];
+        let month = parts.get(1).copied().unwrap_or(if low { "01" } else { "12" });
+        let day = match parts.get(2) {
+            Some(day) => day.to_string(),
+            None if low => "01".to_string(),
+            None => match month {
+                "02" => {
+                    let year: u32 = year.parse().unwrap_or(0);
+                    let leap = year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+                    if leap { "29" } else { "28" }.to_string()
+                }
+                "04" | "06" | "09" | "11" => "30".to_string(),
+                _ => "31".to_string(),
+            },
+        };
+        result = format!("{}-{}-{}", year, month, day);
+    }
+    let time = time.unwrap_or("");
+    // Leave any time zone out: values are compared as local times.
+    let time = time.split(['Z', '+']).next().unwrap_or(time);
+    let time = match time.rfind('-') {
+        Some(i) if i > 0 => &time[..i],
+        _ => time,
+    };
+    let default = if low { "00:00:00.000" } else { "23:59:59.999" };
+    let time = format!("{}{}", time, &default[time.len().min(default.len())..]);
+    if result.is_empty() {
+        time
+    } else {
+        format!("{}T{}", result, time)
+    }
+}
+
+impl<'c, 'a> Context<'c, 'a> {
+    fn with_this<'d>(&'d self, this: &'d Item<'a>, index: usize) -> Context<'d, 'a> {
+        Context {
+            resource: self.resource,
+            focus: self.focus,
+            this: Some(this),
+            index: Some(index),
+        }
+    }
+
+    /// Evaluates `expr` once per item of `input`, collecting the results.
+    fn eval_each(&self, expr: &Expr, input: &[Item<'a>]) -> Result<Vec<Vec<Item<'a>>>> {
+        input
+            .iter()
+            .enumerate()
+            .map(|(index, item)| self.with_this(item, index).eval(expr, std::slice::from_ref(item)))
+            .collect()
+    }
+
+    fn eval(&self, expr: &Expr, input: &[Item<'a>]) -> Result<Vec<Item<'a>>> {
+        match expr {
+            Expr::Empty => Ok(Vec::new()),
+            Expr::Boolean(b) => Ok(vec![Item::Boolean(*b)]),
+            Expr::String(s) => Ok(vec![Item::String(s.clone())]),
+            Expr::Number(n) => match n.parse() {
+                Ok(i) => Ok(vec![Item::Integer(i)]),
+                Err(_) => Ok(vec![Item::Decimal(n.parse().unwrap_or(f64::NAN))]),
+            },
+            Expr::DateTime(d) => Ok(vec![Item::DateTime(d.clone())]),
+            Expr::Variable(name) => match name.as_str() {
+                "resource" | "rootResource" => Ok(vec![self.resource.clone()]),
+                "context" => Ok(vec![self.focus.clone()]),
+                "ucum" => Ok(vec![Item::String("http://unitsofmeasure.org".to_string())]),
+                "sct" => Ok(vec![Item::String("http://snomed.info/sct".to_string())]),
+                "loinc" => Ok(vec![Item::String("http://loinc.org".to_string())]),
+                _ => error(format!("unsupported variable %{}", name)),
+            },
+            Expr::Special(name) => match name.as_str() {
+                "$this" => Ok(self.this.unwrap_or(self.focus).clone()).map(|item| vec![item]),
+                "$index" => match self.index {
+                    Some(index) => Ok(vec![Item::Integer(index as i64)]),
+                    None => Ok(Vec::new()),
+                },
+                _ => error(format!("unsupported {}", name)),
+            },
+            Expr::Member(target, name) => {
+                let input = match target {
+                    Some(target) => self.eval(target, input)?,
+                    None => {
+                        // A leading type name, e.g. `Patient` in `Patient.name`, selects the input.
+                        if name.starts_with(|c: char| c.is_ascii_uppercase()) {
+                            let typed: Vec<Item> = input
+                                .iter()
+                                .filter(|item| matches!(item, Item::Node(Node { type_name: Some(t), .. }) if t == name))
+                                .cloned()
+                                .collect();
+                            if !typed.is_empty() {
+                                return Ok(typed);
+                            }
+                        }
+                        input.to_vec()
+                    }
+                };
+                Ok(input
+                    .iter()
+                    .flat_map(|item| item.get_children(name).into_iter().map(|(_, child)| child))
+                    .collect())
+            }
+            Expr::Function(target, name, args) => {
+                let input = match target {
+                    Some(target) => self.eval(target, input)?,
+                    None => input.to_vec(),
+                };
+                self.call(name, args, input)
+            }
+            Expr::Indexer(target, index) => {
+                let items = self.eval(target, input)?;
+                let index = to_integer(&self.eval(index, input)?)?;
+                Ok(usize::try_from(index)
+                    .ok()
+                    .and_then(|i| items.into_iter().nth(i))
+                    .into_iter()
+                    .collect())
+            }
+            Expr::Negate(operand) => self
+                .eval(operand, input)?
+                .into_iter()
+                .map(|item| match item.get_scalar() {
+                    Scalar::Integer(i) => Ok(Item::Integer(-i)),
+                    Scalar::Decimal(d) => Ok(Item::Decimal(-d)),
+                    _ => error("can only negate numbers"),
+                })
+                .collect(),
+            Expr::Type(op, target, type_name) => {
+                let items = self.eval(target, input)?;
+                match (op, items.as_slice()) {
+                    (BinaryOp::Is, []) => Ok(Vec::new()),
+                    (BinaryOp::Is, [item]) => Ok(vec![Item::Boolean(item.is_type(type_name))]),
+                    (BinaryOp::Is, _) => error("`is` needs a single value"),
+                    _ => Ok(items.into_iter().filter(|item| item.is_type(type_name)).collect()),
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => self.eval_binary(*op, lhs, rhs, input),
+        }
+    }
+
+    fn eval_binary(&self, op: BinaryOp, lhs: &Expr, rhs: &Expr, input: &[Item<'a>]) -> Result<Vec<Item<'a>>> {
+        let boolean = |b: Option<bool>| Ok(b.map(Item::Boolean).into_iter().collect());
+        let left = self.eval(lhs, input)?;
+        // The logical operators only evaluate their right side when it can change the result.
+        match op {
+            BinaryOp::And => {
+                let l = to_boolean(&left)?;
+                if l == Some(false) {
+                    return boolean(Some(false));
+                }
+                return match (l, to_boolean(&self.eval(rhs, input)?)?) {
+                    (_, Some(false)) => boolean(Some(false)),
+                    (Some(true), Some(true)) => boolean(Some(true)),
+                    _ => boolean(None),
+                };
+            }
+            BinaryOp::Or => {
+                let l = to_boolean(&left)?;
+                if l == Some(true) {
+                    return boolean(Some(true));
+                }
+                return match (l, to_boolean(&self.eval(rhs, input)?)?) {
+                    (_, Some(true)) => boolean(Some(true)),
+                    (Some(false), Some(false)) => boolean(Some(false)),
+                    _ => boolean(None),
+                };
+            }
+            BinaryOp::Implies => {
+                let l = to_boolean(&left)?;
+                if l == Some(false) {
+                    return boolean(Some(true));
+                }
+                return match (l, to_boolean(&self.eval(rhs, input)?)?) {
+                    (Some(true), r) => boolean(r),
+                    (None, Some(true)) => boolean(Some(true)),
+                    _ => boolean(None),
+                };
+            }
+            _ => {}
+        }
+        let right = self.eval(rhs, input)?;
+        match op {
+            BinaryOp::Xor => match (to_boolean(&left)?, to_boolean(&right)?) {
+                (Some(l), Some(r)) => boolean(Some(l != r)),
+                _ => boolean(None),
+            },
+            BinaryOp::Union => {
+                let mut items = left;
+                items.extend(right);
+                Ok(distinct(items))
+            }
+            BinaryOp::In | BinaryOp::Contains => {
+                let (element, collection) = if op == BinaryOp::In {
+                    (left, right)
+                } else {
+                    (right, left)
+                };
+                match element.as_slice() {
+                    [] => Ok(Vec::new()),
+                    [item] => boolean(Some(contains_item(&collection, item))),
+                    _ => error("membership needs a single value"),
+                }
+            }
+            BinaryOp::Equal | BinaryOp::NotEqual | BinaryOp::Equivalent | BinaryOp::NotEquivalent => {
+                let equivalent = matches!(op, BinaryOp::Equivalent | BinaryOp::NotEquivalent);
+                if left.is_empty() || right.is_empty() {
+                    return if equivalent {
+                        boolean(Some((left.len() == right.len()) == (op == BinaryOp::Equivalent)))
+                    } else {
+                        Ok(Vec::new())
+                    };
+                }
+                let equal = left.len() == right.len()
+                    && left
+                        .iter()
+                        .zip(&right)
+                        .all(|(l, r)| match (l.get_scalar(), r.get_scalar()) {
+                            (Scalar::String(l), Scalar::String(r)) if equivalent => {
+                                l.trim().eq_ignore_ascii_case(r.trim())
+                            }
+                            (l, r) => scalar_eq(l, r),
+                        });
+                boolean(Some(equal == matches!(op, BinaryOp::Equal | BinaryOp::Equivalent)))
+            }
+            BinaryOp::Less | BinaryOp::LessOrEqual | BinaryOp::Greater | BinaryOp::GreaterOrEqual => {
+                match (left.as_slice(), right.as_slice()) {
+                    ([l], [r]) => {
+                        let Some(ordering) = scalar_cmp(l.get_scalar(), r.get_scalar())? else {
+                            return Ok(Vec::new());
+                        };
+                        boolean(Some(match op {
+                            BinaryOp::Less => ordering == Ordering::Less,
+                            BinaryOp::LessOrEqual => ordering != Ordering::Greater,
+                            BinaryOp::Greater => ordering == Ordering::Greater,
+                            _ => ordering != Ordering::Less,
+                        }))
+                    }
+                    ([], _) | (_, []) => Ok(Vec::new()),
+                    _ => error("comparison needs single values"),
+                }
+            }
+            BinaryOp::Concatenate => {
+                let l = to_string(&left)?.unwrap_or_default();
+                let r = to_string(&right)?.unwrap_or_default();
+                Ok(vec![Item::String(l + &r)])
+            }
+            _ => self.eval_arithmetic(op, &left, &right),
+        }
+    }
+
+    fn eval_arithmetic(&self, op: BinaryOp, left: &[Item<'a>], right: &[Item<'a>]) -> Result<Vec<Item<'a>>> {
+        let (l, r) = match (left, right) {
+            ([l], [r]) => (l.get_scalar(), r.get_scalar()),
+            ([], _) | (_, []) => return Ok(Vec::new()),
+            _ => return error("arithmetic needs single values"),
+        };
+        let item = match (op, l, r) {
+            (BinaryOp::Add, Scalar::String(l), Scalar::String(r)) => Item::String(format!("{}{}", l, r)),
+            (BinaryOp::Add, Scalar::Integer(l), Scalar::Integer(r)) => Item::Integer(l.wrapping_add(r)),
+            (BinaryOp::Subtract, Scalar::Integer(l), Scalar::Integer(r)) => Item::Integer(l.wrapping_sub(r)),
+            (BinaryOp::Multiply, Scalar::Integer(l), Scalar::Integer(r)) => Item::Integer(l.wrapping_mul(r)),
+            (BinaryOp::Div | BinaryOp::Mod, Scalar::Integer(_), Scalar::Integer(0)) => return Ok(Vec::new()),
+            (BinaryOp::Div, Scalar::Integer(l), Scalar::Integer(r)) => Item::Integer(l.wrapping_div(r)),
+            (BinaryOp::Mod, Scalar::Integer(l), Scalar::Integer(r)) => Item::Integer(l.wrapping_rem(r)),
+            _ => {
+                let (Some(l), Some(r)) = (to_f64(l), to_f64(r)) else {
+                    return error("arithmetic needs numbers");
+                };
+                match op {
+                    BinaryOp::Add => Item::Decimal(l + r),
+                    BinaryOp::Subtract => Item::Decimal(l - r),
+                    BinaryOp::Multiply => Item::Decimal(l * r),
+                    _ if r == 0.0 => return Ok(Vec::new()),
+                    BinaryOp::Divide => Item::Decimal(l / r),
+                    BinaryOp::Div => Item::Integer((l / r).trunc() as i64),
+                    _ => Item::Decimal(l % r),
+                }
+            }
+        };
+        Ok(vec![item])
+    }
+
+    fn call(&self, name: &str, args: &[Expr], input: Vec<Item<'a>>) -> Result<Vec<Item<'a>>> {
+        let arity = |n: usize| {
+            if args.len() == n {
+                Ok(())
+            } else {
+                error(format!("{}() takes {} arguments", name, n))
+            }
+        };
+        // Arguments other than criteria are evaluated against the invocation's context.
+        let arg = |i: usize| self.eval(&args[i], std::slice::from_ref(self.this.unwrap_or(self.focus)));
+        let string_arg = |i: usize| -> Result<String> {
+            to_string(&arg(i)?)?.ok_or_else(|| Error(format!("{}() needs a string argument", name)))
+        };
+        let boolean = |b: bool| Ok(vec![Item::Boolean(b)]);
+        let string_input = || to_string(&input);
+        match name {
+            "empty" => {
+                arity(0)?;
+                boolean(input.is_empty())
+            }
+            "exists" => match args {
+                [] => boolean(!input.is_empty()),
+                [criteria] => {
+                    for result in self.eval_each(criteria, &input)? {
+                        if to_boolean(&result)? == Some(true) {
+                            return boolean(true);
+                        }
+                    }
+                    boolean(false)
+                }
+                _ => error("exists() takes at most one argument"),
+            },
+            "all" => {
+                arity(1)?;
+                for result in self.eval_each(&args[0], &input)? {
+                    if to_boolean(&result)? != Some(true) {
+                        return boolean(false);
+                    }
+                }
+                boolean(true)
+            }
+            "allTrue" | "anyTrue" | "allFalse" | "anyFalse" => {
+                arity(0)?;
+                let values: Vec<Option<bool>> = input
+                    .iter()
+                    .map(|item| to_boolean(std::slice::from_ref(item)))
+                    .collect::<Result<_>>()?;
+                boolean(match name {
+                    "allTrue" => values.iter().all(|v| *v == Some(true)),
+                    "anyTrue" => values.contains(&Some(true)),
+                    "allFalse" => values.iter().all(|v| *v == Some(false)),
+                    _ => values.contains(&Some(false)),
+                })
+            }
+            "count" => {
+                arity(0)?;
+                Ok(vec![Item::Integer(input.len() as i64)])
+            }
+            "distinct" => {
+                arity(0)?;
+                Ok(distinct(input))
+            }
+            "isDistinct" => {
+                arity(0)?;
+                let len = input.len();
+                boolean(distinct(input).len() == len)
+            }
+            "where" => {
+                arity(1)?;
+                let results = self.eval_each(&args[0], &input)?;
+                let mut items = Vec::new();
+                for (item, result) in input.into_iter().zip(results) {
+                    if to_boolean(&result)? == Some(true) {
+                        items.push(item);
+                    }
+                }
+                Ok(items)
+            }
+            "select" => {
+                arity(1)?;
+                Ok(self.eval_each(&args[0], &input)?.into_iter().flatten().collect())
+            }
+            "repeat" => {
+                arity(1)?;
+                let mut items: Vec<Item> = Vec::new();
+                let mut next = input;
+                while !next.is_empty() {
+                    let found: Vec<Item> = self.eval_each(&args[0], &next)?.into_iter().flatten().collect();
+                    next = found.into_iter().filter(|item| !contains_item(&items, item)).collect();
+                    next = distinct(next);
+                    items.extend(next.iter().cloned());
+                }
+                Ok(items)
+            }
+            "iif" => {
+                if args.len() != 2 && args.len() != 3 {
+                    return error("iif() takes two or three arguments");
+                }
+                let this = input.first().unwrap_or(self.focus);
+                let context = self.with_this(this, 0);
+                let condition = context.eval(&args[0], std::slice::from_ref(this))?;
+                if to_boolean(&condition)? == Some(true) {
+                    context.eval(&args[1], std::slice::from_ref(this))
+                } else if let Some(otherwise) = args.get(2) {
+                    context.eval(otherwise, std::slice::from_ref(this))
+                } else {
+                    Ok(Vec::new())
+                }
+            }
+            "first" => Ok(input.into_iter().take(1).collect()),
+            "last" => Ok(input.into_iter().last().into_iter().collect()),
+            "tail" => Ok(input.into_iter().skip(1).collect()),
+            "skip" => {
+                arity(1)?;
+                let n = to_integer(&arg(0)?)?.max(0) as usize;
+                Ok(input.into_iter().skip(n).collect())
+            }
+            "take" => {
+                arity(1)?;
+                let n = to_integer(&arg(0)?)?.max(0) as usize;
+                Ok(input.into_iter().take(n).collect())
+            }
+            "single" => match input.len() {
+                0 | 1 => Ok(input),
+                _ => error("single() on more than one value"),
+            },
+            "not" => {
+                arity(0)?;
+                Ok(to_boolean(&input)?.map(|b| Item::Boolean(!b)).into_iter().collect())
+            }
+            "hasValue" => {
+                arity(0)?;
+                boolean(match input.as_slice() {
+                    [item] => !matches!(item.get_scalar(), Scalar::Json(_) | Scalar::Absent),
+                    _ => false,
+                })
+            }
+            "children" => {
+                arity(0)?;
+                Ok(input.iter().flat_map(Item::get_all_children).collect())
+            }
+            "descendants" => {
+                arity(0)?;
+                let mut items = Vec::new();
+                let mut next: Vec<Item> = input.iter().flat_map(Item::get_all_children).collect();
+                while !next.is_empty() {
+                    let children = next.iter().flat_map(Item::get_all_children).collect();
+                    items.append(&mut next);
+                    next = children;
+                }
+                Ok(items)
+            }
+            "ofType" | "as" => {
+                arity(1)?;
+                let type_name = get_type_argument(&args[0])?;
+                Ok(input.into_iter().filter(|item| item.is_type(type_name)).collect())
+            }
+            "is" => {
+                arity(1)?;
+                let type_name = get_type_argument(&args[0])?;
+                match input.as_slice() {
+                    [] => Ok(Vec::new()),
+                    [item] => boolean(item.is_type(type_name)),
+                    _ => error("is() needs a single value"),
+                }
+            }
+            "trace" => Ok(input),
+            "extension" => {
+                arity(1)?;
+                let url = string_arg(0)?;
+                Ok(input
+                    .iter()
+                    .flat_map(|item| item.get_children("extension"))
+                    .map(|(_, extension)| extension)
+                    .filter(|extension| {
+                        extension
+                            .get_children("url")
+                            .iter()
+                            .any(|(_, u)| matches!(u.get_scalar(), Scalar::String(u) if u == url))
+                    })
+                    .collect())
+            }
+            "combine" => {
+                arity(1)?;
+                let mut items = input;
+                items.extend(arg(0)?);
+                Ok(items)
+            }
+            "union" => {
+                arity(1)?;
+                let mut items = input;
+                items.extend(arg(0)?);
+                Ok(distinct(items))
+            }
+            "intersect" | "exclude" => {
+                arity(1)?;
+                let other = arg(0)?;
+                let keep = name == "intersect";
+                let items = input
+                    .into_iter()
+                    .filter(|item| contains_item(&other, item) == keep)
+                    .collect();
+                Ok(if keep { distinct(items) } else { items })
+            }
+            "subsetOf" | "supersetOf" => {
+                arity(1)?;
+                let other = arg(0)?;
+                let (subset, superset) = if name == "subsetOf" {
+                    (&input, &other)
+                } else {
+                    (&other, &input)
+                };
+                boolean(subset.iter().all(|item| contains_item(superset, item)))
+            }
+            "length" => {
+                arity(0)?;
+                Ok(string_input()?
+                    .map(|s| Item::Integer(s.chars().count() as i64))
+                    .into_iter()
+                    .collect())
+            }
+            "startsWith" | "endsWith" | "contains" => {
+                arity(1)?;
+                let Some(s) = string_input()? else {
+                    return Ok(Vec::new());
+                };
+                let other = string_arg(0)?;
+                boolean(match name {
+                    "startsWith" => s.starts_with(&other),
+                    "endsWith" => s.ends_with(&other),
+                    _ => s.contains(&other),
+                })
+            }
+            "matches" | "matchesFull" => {
+                arity(1)?;
+                let Some(s) = string_input()? else {
+                    return Ok(Vec::new());
+                };
+                let pattern = string_arg(0)?;
+                let pattern = if name == "matchesFull" {
+                    format!("^(?:{})$", pattern)
+                } else {
+                    pattern
+                };
+                match regex::Regex::new(&pattern) {
+                    Ok(regex) => boolean(regex.is_match(&s)),
+                    Err(_) => error(format!("invalid regular expression {:?}", pattern)),
+                }
+            }
+            "replaceMatches" => {
+                arity(2)?;
+                let Some(s) = string_input()? else {
+                    return Ok(Vec::new());
+                };
+                let pattern = string_arg(0)?;
+                let Ok(regex) = regex::Regex::new(&pattern) else {
+                    return error(format!("invalid regular expression {:?}", pattern));
+                };
+                Ok(vec![Item::String(
+                    regex.replace_all(&s, string_arg(1)?.as_str()).into_owned(),
+                )])
+            }
+            "replace" => {
+                arity(2)?;
+                let Some(s) = string_input()? else {
+                    return Ok(Vec::new());
+                };
+                Ok(vec![Item::String(s.replace(&string_arg(0)?, &string_arg(1)?))])
+            }
+            "indexOf" => {
+                arity(1)?;
+                let Some(s) = string_input()? else {
+                    return Ok(Vec::new());
+                };
+                let index = s.find(&string_arg(0)?).map_or(-1, |i| s[..i].chars().count() as i64);
+                Ok(vec![Item::Integer(index)])
+            }
+            "substring" => {
+                let Some(s) = string_input()? else {
+                    return Ok(Vec::new());
+                };
+                let start = to_integer(&arg(0)?)?;
+                let chars: Vec<char> = s.chars().collect();
+                if start < 0 || start as usize >= chars.len() {
+                    return Ok(Vec::new());
+                }
+                let len = match args.len() {
+                    1 => chars.len(),
+                    2 => to_integer(&arg(1)?)?.max(0) as usize,
+                    _ => return error("substring() takes one or two arguments"),
+                };
+                Ok(vec![Item::String(chars[start as usize..].iter().take(len).collect())])
+            }
+            "upper" | "lower" => {
+                arity(0)?;
+                Ok(string_input()?
+                    .map(|s| {
+                        Item::String(if name == "upper" {
+                            s.to_uppercase()
+                        } else {
+                            s.to_lowercase()
+                        })
+                    })
+                    .into_iter()
+                    .collect())
+            }
+            "toString" => {
+                arity(0)?;
+                Ok(string_input()?.map(Item::String).into_iter().collect())
+            }
+            "toInteger" => {
+                arity(0)?;
+                Ok(match input.as_slice() {
+                    [item] => match item.get_scalar() {
+                        Scalar::Integer(i) => vec![Item::Integer(i)],
+                        Scalar::String(s) => s.parse().map(Item::Integer).into_iter().collect(),
+                        Scalar::Boolean(b) => vec![Item::Integer(b as i64)],
+                        _ => Vec::new(),
+                    },
+                    _ => Vec::new(),
+                })
+            }
+            "lowBoundary" | "highBoundary" => Ok(input
+                .into_iter()
+                .map(|item| match item.get_scalar() {
+                    Scalar::String(s) => Item::DateTime(get_boundary(s, name == "lowBoundary")),
+                    _ => item,
+                })
+                .collect()),
+            _ => error(format!("unsupported function {}()", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const ELE_1: &str = "hasValue() or (children().count() > id.count())";
+    const DOM_2: &str = "contained.contained.empty()";
+    const DOM_3: &str = "contained.where(((id.exists() and ('#'+id in (%resource.descendants().reference | \
+        %resource.descendants().ofType(canonical) | %resource.descendants().ofType(uri) | \
+        %resource.descendants().ofType(url)))) or descendants().where(reference = '#').exists() or \
+        descendants().where(ofType(canonical) = '#').exists() or descendants().where(ofType(uri) = '#').exists()).not())\
+        .trace('unmatched', id).empty()";
+    const DOM_6: &str = "text.`div`.exists()";
+    const PAT_1: &str = "name.exists() or telecom.exists() or address.exists() or organization.exists()";
+    const PER_1: &str = "start.hasValue().not() or end.hasValue().not() or (start <= end)";
+
+    fn holds(expression: &str, type_name: &str, value: Value) -> bool {
+        let root = Item::root(&value, type_name);
+        evaluate_invariant(expression, &root, &root).unwrap()
+    }
+
+    /// Evaluates `expression` over a primitive with the value `value` and the `_name` sibling `extra`.
+    fn holds_for_primitive(expression: &str, value: Option<Value>, extra: Option<Value>) -> bool {
+        let node = Item::Node(Node {
+            value: value.as_ref(),
+            extra: extra.as_ref(),
+            type_name: Some("string".to_string()),
+        });
+        evaluate_invariant(expression, &node, &node).unwrap()
+    }
+
+    #[test]
+    fn ele_1() {
+        assert!(holds(ELE_1, "Coding", json!({"system": "http://loinc.org"})));
+        assert!(!holds(ELE_1, "Coding", json!({"id": "a"})));
+        assert!(!holds(ELE_1, "Coding", json!({})));
+        assert!(holds_for_primitive(ELE_1, Some(json!("x")), None));
+        let extension = json!({"extension": [{"url": "http://example.org", "valueBoolean": true}]});
+        assert!(holds_for_primitive(ELE_1, None, Some(extension)));
+        assert!(!holds_for_primitive(ELE_1, None, Some(json!({"id": "a"}))));
+    }
+
+    #[test]
+    fn dom_2() {
+        let patient = json!({"resourceType": "Patient", "id": "p"});
+        assert!(holds(DOM_2, "Observation", json!({"contained": [patient]})));
+        let nested = json!({"resourceType": "Patient", "contained": [{"resourceType": "Patient"}]});
+        assert!(!holds(DOM_2, "Observation", json!({"contained": [nested]})));
+    }
+
+    #[test]
+    fn dom_3() {
+        let observation = json!({
+            "resourceType": "Observation",
+            "contained": [{"resourceType": "Patient", "id": "p1"}],
+            "subject": {"reference": "#p1"},
+        });
+        assert!(holds(DOM_3, "Observation", observation));
+        let observation = json!({
+            "resourceType": "Observation",
+            "contained": [{"resourceType": "Patient", "id": "p1"}],
+            "subject": {"reference": "Patient/p1"},
+        });
+        assert!(!holds(DOM_3, "Observation", observation));
+        // A contained resource may instead refer back to the container.
+        let observation = json!({
+            "resourceType": "Observation",
+            "contained": [{"resourceType": "Patient", "link": [{"other": {"reference": "#"}}]}],
+        });
+        assert!(holds(DOM_3, "Observation", observation));
+    }
+
+    #[test]
+    fn dom_6() {
+        let text = json!({"status": "generated", "div": "<div xmlns=\"http://www.w3.org/1999/xhtml\">x</div>"});
+        assert!(holds(
+            DOM_6,
+            "Patient",
+            json!({"resourceType": "Patient", "text": text})
+        ));
+        assert!(!holds(DOM_6, "Patient", json!({"resourceType": "Patient"})));
+    }
+
+    #[test]
+    fn pat_1() {
+        assert!(holds(PAT_1, "BackboneElement", json!({"name": {"family": "Doe"}})));
+        assert!(holds(
+            PAT_1,
+            "BackboneElement",
+            json!({"organization": {"reference": "Organization/1"}})
+        ));
+        assert!(!holds(PAT_1, "BackboneElement", json!({"gender": "female"})));
+    }
+
+    #[test]
+    fn per_1() {
+        let period = |start: &str, end: &str| json!({"start": start, "end": end});
+        assert!(holds(PER_1, "Period", period("2020-01-01", "2020-01-02")));
+        assert!(!holds(PER_1, "Period", period("2020-01-02", "2020-01-01T10:00:00Z")));
+        // 10:00 at +10:00 is midnight UTC, before 01:00 UTC, though it sorts after it as a string.
+        assert!(holds(
+            PER_1,
+            "Period",
+            period("2020-01-01T10:00:00+10:00", "2020-01-01T01:00:00Z")
+        ));
+        assert!(!holds(
+            PER_1,
+            "Period",
+            period("2020-01-01T02:00:00Z", "2020-01-01T10:00:00+10:00")
+        ));
+        // The same day to the precision of both, which can't tell which comes first.
+        assert!(holds(PER_1, "Period", period("2020-01-01T10:00:00Z", "2020-01-01")));
+        assert!(holds(PER_1, "Period", json!({"start": "2020-01-01"})));
+    }
+
+    #[test]
+    fn temporal_comparison() {
+        let compare = |a: &str, b: &str| Temporal::parse(a).unwrap().compare(&Temporal::parse(b).unwrap());
+        assert_eq!(compare("2020", "2021"), Some(Ordering::Less));
+        assert_eq!(compare("2020-02", "2020-01-31"), Some(Ordering::Greater));
+        assert_eq!(compare("2020-01", "2020-01-31"), None);
+        assert_eq!(
+            compare("2020-01-01T00:00:00Z", "2019-12-31T19:00:00-05:00"),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(
+            compare("2020-03-01T00:30:00+01:00", "2020-02-29T23:30:00Z"),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(
+            compare("2020-01-01T10:00:00", "2020-01-01T10:00:00.000Z"),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(
+            compare("2020-01-01T10:00:00.5Z", "2020-01-01T10:00:00.499Z"),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(compare("T10:00", "10:00:00"), None);
+        assert_eq!(compare("T10:00", "10:01:00"), Some(Ordering::Less));
+        assert_eq!(compare("10:00:00", "2020-01-01"), None);
+        for invalid in [
+            "2020-13",
+            "2020-01-32",
+            "20-01-01",
+            "2020-01T10:00",
+            "25:00",
+            "2020-01-01-01",
+            "abc",
+        ] {
+            assert_eq!(Temporal::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn temporal_literals() {
+        let patient = json!({"resourceType": "Patient", "birthDate": "1974-12-25"});
+        assert!(holds("birthDate < @1980", "Patient", patient.clone()));
+        assert!(holds("birthDate = @1974-12-25", "Patient", patient.clone()));
+        assert!(!holds("birthDate > @1975", "Patient", patient.clone()));
+        assert!(holds("(birthDate > @1974-12-25T10:00:00Z).empty()", "Patient", patient));
+    }
+}
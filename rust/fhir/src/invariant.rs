@@ -0,0 +1,116 @@
+//! Invariants, the FHIRPath constraints such as `dom-3` or `obs-6` that definitions put on
+//! elements, and the issues found by evaluating them.
+//!
+//! Each generated type lists the invariants on its element and its direct children in
+//! `INVARIANTS`, and `validate_invariants()` evaluates them over the value and everything in it.
+
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::fhirpath::{self, Item};
+
+/// Whether breaking an invariant makes a value invalid or only merits a warning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A constraint on an element, as given in its definition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Invariant {
+    /// Id of the constrained element, e.g. `Patient.contact`.
+    pub element: &'static str,
+    /// The invariant's key, e.g. `pat-1`.
+    pub key: &'static str,
+    pub severity: Severity,
+    /// What the invariant requires, in words.
+    pub human: &'static str,
+    /// The FHIRPath expression that must hold.
+    pub expression: &'static str,
+}
+
+/// A value found to break, or not to be checkable against, an invariant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvariantIssue {
+    pub key: &'static str,
+    pub severity: Severity,
+    pub human: &'static str,
+    /// Where the value is, e.g. `Patient.contact[1]` or `Observation.valueQuantity`.
+    pub path: String,
+    pub kind: InvariantIssueKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvariantIssueKind {
+    /// The expression evaluated to false.
+    Failed,
+    /// The expression couldn't be evaluated, for the given reason, e.g. because it calls a
+    /// function such as `resolve()` that needs more than the resource itself.
+    Unevaluated(String),
+}
+
+impl fmt::Display for InvariantIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{} {} at {}: {}", severity, self.key, self.path, self.human)?;
+        if let InvariantIssueKind::Unevaluated(ref reason) = self.kind {
+            write!(f, " (not evaluated: {})", reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// Evaluates the invariants of the generated type for `element` over `value`, the type's JSON at
+/// `path`, and those on its children over each of their values. `resource` is the JSON of the
+/// resource holding the value, for `%resource`.
+#[doc(hidden)]
+pub fn check_invariants(
+    invariants: &[Invariant],
+    element: &str,
+    type_name: &str,
+    value: &Value,
+    resource: &Value,
+    path: &str,
+    issues: &mut Vec<InvariantIssue>,
+) {
+    let focus = Item::root(value, type_name);
+    let resource_type = resource.get("resourceType").and_then(Value::as_str);
+    let resource = Item::root(resource, resource_type.unwrap_or(type_name));
+    for invariant in invariants {
+        let targets = if invariant.element == element {
+            vec![(path.to_string(), focus.clone())]
+        } else {
+            let Some(name) = invariant
+                .element
+                .strip_prefix(element)
+                .and_then(|n| n.strip_prefix('.'))
+            else {
+                continue;
+            };
+            focus
+                .get_children(name.trim_end_matches("[x]"))
+                .into_iter()
+                .map(|(child, item)| (format!("{}.{}", path, child), item))
+                .collect()
+        };
+        for (path, item) in targets {
+            let kind = match fhirpath::evaluate_invariant(invariant.expression, &item, &resource) {
+                Ok(true) => continue,
+                Ok(false) => InvariantIssueKind::Failed,
+                Err(e) => InvariantIssueKind::Unevaluated(e.to_string()),
+            };
+            issues.push(InvariantIssue {
+                key: invariant.key,
+                severity: invariant.severity,
+                human: invariant.human,
+                path,
+                kind,
+            });
+        }
+    }
+}
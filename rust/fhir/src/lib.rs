@@ -1,3 +1,13 @@
+pub mod code;
+pub mod element;
+pub mod extension;
+mod fhirpath;
 mod generated;
+pub mod invariant;
+pub mod primitives;
+pub mod profile;
+pub mod reference;
+#[doc(hidden)]
+pub mod serde_support;
 
 pub use generated::*;
@@ -0,0 +1,1136 @@
+//! Rust types for the FHIR primitive datatypes.
+//!
+//! Each type validates the lexical rules of its StructureDefinition when parsed (`FromStr`) or
+//! deserialized, and renders back to exactly the text it was parsed from, so that e.g. the
+//! precision of a `dateTime` or `decimal` survives a round trip.
+
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+/// A value that doesn't match the lexical rules of a primitive type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrimitiveError {
+    pub type_name: &'static str,
+    pub value: String,
+}
+
+impl PrimitiveError {
+    fn new(type_name: &'static str, value: &str) -> Self {
+        PrimitiveError {
+            type_name,
+            value: value.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for PrimitiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid {}: {:?}", self.type_name, self.value)
+    }
+}
+
+impl std::error::Error for PrimitiveError {}
+
+/// Implements `Serialize`/`Deserialize` as a JSON string through `Display`/`FromStr`.
+macro_rules! serde_via_str {
+    ($name:ident, $expecting:literal) => {
+        impl serde::Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                struct StrVisitor;
+                impl serde::de::Visitor<'_> for StrVisitor {
+                    type Value = $name;
+
+                    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                        formatter.write_str($expecting)
+                    }
+
+                    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+                    where
+                        E: serde::de::Error,
+                    {
+                        v.parse().map_err(E::custom)
+                    }
+                }
+                deserializer.deserialize_str(StrVisitor)
+            }
+        }
+    };
+}
+
+/// A string-based primitive. `validate` checks the lexical rules of the type.
+macro_rules! string_primitive {
+    ($(#[$meta:meta])* $name:ident, $type_name:literal, $validate:expr) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(String);
+
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+
+            pub fn into_string(self) -> String {
+                self.0
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = PrimitiveError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let validate: fn(&str) -> bool = $validate;
+                if validate(s) {
+                    Ok($name(s.to_string()))
+                } else {
+                    Err(PrimitiveError::new($type_name, s))
+                }
+            }
+        }
+
+        impl TryFrom<String> for $name {
+            type Error = PrimitiveError;
+
+            fn try_from(s: String) -> Result<Self, Self::Error> {
+                let validate: fn(&str) -> bool = $validate;
+                if validate(&s) {
+                    Ok($name(s))
+                } else {
+                    Err(PrimitiveError::new($type_name, &s))
+                }
+            }
+        }
+
+        impl TryFrom<&str> for $name {
+            type Error = PrimitiveError;
+
+            fn try_from(s: &str) -> Result<Self, Self::Error> {
+                s.parse()
+            }
+        }
+
+        impl Deref for $name {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(value: $name) -> String {
+                value.0
+            }
+        }
+
+        serde_via_str!($name, $type_name);
+    };
+}
+
+/// `boolean`: `true` or `false`.
+pub type Boolean = bool;
+
+/// `integer`: a signed 32-bit integer.
+pub type Integer = i32;
+
+/// A non-negative integer from `$min` to `$max`.
+macro_rules! bounded_int {
+    ($(#[$meta:meta])* $name:ident, $type_name:literal, $min:expr, $max:expr) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(u32);
+
+        impl $name {
+            pub const MIN: u32 = $min;
+            pub const MAX: u32 = $max;
+
+            pub fn new(value: u32) -> Option<Self> {
+                (Self::MIN..=Self::MAX).contains(&value).then_some($name(value))
+            }
+
+            pub fn get(self) -> u32 {
+                self.0
+            }
+        }
+
+        impl TryFrom<u32> for $name {
+            type Error = PrimitiveError;
+
+            fn try_from(value: u32) -> Result<Self, Self::Error> {
+                $name::new(value).ok_or_else(|| PrimitiveError::new($type_name, &value.to_string()))
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = PrimitiveError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let err = || PrimitiveError::new($type_name, s);
+                if !is_digits(s) || (s.len() > 1 && s.starts_with('0')) {
+                    return Err(err());
+                }
+                s.parse().ok().and_then($name::new).ok_or_else(err)
+            }
+        }
+
+        impl From<$name> for u32 {
+            fn from(value: $name) -> u32 {
+                value.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl serde::Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                serializer.serialize_u32(self.0)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                let value = u32::deserialize(deserializer)?;
+                $name::try_from(value).map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+bounded_int!(
+    /// `unsignedInt`: an integer from 0 to 2^31-1, the same upper limit as `integer`.
+    UnsignedInt,
+    "unsignedInt",
+    0,
+    i32::MAX as u32
+);
+
+bounded_int!(
+    /// `positiveInt`: an integer from 1 to 2^31-1.
+    PositiveInt,
+    "positiveInt",
+    1,
+    i32::MAX as u32
+);
+
+/// `integer64`: a signed 64-bit integer, which FHIR JSON carries as a string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Integer64(pub i64);
+
+impl FromStr for Integer64 {
+    type Err = PrimitiveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse()
+            .map(Integer64)
+            .map_err(|_| PrimitiveError::new("integer64", s))
+    }
+}
+
+impl fmt::Display for Integer64 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+serde_via_str!(Integer64, "integer64");
+
+/// `decimal`: a rational number kept in its textual form, since FHIR requires the precision of a
+/// decimal (e.g. the trailing zeros in `1.50`) to be preserved.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Decimal(String);
+
+impl Decimal {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.0.parse().unwrap_or(f64::NAN)
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = PrimitiveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if is_decimal(s) {
+            Ok(Decimal(s.to_string()))
+        } else {
+            Err(PrimitiveError::new("decimal", s))
+        }
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(value: i64) -> Self {
+        Decimal(value.to_string())
+    }
+}
+
+impl TryFrom<f64> for Decimal {
+    type Error = PrimitiveError;
+
+    fn try_from(value: f64) -> Result<Self, Self::Error> {
+        value.to_string().parse()
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl serde::Serialize for Decimal {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let number: serde_json::Number = self.0.parse().map_err(serde::ser::Error::custom)?;
+        number.serialize(serializer)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Decimal {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let number = serde_json::Number::deserialize(deserializer)?;
+        number.to_string().parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// `-?(0|[1-9][0-9]*)(\.[0-9]+)?([eE][+-]?[0-9]+)?`
+fn is_decimal(s: &str) -> bool {
+    let s = s.strip_prefix('-').unwrap_or(s);
+    let (mantissa, exponent) = match s.find(['e', 'E']) {
+        Some(i) => (&s[..i], Some(&s[i + 1..])),
+        None => (s, None),
+    };
+    let (integer, fraction) = match mantissa.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (mantissa, None),
+    };
+    let integer_ok = is_digits(integer) && (integer == "0" || !integer.starts_with('0'));
+    let fraction_ok = fraction.is_none_or(is_digits);
+    let exponent_ok = exponent.is_none_or(|e| is_digits(e.strip_prefix(['+', '-']).unwrap_or(e)));
+    integer_ok && fraction_ok && exponent_ok
+}
+
+fn is_digits(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+string_primitive!(
+    /// `uri`: a Uniform Resource Identifier; any non-empty string without whitespace.
+    Uri,
+    "uri",
+    |s| !s.is_empty() && !s.contains(char::is_whitespace)
+);
+
+string_primitive!(
+    /// `url`: a Uniform Resource Locator.
+    Url,
+    "url",
+    |s| !s.is_empty() && !s.contains(char::is_whitespace)
+);
+
+string_primitive!(
+    /// `canonical`: a URI referring to a resource by its canonical URL, optionally with `|version`.
+    Canonical,
+    "canonical",
+    |s| !s.is_empty() && !s.contains(char::is_whitespace)
+);
+
+string_primitive!(
+    /// `oid`: an OID as a URI, e.g. `urn:oid:1.2.3.4.5`.
+    Oid,
+    "oid",
+    |s| s
+        .strip_prefix("urn:oid:")
+        .is_some_and(|oid| oid.split('.').all(|part| is_digits(part) && (part == "0" || !part.starts_with('0'))))
+);
+
+string_primitive!(
+    /// `uuid`: a UUID as a URI, e.g. `urn:uuid:c757873d-ec9a-4326-a141-556f43239520`.
+    Uuid,
+    "uuid",
+    |s| s.strip_prefix("urn:uuid:").is_some_and(is_uuid)
+);
+
+string_primitive!(
+    /// `code`: a token from a set of controlled strings; no leading, trailing or repeated whitespace.
+    Code,
+    "code",
+    |s| !s.is_empty() && s.trim() == s && !s.contains("  ") && !s.contains(['\t', '\n', '\r'])
+);
+
+string_primitive!(
+    /// `id`: up to 64 characters of `A-Z`, `a-z`, `0-9`, `-` and `.`.
+    Id,
+    "id",
+    |s| !s.is_empty() && s.len() <= 64 && s.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.')
+);
+
+string_primitive!(
+    /// `markdown`: a string that may contain GitHub Flavored Markdown.
+    Markdown,
+    "markdown",
+    |s| !s.trim().is_empty()
+);
+
+string_primitive!(
+    /// `base64Binary`: base64 encoded content.
+    Base64Binary,
+    "base64Binary",
+    |s| s
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/' | b'=') || b.is_ascii_whitespace())
+);
+
+string_primitive!(
+    /// `xhtml`: the XHTML content of a narrative `div`.
+    Xhtml,
+    "xhtml",
+    |s| !s.trim().is_empty()
+);
+
+fn is_uuid(s: &str) -> bool {
+    let groups: Vec<&str> = s.split('-').collect();
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(group, len)| group.len() == len && group.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+/// `date`: a year, year and month, or full date, with no time zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    year: u16,
+    month: Option<u8>,
+    day: Option<u8>,
+}
+
+impl Date {
+    pub fn from_year(year: u16) -> Result<Self, PrimitiveError> {
+        Date::new(year, None, None)
+    }
+
+    pub fn from_year_month(year: u16, month: u8) -> Result<Self, PrimitiveError> {
+        Date::new(year, Some(month), None)
+    }
+
+    pub fn from_ymd(year: u16, month: u8, day: u8) -> Result<Self, PrimitiveError> {
+        Date::new(year, Some(month), Some(day))
+    }
+
+    fn new(year: u16, month: Option<u8>, day: Option<u8>) -> Result<Self, PrimitiveError> {
+        let date = Date { year, month, day };
+        let month_ok = month.is_none_or(|m| (1..=12).contains(&m));
+        let day_ok = match (month, day) {
+            (Some(m), Some(d)) => d >= 1 && d <= days_in_month(year, m),
+            (None, Some(_)) => false,
+            _ => true,
+        };
+        if (1..=9999).contains(&year) && month_ok && day_ok {
+            Ok(date)
+        } else {
+            Err(PrimitiveError::new("date", &date.to_string()))
+        }
+    }
+
+    pub fn year(&self) -> u16 {
+        self.year
+    }
+
+    pub fn month(&self) -> Option<u8> {
+        self.month
+    }
+
+    pub fn day(&self) -> Option<u8> {
+        self.day
+    }
+
+    /// Whether year, month and day are all present.
+    pub fn is_full(&self) -> bool {
+        self.day.is_some()
+    }
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl FromStr for Date {
+    type Err = PrimitiveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || PrimitiveError::new("date", s);
+        let mut parts = s.split('-');
+        let year = parts.next().filter(|y| y.len() == 4).ok_or_else(err)?;
+        let month = parts.next().map(|m| parse_fixed(m, 2)).transpose().map_err(|_| err())?;
+        let day = parts.next().map(|d| parse_fixed(d, 2)).transpose().map_err(|_| err())?;
+        if parts.next().is_some() {
+            return Err(err());
+        }
+        let year = parse_fixed(year, 4).map_err(|_| err())?;
+        Date::new(year as u16, month.map(|m| m as u8), day.map(|d| d as u8)).map_err(|_| err())
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}", self.year)?;
+        if let Some(month) = self.month {
+            write!(f, "-{:02}", month)?;
+        }
+        if let Some(day) = self.day {
+            write!(f, "-{:02}", day)?;
+        }
+        Ok(())
+    }
+}
+
+serde_via_str!(Date, "date");
+
+/// `time`: a time of day, `hh:mm:ss` with optional fractional seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Time {
+    hour: u8,
+    minute: u8,
+    second: u8,
+    nanosecond: u32,
+    /// Number of fractional digits written, so `12:00:00.100` keeps its precision.
+    fraction_digits: u8,
+}
+
+impl Time {
+    pub fn from_hms(hour: u8, minute: u8, second: u8) -> Result<Self, PrimitiveError> {
+        Time::from_hms_nano(hour, minute, second, 0)
+    }
+
+    pub fn from_hms_nano(hour: u8, minute: u8, second: u8, nanosecond: u32) -> Result<Self, PrimitiveError> {
+        let time = Time {
+            hour,
+            minute,
+            second,
+            nanosecond,
+            fraction_digits: fraction_digits(nanosecond),
+        };
+        if hour < 24 && minute < 60 && second <= 60 && nanosecond < 1_000_000_000 {
+            Ok(time)
+        } else {
+            Err(PrimitiveError::new(
+                "time",
+                &format!("{:02}:{:02}:{:02}", hour, minute, second),
+            ))
+        }
+    }
+
+    pub fn hour(&self) -> u8 {
+        self.hour
+    }
+
+    pub fn minute(&self) -> u8 {
+        self.minute
+    }
+
+    pub fn second(&self) -> u8 {
+        self.second
+    }
+
+    pub fn nanosecond(&self) -> u32 {
+        self.nanosecond
+    }
+}
+
+/// Fewest fractional digits that represent `nanosecond` exactly.
+fn fraction_digits(mut nanosecond: u32) -> u8 {
+    if nanosecond == 0 {
+        return 0;
+    }
+    let mut digits = 9;
+    while nanosecond.is_multiple_of(10) {
+        nanosecond /= 10;
+        digits -= 1;
+    }
+    digits
+}
+
+impl FromStr for Time {
+    type Err = PrimitiveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || PrimitiveError::new("time", s);
+        let (hms, fraction) = match s.split_once('.') {
+            Some((hms, fraction)) => (hms, Some(fraction)),
+            None => (s, None),
+        };
+        let mut parts = hms.split(':');
+        let mut next = || {
+            parts
+                .next()
+                .ok_or_else(err)
+                .and_then(|p| parse_fixed(p, 2).map_err(|_| err()))
+        };
+        let (hour, minute, second) = (next()?, next()?, next()?);
+        if parts.next().is_some() {
+            return Err(err());
+        }
+        let (nanosecond, fraction_digits) = match fraction {
+            Some(fraction) if is_digits(fraction) && fraction.len() <= 9 => {
+                let padded = format!("{:0<9}", fraction);
+                (padded.parse().map_err(|_| err())?, fraction.len() as u8)
+            }
+            Some(_) => return Err(err()),
+            None => (0, 0),
+        };
+        let mut time = Time::from_hms_nano(hour as u8, minute as u8, second as u8, nanosecond).map_err(|_| err())?;
+        time.fraction_digits = fraction_digits;
+        Ok(time)
+    }
+}
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}:{:02}", self.hour, self.minute, self.second)?;
+        if self.fraction_digits > 0 {
+            let fraction = format!("{:09}", self.nanosecond);
+            write!(f, ".{}", &fraction[..self.fraction_digits as usize])?;
+        }
+        Ok(())
+    }
+}
+
+serde_via_str!(Time, "time");
+
+/// The time zone of a `dateTime` or `instant`. `Z` is kept apart from `+00:00` so it renders back
+/// the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TimezoneOffset {
+    Utc,
+    /// Offset from UTC in minutes, between -14:00 and +14:00.
+    Minutes(i16),
+}
+
+impl FromStr for TimezoneOffset {
+    type Err = PrimitiveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || PrimitiveError::new("timezone offset", s);
+        if s == "Z" {
+            return Ok(TimezoneOffset::Utc);
+        }
+        let (sign, offset) = match s.split_at_checked(1) {
+            Some(("+", offset)) => (1, offset),
+            Some(("-", offset)) => (-1, offset),
+            _ => return Err(err()),
+        };
+        let (hours, minutes) = offset.split_once(':').ok_or_else(err)?;
+        let hours = parse_fixed(hours, 2).map_err(|_| err())? as i16;
+        let minutes = parse_fixed(minutes, 2).map_err(|_| err())? as i16;
+        if minutes >= 60 || hours > 14 || (hours == 14 && minutes > 0) {
+            return Err(err());
+        }
+        Ok(TimezoneOffset::Minutes(sign * (hours * 60 + minutes)))
+    }
+}
+
+impl fmt::Display for TimezoneOffset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimezoneOffset::Utc => f.write_str("Z"),
+            TimezoneOffset::Minutes(minutes) => {
+                let sign = if *minutes < 0 { '-' } else { '+' };
+                let minutes = minutes.unsigned_abs();
+                write!(f, "{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+            }
+        }
+    }
+}
+
+/// Splits `2015-02-07T13:28:17.239+02:00` into its date, time and offset parts.
+fn split_date_time(s: &str) -> (&str, Option<&str>, Option<&str>) {
+    let Some((date, time)) = s.split_once('T') else {
+        return (s, None, None);
+    };
+    match time.find(['Z', '+', '-']) {
+        Some(i) => (date, Some(&time[..i]), Some(&time[i..])),
+        None => (date, Some(time), None),
+    }
+}
+
+/// `dateTime`: a date with optional time of day and time zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DateTime {
+    date: Date,
+    time: Option<Time>,
+    offset: Option<TimezoneOffset>,
+}
+
+impl DateTime {
+    pub fn new(date: Date, time: Option<(Time, TimezoneOffset)>) -> Result<Self, PrimitiveError> {
+        if time.is_some() && !date.is_full() {
+            return Err(PrimitiveError::new("dateTime", &date.to_string()));
+        }
+        Ok(DateTime {
+            date,
+            time: time.map(|(t, _)| t),
+            offset: time.map(|(_, o)| o),
+        })
+    }
+
+    pub fn date(&self) -> Date {
+        self.date
+    }
+
+    pub fn time(&self) -> Option<Time> {
+        self.time
+    }
+
+    pub fn offset(&self) -> Option<TimezoneOffset> {
+        self.offset
+    }
+}
+
+impl From<Date> for DateTime {
+    fn from(date: Date) -> Self {
+        DateTime {
+            date,
+            time: None,
+            offset: None,
+        }
+    }
+}
+
+impl From<Instant> for DateTime {
+    fn from(instant: Instant) -> Self {
+        DateTime {
+            date: instant.date,
+            time: Some(instant.time),
+            offset: Some(instant.offset),
+        }
+    }
+}
+
+impl FromStr for DateTime {
+    type Err = PrimitiveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || PrimitiveError::new("dateTime", s);
+        let (date, time, offset) = split_date_time(s);
+        let date: Date = date.parse().map_err(|_| err())?;
+        let time: Option<Time> = time.map(str::parse).transpose().map_err(|_| err())?;
+        let offset: Option<TimezoneOffset> = offset.map(str::parse).transpose().map_err(|_| err())?;
+        // A time needs a full date and a time zone.
+        if time.is_some() && (!date.is_full() || offset.is_none()) {
+            return Err(err());
+        }
+        Ok(DateTime { date, time, offset })
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.date)?;
+        if let Some(time) = self.time {
+            write!(f, "T{}", time)?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, "{}", offset)?;
+        }
+        Ok(())
+    }
+}
+
+serde_via_str!(DateTime, "dateTime");
+
+/// `instant`: a point in time to at least the second, always with a time zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Instant {
+    date: Date,
+    time: Time,
+    offset: TimezoneOffset,
+}
+
+impl Instant {
+    pub fn new(date: Date, time: Time, offset: TimezoneOffset) -> Result<Self, PrimitiveError> {
+        if !date.is_full() {
+            return Err(PrimitiveError::new("instant", &date.to_string()));
+        }
+        Ok(Instant { date, time, offset })
+    }
+
+    pub fn date(&self) -> Date {
+        self.date
+    }
+
+    pub fn time(&self) -> Time {
+        self.time
+    }
+
+    pub fn offset(&self) -> TimezoneOffset {
+        self.offset
+    }
+}
+
+impl FromStr for Instant {
+    type Err = PrimitiveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || PrimitiveError::new("instant", s);
+        match split_date_time(s) {
+            (date, Some(time), Some(offset)) => Instant::new(
+                date.parse().map_err(|_| err())?,
+                time.parse().map_err(|_| err())?,
+                offset.parse().map_err(|_| err())?,
+            )
+            .map_err(|_| err()),
+            _ => Err(err()),
+        }
+    }
+}
+
+impl fmt::Display for Instant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}T{}{}", self.date, self.time, self.offset)
+    }
+}
+
+serde_via_str!(Instant, "instant");
+
+/// Parses exactly `len` ASCII digits.
+fn parse_fixed(s: &str, len: usize) -> Result<u32, ()> {
+    if s.len() == len && is_digits(s) {
+        s.parse().map_err(|_| ())
+    } else {
+        Err(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+    use serde_json::json;
+
+    /// Checks that each value parses and renders back unchanged.
+    fn round_trips<T>(values: &[&str])
+    where
+        T: FromStr + fmt::Display,
+        T::Err: fmt::Debug,
+    {
+        for value in values {
+            let parsed: T = value.parse().unwrap_or_else(|err| panic!("{:?}: {:?}", value, err));
+            assert_eq!(parsed.to_string(), *value);
+        }
+    }
+
+    fn rejects<T: FromStr>(values: &[&str]) {
+        for value in values {
+            assert!(value.parse::<T>().is_err(), "accepted {:?}", value);
+        }
+    }
+
+    fn json_round_trips<T: Serialize + DeserializeOwned>(value: serde_json::Value) {
+        let parsed: T = serde_json::from_value(value.clone()).unwrap_or_else(|err| panic!("{}: {}", value, err));
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), value);
+    }
+
+    fn json_rejects<T: DeserializeOwned>(value: serde_json::Value) {
+        assert!(
+            serde_json::from_value::<T>(value.clone()).is_err(),
+            "accepted {}",
+            value
+        );
+    }
+
+    #[test]
+    fn unsigned_int() {
+        round_trips::<UnsignedInt>(&["0", "1", "2147483647"]);
+        rejects::<UnsignedInt>(&["", "-1", "+1", "01", "1.0", "2147483648", "4294967295"]);
+        json_round_trips::<UnsignedInt>(json!(0));
+        json_round_trips::<UnsignedInt>(json!(2147483647));
+        json_rejects::<UnsignedInt>(json!(2147483648u32));
+        json_rejects::<UnsignedInt>(json!(-1));
+        json_rejects::<UnsignedInt>(json!("1"));
+        assert_eq!(
+            UnsignedInt::new(UnsignedInt::MAX).map(UnsignedInt::get),
+            Some(2147483647)
+        );
+        assert!(UnsignedInt::new(UnsignedInt::MAX + 1).is_none());
+    }
+
+    #[test]
+    fn positive_int() {
+        round_trips::<PositiveInt>(&["1", "2147483647"]);
+        rejects::<PositiveInt>(&["0", "-1", "2147483648"]);
+        json_round_trips::<PositiveInt>(json!(1));
+        json_rejects::<PositiveInt>(json!(0));
+        json_rejects::<PositiveInt>(json!(2147483648u32));
+        assert!(PositiveInt::try_from(0).is_err());
+        assert_eq!(u32::from(PositiveInt::try_from(5).unwrap()), 5);
+    }
+
+    #[test]
+    fn integer64() {
+        round_trips::<Integer64>(&["0", "-9223372036854775808", "9223372036854775807"]);
+        rejects::<Integer64>(&["", "1.0", "9223372036854775808", "one"]);
+        json_round_trips::<Integer64>(json!("-42"));
+        json_rejects::<Integer64>(json!(42));
+    }
+
+    #[test]
+    fn decimal() {
+        round_trips::<Decimal>(&[
+            "0",
+            "-0",
+            "1.50",
+            "0.001",
+            "-12.3e-10",
+            "1E+3",
+            "12345678901234567890.123456789",
+        ]);
+        rejects::<Decimal>(&["", "01", "1.", ".5", "+1", "1e", "1.5.0", "NaN", " 1"]);
+        json_round_trips::<Decimal>(serde_json::from_str("1.50").unwrap());
+        json_round_trips::<Decimal>(serde_json::from_str("12345678901234567890.123456789").unwrap());
+        json_rejects::<Decimal>(json!("1.5"));
+        assert_eq!(Decimal::from(-3).to_string(), "-3");
+        assert_eq!(Decimal::try_from(0.25).unwrap().to_f64(), 0.25);
+        assert!(Decimal::try_from(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn uris() {
+        let uris = ["http://hl7.org/fhir/Patient", "urn:oid:1.2.3", "Patient/123", "#a"];
+        round_trips::<Uri>(&uris);
+        round_trips::<Url>(&uris);
+        round_trips::<Canonical>(&["http://hl7.org/fhir/StructureDefinition/Patient|5.0.0"]);
+        rejects::<Uri>(&["", "http://example.org/a b", "a\tb"]);
+        rejects::<Url>(&["", " http://example.org"]);
+        rejects::<Canonical>(&["", "http://example.org/a b"]);
+        json_round_trips::<Uri>(json!("http://example.org"));
+        json_rejects::<Uri>(json!(""));
+    }
+
+    #[test]
+    fn oid() {
+        round_trips::<Oid>(&["urn:oid:1.2.36.146.595.217.0.1", "urn:oid:0"]);
+        rejects::<Oid>(&[
+            "1.2.3",
+            "urn:oid:",
+            "urn:oid:1..2",
+            "urn:oid:1.02",
+            "urn:oid:1.a",
+            "urn:uuid:1",
+        ]);
+    }
+
+    #[test]
+    fn uuid() {
+        round_trips::<Uuid>(&["urn:uuid:c757873d-ec9a-4326-a141-556f43239520"]);
+        rejects::<Uuid>(&[
+            "c757873d-ec9a-4326-a141-556f43239520",
+            "urn:uuid:c757873d-ec9a-4326-a141-556f4323952",
+            "urn:uuid:c757873d-ec9a-4326-a141556f43239520",
+            "urn:uuid:g757873d-ec9a-4326-a141-556f43239520",
+        ]);
+    }
+
+    #[test]
+    fn code() {
+        round_trips::<Code>(&["final", "entered-in-error", "two words", "[lb_av]"]);
+        rejects::<Code>(&["", " final", "final ", "two  spaces", "a\tb", "a\nb"]);
+        json_round_trips::<Code>(json!("final"));
+        json_rejects::<Code>(json!(1));
+    }
+
+    #[test]
+    fn id() {
+        round_trips::<Id>(&["example", "a-b.c", &"a".repeat(64)]);
+        rejects::<Id>(&["", "a b", "a_b", "a/b", &"a".repeat(65)]);
+    }
+
+    #[test]
+    fn markdown_and_xhtml() {
+        round_trips::<Markdown>(&["*emphasis*", " leading space"]);
+        rejects::<Markdown>(&["", "   "]);
+        round_trips::<Xhtml>(&["<div xmlns=\"http://www.w3.org/1999/xhtml\">text</div>"]);
+        rejects::<Xhtml>(&["", "\n"]);
+    }
+
+    #[test]
+    fn base64_binary() {
+        round_trips::<Base64Binary>(&["", "aGVsbG8=", "aGVs\nbG8="]);
+        rejects::<Base64Binary>(&["aGVsbG8*", "a-b"]);
+    }
+
+    #[test]
+    fn string_primitive_conversions() {
+        let code = Code::try_from("final".to_string()).unwrap();
+        assert_eq!(code.as_str(), "final");
+        assert_eq!(&*code, "final");
+        assert_eq!(String::from(code.clone()), "final");
+        assert_eq!(code.into_string(), "final");
+        let err = Code::try_from(" final").unwrap_err();
+        assert_eq!(err.to_string(), "invalid code: \" final\"");
+    }
+
+    #[test]
+    fn date() {
+        round_trips::<Date>(&[
+            "2018",
+            "1973-06",
+            "1905-08-23",
+            "2000-02-29",
+            "0001-01-01",
+            "9999-12-31",
+        ]);
+        rejects::<Date>(&[
+            "",
+            "18",
+            "0000",
+            "2018-13",
+            "2018-00",
+            "2018-6",
+            "2018-06-00",
+            "2018-06-31",
+            "1900-02-29",
+            "2018-06-01-01",
+            "2018-06-01T00:00:00Z",
+        ]);
+        json_round_trips::<Date>(json!("1905-08-23"));
+        json_rejects::<Date>(json!("1905-8-23"));
+        let date = Date::from_ymd(2024, 2, 29).unwrap();
+        assert_eq!((date.year(), date.month(), date.day()), (2024, Some(2), Some(29)));
+        assert!(Date::from_ymd(2023, 2, 29).is_err());
+        assert!(!Date::from_year_month(2024, 2).unwrap().is_full());
+    }
+
+    #[test]
+    fn time() {
+        round_trips::<Time>(&["00:00:00", "23:59:59", "12:00:00.100", "12:00:00.000000001", "23:59:60"]);
+        rejects::<Time>(&[
+            "",
+            "12:00",
+            "24:00:00",
+            "12:60:00",
+            "12:00:61",
+            "1:00:00",
+            "12:00:00.",
+            "12:00:00.1234567890",
+        ]);
+        let time = Time::from_hms_nano(12, 30, 15, 250_000_000).unwrap();
+        assert_eq!(time.to_string(), "12:30:15.25");
+        assert_eq!(
+            (time.hour(), time.minute(), time.second(), time.nanosecond()),
+            (12, 30, 15, 250_000_000)
+        );
+        json_round_trips::<Time>(json!("12:00:00.100"));
+    }
+
+    #[test]
+    fn timezone_offset() {
+        round_trips::<TimezoneOffset>(&["Z", "+00:00", "-05:00", "+14:00", "-14:00", "+05:30"]);
+        rejects::<TimezoneOffset>(&["", "z", "05:00", "+5:00", "+0500", "+14:30", "+15:00", "+05:60"]);
+        assert_eq!("-05:30".parse(), Ok(TimezoneOffset::Minutes(-330)));
+    }
+
+    #[test]
+    fn date_time() {
+        round_trips::<DateTime>(&[
+            "2018",
+            "1973-06",
+            "1905-08-23",
+            "2015-02-07T13:28:17-05:00",
+            "2017-01-01T00:00:00.000Z",
+            "2017-01-01T00:00:00+00:00",
+            "2017-01-01T23:59:60.5+14:00",
+        ]);
+        rejects::<DateTime>(&[
+            "",
+            "2015-02-07T13:28:17",
+            "2015-02-07T13:28:17.239",
+            "2015-02-07T13:28-05:00",
+            "2015-02-07T",
+            "2015-02T13:28:17Z",
+            "2015-02-07T13:28:17+15:00",
+            "2015-02-07Z",
+        ]);
+        json_round_trips::<DateTime>(json!("2015-02-07T13:28:17-05:00"));
+        json_rejects::<DateTime>(json!("2015-02-07T13:28:17"));
+        let date = Date::from_ymd(2015, 2, 7).unwrap();
+        let time = Time::from_hms(13, 28, 17).unwrap();
+        let date_time = DateTime::new(date, Some((time, TimezoneOffset::Utc))).unwrap();
+        assert_eq!(date_time.to_string(), "2015-02-07T13:28:17Z");
+        assert!(DateTime::new(Date::from_year(2015).unwrap(), Some((time, TimezoneOffset::Utc))).is_err());
+        assert_eq!(DateTime::from(date).to_string(), "2015-02-07");
+    }
+
+    #[test]
+    fn instant() {
+        round_trips::<Instant>(&["2015-02-07T13:28:17.239+02:00", "2017-01-01T00:00:00Z"]);
+        rejects::<Instant>(&[
+            "",
+            "2015-02-07",
+            "2015-02-07T13:28:17",
+            "2015-02T13:28:17Z",
+            "2015-02-07T13:28Z",
+        ]);
+        json_round_trips::<Instant>(json!("2017-01-01T00:00:00Z"));
+        let instant: Instant = "2015-02-07T13:28:17.239+02:00".parse().unwrap();
+        assert_eq!(DateTime::from(instant).to_string(), "2015-02-07T13:28:17.239+02:00");
+    }
+
+    #[test]
+    fn boolean_and_integer() {
+        json_round_trips::<Boolean>(json!(true));
+        json_rejects::<Boolean>(json!("true"));
+        json_round_trips::<Integer>(json!(-2147483648));
+        json_rejects::<Integer>(json!(2147483648u32));
+        json_rejects::<Integer>(serde_json::from_str("1.0").unwrap());
+    }
+}
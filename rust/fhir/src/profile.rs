@@ -0,0 +1,89 @@
+//! Conformance errors and value matching for the profile types generated from constraining
+//! definitions such as US Core Patient.
+
+use std::fmt;
+
+use serde::Serialize;
+
+/// A resource that doesn't conform to a profile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileError {
+    /// Canonical URL of the profile.
+    pub profile: &'static str,
+    /// Id of the element the resource breaks, e.g. `Patient.gender`.
+    pub element: &'static str,
+    pub kind: ProfileErrorKind,
+}
+
+/// How a resource breaks a profile's constraint on one of its elements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileErrorKind {
+    /// Fewer values than the profile's minimum.
+    Missing,
+    /// More values than the profile's maximum.
+    TooMany,
+    /// A value that differs from the one the profile fixes.
+    Fixed,
+    /// A value that doesn't match the profile's pattern.
+    Pattern,
+    /// A value of a type the profile doesn't allow.
+    Type,
+    /// A reference to a resource type the profile doesn't allow.
+    ReferenceTarget,
+    /// A resource of another type than the one the profile constrains.
+    ResourceType,
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.kind {
+            ProfileErrorKind::Missing => "is missing or has too few values",
+            ProfileErrorKind::TooMany => "has too many values",
+            ProfileErrorKind::Fixed => "differs from the fixed value",
+            ProfileErrorKind::Pattern => "doesn't match the pattern",
+            ProfileErrorKind::Type => "has a type the profile doesn't allow",
+            ProfileErrorKind::ReferenceTarget => "references a resource type the profile doesn't allow",
+            ProfileErrorKind::ResourceType => "is not the resource type the profile constrains",
+        };
+        write!(f, "profile {}: {} {}", self.profile, self.element, reason)
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+/// Whether `value` is exactly the JSON value `fixed`.
+pub fn matches_fixed<T: Serialize + ?Sized>(value: &T, fixed: &str) -> bool {
+    match (
+        serde_json::to_value(value),
+        serde_json::from_str::<serde_json::Value>(fixed),
+    ) {
+        (Ok(value), Ok(fixed)) => value == fixed,
+        _ => false,
+    }
+}
+
+/// Whether `value` has every part of the JSON value `pattern`. Arrays match when each item of the
+/// pattern matches some item of the value.
+pub fn matches_pattern<T: Serialize + ?Sized>(value: &T, pattern: &str) -> bool {
+    match (
+        serde_json::to_value(value),
+        serde_json::from_str::<serde_json::Value>(pattern),
+    ) {
+        (Ok(value), Ok(pattern)) => contains_pattern(&value, &pattern),
+        _ => false,
+    }
+}
+
+fn contains_pattern(value: &serde_json::Value, pattern: &serde_json::Value) -> bool {
+    use serde_json::Value;
+
+    match (value, pattern) {
+        (Value::Object(value), Value::Object(pattern)) => pattern
+            .iter()
+            .all(|(key, p)| value.get(key).is_some_and(|v| contains_pattern(v, p))),
+        (Value::Array(value), Value::Array(pattern)) => {
+            pattern.iter().all(|p| value.iter().any(|v| contains_pattern(v, p)))
+        }
+        _ => value == pattern,
+    }
+}
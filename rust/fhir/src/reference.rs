@@ -0,0 +1,221 @@
+//! Parsing of `Reference.reference` strings.
+
+/// A literal reference split into its parts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LiteralReference<'a> {
+    /// `#id`, a resource in the referring resource's `contained`. A bare `#` refers to the
+    /// containing resource itself, and has an empty id.
+    Contained { id: &'a str },
+    /// `Patient/123` or `Patient/123/_history/2`, relative to the server's base URL.
+    Relative {
+        resource_type: &'a str,
+        id: &'a str,
+        version: Option<&'a str>,
+    },
+    /// `http://example.org/fhir/Patient/123`, possibly with `/_history/2`. `base` excludes the
+    /// trailing slash.
+    Absolute {
+        base: &'a str,
+        resource_type: &'a str,
+        id: &'a str,
+        version: Option<&'a str>,
+    },
+    /// `urn:uuid:...`, usually the `fullUrl` of another entry in the same Bundle.
+    Uuid { uuid: &'a str },
+    /// `urn:oid:...`.
+    Oid { oid: &'a str },
+}
+
+impl<'a> LiteralReference<'a> {
+    /// Splits `reference`, or returns `None` if it is none of the forms FHIR defines, e.g. a
+    /// relative URL whose first segment isn't a resource type.
+    pub fn parse(reference: &'a str) -> Option<Self> {
+        if let Some(id) = reference.strip_prefix('#') {
+            return (id.is_empty() || is_id(id)).then_some(LiteralReference::Contained { id });
+        }
+        if let Some(uuid) = reference.strip_prefix("urn:uuid:") {
+            return (!uuid.is_empty()).then_some(LiteralReference::Uuid { uuid });
+        }
+        if let Some(oid) = reference.strip_prefix("urn:oid:") {
+            return (!oid.is_empty()).then_some(LiteralReference::Oid { oid });
+        }
+
+        let (path, version) = match reference.split_once("/_history/") {
+            Some((path, version)) if is_id(version) => (path, Some(version)),
+            Some(_) => return None,
+            None => (reference, None),
+        };
+        let (rest, id) = path.rsplit_once('/')?;
+        let (base, resource_type) = match rest.rsplit_once('/') {
+            Some((base, resource_type)) => (Some(base), resource_type),
+            None => (None, rest),
+        };
+        if !is_id(id) || !is_resource_type(resource_type) {
+            return None;
+        }
+        match base {
+            None => Some(LiteralReference::Relative {
+                resource_type,
+                id,
+                version,
+            }),
+            Some(base) if is_http_url(base) => Some(LiteralReference::Absolute {
+                base,
+                resource_type,
+                id,
+                version,
+            }),
+            Some(_) => None,
+        }
+    }
+
+    /// The resource type named in the reference; contained and URN references don't name one.
+    pub fn resource_type(&self) -> Option<&'a str> {
+        match *self {
+            LiteralReference::Relative { resource_type, .. } | LiteralReference::Absolute { resource_type, .. } => {
+                Some(resource_type)
+            }
+            _ => None,
+        }
+    }
+
+    /// The id of the referenced resource, or the UUID or OID for URN references.
+    pub fn id(&self) -> &'a str {
+        match *self {
+            LiteralReference::Contained { id }
+            | LiteralReference::Relative { id, .. }
+            | LiteralReference::Absolute { id, .. } => id,
+            LiteralReference::Uuid { uuid } => uuid,
+            LiteralReference::Oid { oid } => oid,
+        }
+    }
+}
+
+/// `[A-Za-z0-9\-\.]{1,64}`
+fn is_id(s: &str) -> bool {
+    (1..=64).contains(&s.len()) && s.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.')
+}
+
+/// An `http` or `https` URL with something after the scheme.
+fn is_http_url(s: &str) -> bool {
+    s.strip_prefix("http://")
+        .or_else(|| s.strip_prefix("https://"))
+        .is_some_and(|rest| !rest.is_empty() && !rest.starts_with('/'))
+}
+
+fn is_resource_type(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_uppercase()) && s.bytes().all(|b| b.is_ascii_alphanumeric())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contained() {
+        assert_eq!(
+            LiteralReference::parse("#p1"),
+            Some(LiteralReference::Contained { id: "p1" })
+        );
+        assert_eq!(
+            LiteralReference::parse("#"),
+            Some(LiteralReference::Contained { id: "" })
+        );
+        assert_eq!(LiteralReference::parse("#p1").unwrap().resource_type(), None);
+        assert_eq!(LiteralReference::parse("#p1").unwrap().id(), "p1");
+    }
+
+    #[test]
+    fn relative() {
+        let reference = LiteralReference::parse("Patient/123").unwrap();
+        assert_eq!(
+            reference,
+            LiteralReference::Relative {
+                resource_type: "Patient",
+                id: "123",
+                version: None,
+            }
+        );
+        assert_eq!(reference.resource_type(), Some("Patient"));
+        assert_eq!(reference.id(), "123");
+    }
+
+    #[test]
+    fn relative_versioned() {
+        assert_eq!(
+            LiteralReference::parse("Observation/a.b-c/_history/2"),
+            Some(LiteralReference::Relative {
+                resource_type: "Observation",
+                id: "a.b-c",
+                version: Some("2"),
+            })
+        );
+    }
+
+    #[test]
+    fn absolute() {
+        assert_eq!(
+            LiteralReference::parse("http://example.org/fhir/Patient/123"),
+            Some(LiteralReference::Absolute {
+                base: "http://example.org/fhir",
+                resource_type: "Patient",
+                id: "123",
+                version: None,
+            })
+        );
+        let reference = LiteralReference::parse("https://example.org/Patient/123/_history/7").unwrap();
+        assert_eq!(
+            reference,
+            LiteralReference::Absolute {
+                base: "https://example.org",
+                resource_type: "Patient",
+                id: "123",
+                version: Some("7"),
+            }
+        );
+        assert_eq!(reference.resource_type(), Some("Patient"));
+    }
+
+    #[test]
+    fn urns() {
+        let uuid = LiteralReference::parse("urn:uuid:c757873d-ec9a-4326-a141-556f43239520").unwrap();
+        assert_eq!(
+            uuid,
+            LiteralReference::Uuid {
+                uuid: "c757873d-ec9a-4326-a141-556f43239520"
+            }
+        );
+        assert_eq!(uuid.id(), "c757873d-ec9a-4326-a141-556f43239520");
+        assert_eq!(uuid.resource_type(), None);
+        assert_eq!(
+            LiteralReference::parse("urn:oid:1.2.3"),
+            Some(LiteralReference::Oid { oid: "1.2.3" })
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        for reference in [
+            "",
+            "Patient",
+            "Patient/",
+            "/Patient/123",
+            "patient/123",
+            "Patient/12 3",
+            "Patient/123/",
+            "Patient/123/_history/",
+            "Patient/123/_history/1/2",
+            "Patient/123/_history/1/_history/2",
+            "#a b",
+            "urn:uuid:",
+            "urn:oid:",
+            "ftp://example.org/Patient/123",
+            "http:///Patient/123",
+            "example.org/fhir/Patient/123",
+            "Patient/123#p1",
+            &format!("Patient/{}", "a".repeat(65)),
+        ] {
+            assert_eq!(LiteralReference::parse(reference), None, "{:?}", reference);
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Helpers called from the generated `Serialize`/`Deserialize` impls.
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use serde::de::{DeserializeSeed, MapAccess, SeqAccess, Visitor};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize};
+
+use crate::element::Element;
+
+/// The contents of a `_name` property.
+#[derive(Serialize)]
+struct PrimitiveExtensionsRef<'a, E> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: &'a Option<String>,
+    #[serde(skip_serializing_if = "<[E]>::is_empty")]
+    extension: &'a [E],
+}
+
+#[derive(Deserialize)]
+#[serde(bound = "E: Deserialize<'de>", deny_unknown_fields)]
+struct PrimitiveExtensions<E> {
+    id: Option<String>,
+    #[serde(default = "Vec::new")]
+    extension: Vec<E>,
+}
+
+impl<'a, T, E> From<&'a Element<T, E>> for PrimitiveExtensionsRef<'a, E> {
+    fn from(element: &'a Element<T, E>) -> Self {
+        PrimitiveExtensionsRef {
+            id: &element.id,
+            extension: &element.extension,
+        }
+    }
+}
+
+/// Writes `name` with the value and `_name` with the id and extensions, skipping either if empty.
+pub fn serialize_primitive<M, T, E>(map: &mut M, name: &str, element: &Element<T, E>) -> Result<(), M::Error>
+where
+    M: SerializeMap,
+    T: Serialize,
+    E: Serialize,
+{
+    if let Some(value) = &element.value {
+        map.serialize_entry(name, value)?;
+    }
+    if element.has_extensions() {
+        map.serialize_entry(&format!("_{}", name), &PrimitiveExtensionsRef::from(element))?;
+    }
+    Ok(())
+}
+
+/// Writes a repeating primitive as parallel `name` and `_name` arrays, with `null` standing in for
+/// whichever half an item lacks. Empty items are dropped.
+pub fn serialize_primitive_vec<M, T, E>(map: &mut M, name: &str, elements: &[Element<T, E>]) -> Result<(), M::Error>
+where
+    M: SerializeMap,
+    T: Serialize,
+    E: Serialize,
+{
+    let elements: Vec<&Element<T, E>> = elements.iter().filter(|e| !e.is_empty()).collect();
+    if elements.iter().any(|e| e.value.is_some()) {
+        let values: Vec<Option<&T>> = elements.iter().map(|e| e.value.as_ref()).collect();
+        map.serialize_entry(name, &values)?;
+    }
+    if elements.iter().any(|e| e.has_extensions()) {
+        let extensions: Vec<Option<PrimitiveExtensionsRef<E>>> = elements
+            .iter()
+            .map(|e| e.has_extensions().then(|| PrimitiveExtensionsRef::from(*e)))
+            .collect();
+        map.serialize_entry(&format!("_{}", name), &extensions)?;
+    }
+    Ok(())
+}
+
+/// Reads the next value into `element`: its id and extensions if the key was `_name`, otherwise
+/// its value.
+pub fn deserialize_primitive<'de, A, T, E>(
+    element: &mut Element<T, E>,
+    extensions: bool,
+    map: &mut A,
+) -> Result<(), A::Error>
+where
+    A: MapAccess<'de>,
+    T: Deserialize<'de>,
+    E: Deserialize<'de>,
+{
+    if extensions {
+        let PrimitiveExtensions { id, extension } = map.next_value::<PrimitiveExtensions<E>>()?;
+        element.id = id;
+        element.extension = extension;
+    } else {
+        element.value = Some(map.next_value()?);
+    }
+    Ok(())
+}
+
+/// Like [`deserialize_primitive`] for a repeating primitive, merging `name` and `_name` arrays item
+/// by item whichever comes first.
+pub fn deserialize_primitive_vec<'de, A, T, E>(
+    elements: &mut Vec<Element<T, E>>,
+    extensions: bool,
+    map: &mut A,
+) -> Result<(), A::Error>
+where
+    A: MapAccess<'de>,
+    T: Deserialize<'de>,
+    E: Deserialize<'de>,
+{
+    map.next_value_seed(PrimitiveVecSeed { elements, extensions })
+}
+
+struct PrimitiveVecSeed<'a, T, E> {
+    elements: &'a mut Vec<Element<T, E>>,
+    extensions: bool,
+}
+
+impl<'de, T, E> DeserializeSeed<'de> for PrimitiveVecSeed<'_, T, E>
+where
+    T: Deserialize<'de>,
+    E: Deserialize<'de>,
+{
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<(), D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, T, E> Visitor<'de> for PrimitiveVecSeed<'_, T, E>
+where
+    T: Deserialize<'de>,
+    E: Deserialize<'de>,
+{
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("an array of primitive values or null")
+    }
+
+    fn visit_seq<S>(self, mut seq: S) -> Result<(), S::Error>
+    where
+        S: SeqAccess<'de>,
+    {
+        let mut index = 0;
+        if self.extensions {
+            while let Some(item) = seq.next_element::<Option<PrimitiveExtensions<E>>>()? {
+                let element = element_at(self.elements, index);
+                if let Some(PrimitiveExtensions { id, extension }) = item {
+                    element.id = id;
+                    element.extension = extension;
+                }
+                index += 1;
+            }
+        } else {
+            while let Some(value) = seq.next_element::<Option<T>>()? {
+                element_at(self.elements, index).value = value;
+                index += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn element_at<T, E>(elements: &mut Vec<Element<T, E>>, index: usize) -> &mut Element<T, E> {
+    if elements.len() <= index {
+        elements.resize_with(index + 1, Element::default);
+    }
+    &mut elements[index]
+}
+
+/// Reads a JSON string through `T`'s `FromStr`, as the generated code enums do.
+pub fn deserialize_from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    struct StrVisitor<T>(PhantomData<T>);
+
+    impl<T> Visitor<'_> for StrVisitor<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        type Value = T;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a code")
+        }
+
+        fn visit_str<E>(self, v: &str) -> Result<T, E>
+        where
+            E: serde::de::Error,
+        {
+            v.parse().map_err(E::custom)
+        }
+    }
+
+    deserializer.deserialize_str(StrVisitor(PhantomData))
+}
@@ -0,0 +1,122 @@
+//! Round-trips examples through the generated types: a few small ones kept under `tests/examples`,
+//! and the official examples, which `codegen --download` saves as
+//! `resources/examples-<release>.json.zip` next to the definitions. The official examples are large
+//! and not checked in, so those tests are ignored by default; run them with
+//! `cargo test -- --ignored`, setting `FHIR_RESOURCES_DIR` if the zips live somewhere other than
+//! the repository's `resources` directory.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Parses `data` as `T` and back, recording a failure if the JSON differs.
+fn check<T: DeserializeOwned + Serialize>(name: &str, data: &[u8], failures: &mut Vec<String>) {
+    let expected: serde_json::Value = match serde_json::from_slice(data) {
+        Ok(value) => value,
+        Err(err) => {
+            failures.push(format!("{}: not JSON: {}", name, err));
+            return;
+        }
+    };
+    let actual = serde_json::from_value::<T>(expected.clone()).and_then(|resource| serde_json::to_value(&resource));
+    match actual {
+        Ok(actual) if actual == expected => {}
+        Ok(actual) => failures.push(format!("{}: differs after round trip:\n{}", name, actual)),
+        Err(err) => failures.push(format!("{}: {}", name, err)),
+    }
+}
+
+fn assert_no_failures(failures: &[String], total: usize, release: &str) {
+    assert!(
+        failures.is_empty(),
+        "{} of {} {} examples failed:\n{}",
+        failures.len(),
+        total,
+        release,
+        failures.join("\n")
+    );
+}
+
+fn round_trip_fixtures<T: DeserializeOwned + Serialize>(release: &str) {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/examples")
+        .join(release);
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .unwrap_or_else(|err| panic!("{:?}: {}", dir, err))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no examples in {:?}", dir);
+
+    let mut failures = Vec::new();
+    for path in &paths {
+        let data = std::fs::read(path).unwrap();
+        check::<T>(&path.display().to_string(), &data, &mut failures);
+    }
+    assert_no_failures(&failures, paths.len(), release);
+}
+
+fn round_trip_official<T: DeserializeOwned + Serialize>(release: &str) {
+    let resources_dir = std::env::var_os("FHIR_RESOURCES_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../resources"));
+    let path = resources_dir.join(format!("examples-{}.json.zip", release));
+    let file = File::open(&path).unwrap_or_else(|err| {
+        panic!(
+            "{:?}: {}; run `codegen --download` or set FHIR_RESOURCES_DIR",
+            path, err
+        )
+    });
+
+    let mut archive = zip::ZipArchive::new(file).unwrap();
+    let mut failures = Vec::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).unwrap();
+        if !file.name().ends_with(".json") {
+            continue;
+        }
+        let name = file.name().to_string();
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).unwrap();
+        check::<T>(&name, &data, &mut failures);
+    }
+    assert_no_failures(&failures, archive.len(), release);
+}
+
+#[cfg(feature = "r5")]
+#[test]
+fn r5_fixtures() {
+    round_trip_fixtures::<fhir::r5::Resource>("r5");
+}
+
+#[cfg(feature = "r4")]
+#[test]
+#[ignore = "needs resources/examples-r4.json.zip from `codegen --download`"]
+fn r4_examples() {
+    round_trip_official::<fhir::r4::Resource>("r4");
+}
+
+#[cfg(feature = "r4b")]
+#[test]
+#[ignore = "needs resources/examples-r4b.json.zip from `codegen --download`"]
+fn r4b_examples() {
+    round_trip_official::<fhir::r4b::Resource>("r4b");
+}
+
+#[cfg(feature = "r5")]
+#[test]
+#[ignore = "needs resources/examples-r5.json.zip from `codegen --download`"]
+fn r5_examples() {
+    round_trip_official::<fhir::r5::Resource>("r5");
+}
+
+#[cfg(feature = "r6")]
+#[test]
+#[ignore = "needs resources/examples-r6.json.zip from `codegen --download`"]
+fn r6_examples() {
+    round_trip_official::<fhir::r6::Resource>("r6");
+}
@@ -0,0 +1,132 @@
+//! The JSON representation of primitives and choice elements.
+
+#![cfg(feature = "r5")]
+
+use fhir::r5::{Observation, ObservationValue, Patient, PatientDeceased};
+use serde_json::json;
+
+fn patient(value: serde_json::Value) -> Result<Patient, serde_json::Error> {
+    serde_json::from_value(value)
+}
+
+#[test]
+fn primitive_value_and_extensions_merge() {
+    let value = json!({
+        "resourceType": "Patient",
+        "birthDate": "1974-12-25",
+        "_birthDate": {"id": "b", "extension": [{"url": "http://example.org/x", "valueCode": "unknown"}]},
+    });
+    let patient = patient(value.clone()).unwrap();
+    let birth_date = patient.birth_date.as_ref().unwrap();
+    assert_eq!(birth_date.value.as_ref().unwrap().to_string(), "1974-12-25");
+    assert_eq!(birth_date.id.as_deref(), Some("b"));
+    assert_eq!(birth_date.extension.len(), 1);
+    assert_eq!(serde_json::to_value(&patient).unwrap(), value);
+
+    // The order of the two properties doesn't matter.
+    let reversed: Patient =
+        serde_json::from_str(r#"{"resourceType":"Patient","_birthDate":{"id":"b"},"birthDate":"1974-12-25"}"#).unwrap();
+    let birth_date = reversed.birth_date.as_ref().unwrap();
+    assert_eq!(birth_date.id.as_deref(), Some("b"));
+    assert!(birth_date.value.is_some());
+}
+
+#[test]
+fn primitive_extensions_without_value() {
+    let value = json!({"resourceType": "Patient", "_birthDate": {"id": "b"}});
+    let patient = patient(value.clone()).unwrap();
+    let birth_date = patient.birth_date.as_ref().unwrap();
+    assert!(birth_date.value.is_none());
+    assert_eq!(birth_date.id.as_deref(), Some("b"));
+    assert_eq!(serde_json::to_value(&patient).unwrap(), value);
+}
+
+#[test]
+fn repeating_primitive_arrays_merge_by_index() {
+    let value = json!({
+        "resourceType": "Patient",
+        "name": [{"given": ["Peter", null, "James"], "_given": [null, {"id": "g2"}, {"id": "g3"}]}],
+    });
+    let patient = patient(value.clone()).unwrap();
+    let given = &patient.name[0].given;
+    assert_eq!(given.len(), 3);
+    assert_eq!(given[0].value.as_deref(), Some("Peter"));
+    assert!(given[0].id.is_none());
+    assert!(given[1].value.is_none());
+    assert_eq!(given[1].id.as_deref(), Some("g2"));
+    assert_eq!(given[2].value.as_deref(), Some("James"));
+    assert_eq!(given[2].id.as_deref(), Some("g3"));
+    assert_eq!(serde_json::to_value(&patient).unwrap(), value);
+}
+
+#[test]
+fn choice_property_names() {
+    let value = json!({"resourceType": "Patient", "deceasedDateTime": "2015-02-07T13:28:17-05:00"});
+    let deceased = patient(value.clone()).unwrap();
+    assert!(matches!(deceased.deceased, Some(PatientDeceased::DateTime(_))));
+    assert_eq!(serde_json::to_value(&deceased).unwrap(), value);
+
+    let value = json!({"resourceType": "Patient", "deceasedBoolean": true, "_deceasedBoolean": {"id": "d"}});
+    let deceased = patient(value.clone()).unwrap();
+    match &deceased.deceased {
+        Some(PatientDeceased::Boolean(element)) => {
+            assert_eq!(element.value, Some(true));
+            assert_eq!(element.id.as_deref(), Some("d"));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(serde_json::to_value(&deceased).unwrap(), value);
+
+    let value = json!({
+        "resourceType": "Observation",
+        "status": "final",
+        "code": {"text": "weight"},
+        "valueQuantity": {"value": 185.00, "unit": "lbs"},
+    });
+    let observation: Observation = serde_json::from_value(value.clone()).unwrap();
+    assert!(matches!(observation.value, Some(ObservationValue::Quantity(_))));
+    assert_eq!(serde_json::to_value(&observation).unwrap(), value);
+}
+
+#[test]
+fn choice_rejects_unknown_type() {
+    let err = patient(json!({"resourceType": "Patient", "deceasedString": "yes"})).unwrap_err();
+    assert!(err.to_string().contains("unknown field `deceasedString`"), "{}", err);
+}
+
+#[test]
+fn choice_rejects_duplicate_variants() {
+    let err = patient(json!({
+        "resourceType": "Patient",
+        "deceasedBoolean": true,
+        "deceasedDateTime": "2015-02-07",
+    }))
+    .unwrap_err();
+    assert!(err.to_string().contains("duplicate field `deceased[x]`"), "{}", err);
+
+    // A variant's `_name` must go with the variant that carries the value.
+    assert!(patient(json!({
+        "resourceType": "Patient",
+        "deceasedBoolean": true,
+        "_deceasedDateTime": {"id": "d"},
+    }))
+    .is_err());
+}
+
+#[test]
+fn rejects_unknown_fields() {
+    let err = patient(json!({"resourceType": "Patient", "birthdate": "1974-12-25"})).unwrap_err();
+    assert!(err.to_string().contains("unknown field `birthdate`"), "{}", err);
+
+    let err = patient(json!({"resourceType": "Patient", "name": [{"family": "Chalmers", "surname": "Chalmers"}]}))
+        .unwrap_err();
+    assert!(err.to_string().contains("unknown field `surname`"), "{}", err);
+
+    let err = patient(json!({"resourceType": "Patient", "_birthDate": {"value": "1974"}})).unwrap_err();
+    assert!(err.to_string().contains("unknown field `value`"), "{}", err);
+}
+
+#[test]
+fn rejects_wrong_resource_type() {
+    assert!(patient(json!({"resourceType": "Observation"})).is_err());
+}